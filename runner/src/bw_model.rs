@@ -0,0 +1,102 @@
+//! Bandwidth models map a throttle setting (a Quartz throttle register value, an
+//! uncore ratio, or an MBA level) to the read bandwidth measured with it. They use
+//! the same format Quartz does (see `c220g2_bw_model`), one `read\t<setting>\t<MB/s>`
+//! line per point.
+
+/// Prefix of the line recording which CPU a model was measured on. Quartz only
+/// looks at lines containing "read", so it ignores this.
+const CPU_MODEL_PREFIX: &str = "# cpu: ";
+/// Prefix of the line recording which throttle the settings are for.
+const KNOB_PREFIX: &str = "# knob: ";
+
+#[derive(Clone, Debug)]
+pub struct BwModel {
+    /// The CPU model the points were measured on, if known.
+    pub cpu_model: Option<String>,
+    /// The throttle the settings are for (quartz, uncore or mba), if known.
+    /// Models without one, like `c220g2_bw_model`, are taken to be right.
+    pub knob: Option<String>,
+    /// (setting, read bandwidth in MB/s)
+    pub points: Vec<(u64, f64)>,
+}
+
+impl BwModel {
+    pub fn parse(contents: &str) -> Result<Self, failure::Error> {
+        let mut cpu_model = None;
+        let mut knob = None;
+        let mut points = Vec::new();
+
+        for line in contents.lines() {
+            if let Some(model) = line.strip_prefix(CPU_MODEL_PREFIX) {
+                cpu_model = Some(model.trim().to_string());
+                continue;
+            }
+            if let Some(k) = line.strip_prefix(KNOB_PREFIX) {
+                knob = Some(k.trim().to_string());
+                continue;
+            }
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                ["read", setting, bw] => points.push((setting.parse()?, bw.parse()?)),
+                _ => return Err(failure::format_err!("Invalid bandwidth model line: {}", line)),
+            }
+        }
+
+        if points.is_empty() {
+            return Err(failure::format_err!("Bandwidth model has no points"));
+        }
+
+        Ok(BwModel {
+            cpu_model,
+            knob,
+            points,
+        })
+    }
+
+    /// Format the model the way `parse` and Quartz expect it.
+    pub fn format(&self) -> String {
+        let mut out = String::new();
+
+        if let Some(cpu_model) = &self.cpu_model {
+            out.push_str(&format!("{}{}\n", CPU_MODEL_PREFIX, cpu_model));
+        }
+        if let Some(knob) = &self.knob {
+            out.push_str(&format!("{}{}\n", KNOB_PREFIX, knob));
+        }
+        for (setting, bw) in &self.points {
+            out.push_str(&format!("read\t{}\t{:.6}\n", setting, bw));
+        }
+
+        out
+    }
+
+    /// Load a model from a file on the local machine.
+    pub fn load(path: &str) -> Result<Self, failure::Error> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Make sure the settings are for the throttle `knob`.
+    pub fn check_knob(&self, knob: &str) -> Result<(), failure::Error> {
+        match &self.knob {
+            Some(k) if k != knob => Err(failure::format_err!(
+                "The bandwidth model is for {} settings, not {}",
+                k,
+                knob
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// The setting whose measured bandwidth is closest to `mbps`, like Quartz picks it.
+    pub fn setting_for(&self, mbps: f64) -> u64 {
+        self.points
+            .iter()
+            .min_by(|a, b| (a.1 - mbps).abs().total_cmp(&(b.1 - mbps).abs()))
+            .unwrap()
+            .0
+    }
+}
@@ -0,0 +1,341 @@
+//! Measure how a bandwidth throttle setting maps to achieved bandwidth on a host,
+//! producing a bandwidth model that can be used to pick a setting for a target
+//! bandwidth.
+
+use clap::{arg, ArgAction};
+
+use libscail::{dir, get_user_home_dir, Login};
+
+use spurs::{cmd, Execute, SshShell};
+
+use crate::bw_model::BwModel;
+use crate::quartz::{start_quartz, QuartzConfig};
+use crate::throttle::{set_mba, set_uncore_ratio, UncoreInterface, UncoreLimit};
+
+/// Quartz's unthrottled value of the memory controller throttle register.
+const QUARTZ_REG_MAX: u64 = 0x8fff;
+/// The memory controller throttle register Quartz writes (THRT_PWR_DIMM).
+const QUARTZ_REG_OFFSET: &str = "0x190";
+
+/// The memory controller throttle registers of the measured node. They are put
+/// back to unthrottled when this is dropped, so a failed sweep doesn't leave the
+/// node throttled.
+struct QuartzRegThrottle<'s> {
+    ushell: &'s SshShell,
+    regs: &'s [String],
+    active: bool,
+}
+
+impl QuartzRegThrottle<'_> {
+    fn set(&mut self, value: u64) -> Result<(), failure::Error> {
+        // Some registers may be written even if this fails
+        self.active = true;
+        self.write(value)
+    }
+
+    fn restore(mut self) -> Result<(), failure::Error> {
+        self.unthrottle()
+    }
+
+    fn unthrottle(&mut self) -> Result<(), failure::Error> {
+        if !self.active {
+            return Ok(());
+        }
+
+        self.write(QUARTZ_REG_MAX)?;
+        self.active = false;
+
+        Ok(())
+    }
+
+    fn write(&self, value: u64) -> Result<(), failure::Error> {
+        for reg in self.regs {
+            self.ushell.run(cmd!(
+                "sudo setpci -s {} {}.w={:x}",
+                reg,
+                QUARTZ_REG_OFFSET,
+                value
+            ))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for QuartzRegThrottle<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = self.unthrottle() {
+            println!("Failed to restore the memory controller throttle: {}", e);
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Knob {
+    /// The memory controller throttle register Quartz uses.
+    QuartzReg,
+    /// The uncore frequency ratio of the socket of the measured node.
+    Uncore,
+    /// The MBA percentage of the cores running the bandwidth kernel.
+    Mba,
+}
+
+pub fn cli_options() -> clap::Command {
+    clap::Command::new("calibrate_bw")
+        .about("Measure the bandwidth achieved at each setting of a throttle and write a bandwidth model")
+        .arg_required_else_help(true)
+        .disable_version_flag(true)
+        .arg(arg!(<hostname> "The domain name of the remote"))
+        .arg(arg!(<username> "The username on the remote"))
+        .arg(
+            arg!(<knob> "The throttle to sweep")
+                .value_parser(["quartz", "uncore", "mba"]),
+        )
+        .arg(arg!(<output> "The local file to write the model to"))
+        .arg(
+            arg!(--node <NODE> "The NUMA node whose bandwidth is measured. Default: 1")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            arg!(--start <START>
+                "The first setting. Default: 0x800f for quartz, 6 for uncore, 10 for mba")
+                .value_parser(parse_setting),
+        )
+        .arg(
+            arg!(--end <END> "The last setting. Default: 0x8fff for quartz, 30 for uncore, 100 for mba")
+                .value_parser(parse_setting),
+        )
+        .arg(
+            arg!(--step <STEP> "The distance between settings. Default: 15 for quartz, 1 for uncore, 10 for mba")
+                .value_parser(parse_step),
+        )
+        .arg(
+            arg!(--trials <TRIALS> "How many times to measure each setting. Default: 1")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            arg!(--threads <THREADS> "The number of STREAM threads. Default: all cores of socket 0")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            arg!(--uncore_sysfs "Use the intel_uncore_frequency sysfs files instead of the MSR")
+                .action(ArgAction::SetTrue),
+        )
+}
+
+/// Settings can be given in decimal or, like register values usually are, hex.
+fn parse_setting(s: &str) -> Result<u64, std::num::ParseIntError> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse::<u64>(),
+    }
+}
+
+fn parse_step(s: &str) -> Result<u64, String> {
+    match parse_setting(s) {
+        Ok(0) => Err("The step must be at least 1".into()),
+        Ok(step) => Ok(step),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+pub fn run(sub_m: &clap::ArgMatches) -> Result<(), failure::Error> {
+    let login = Login {
+        username: sub_m.get_one::<String>("username").unwrap(),
+        hostname: sub_m.get_one::<String>("hostname").unwrap(),
+        host: sub_m.get_one::<String>("hostname").unwrap(),
+    };
+
+    let knob_name = sub_m.get_one::<String>("knob").unwrap();
+    let knob = match knob_name.as_str() {
+        "quartz" => Knob::QuartzReg,
+        "uncore" => Knob::Uncore,
+        _ => Knob::Mba,
+    };
+    let output = sub_m.get_one::<String>("output").unwrap();
+    let node = *sub_m.get_one::<usize>("node").unwrap_or(&1);
+    let (def_start, def_end, def_step) = match knob {
+        Knob::QuartzReg => (0x800f, QUARTZ_REG_MAX, 15),
+        Knob::Uncore => (6, 30, 1),
+        Knob::Mba => (10, 100, 10),
+    };
+    let start = *sub_m.get_one::<u64>("start").unwrap_or(&def_start);
+    let end = *sub_m.get_one::<u64>("end").unwrap_or(&def_end);
+    let step = *sub_m.get_one::<u64>("step").unwrap_or(&def_step);
+    let trials = *sub_m.get_one::<usize>("trials").unwrap_or(&1);
+    let threads = sub_m.get_one::<usize>("threads").copied();
+    let uncore_interface = if sub_m.get_flag("uncore_sysfs") {
+        UncoreInterface::Sysfs
+    } else {
+        UncoreInterface::Msr
+    };
+
+    let ushell = SshShell::with_any_key(login.username, login.host)?;
+    let user_home = get_user_home_dir(&ushell)?;
+    let results_dir = dir!(&user_home, crate::RESULTS_PATH);
+    let log_file = dir!(&results_dir, "calibrate_bw.log");
+    let stream_dir = dir!(&user_home, crate::WORKLOADS_PATH, "stream/");
+    let quartz_dir = dir!(&user_home, crate::WKSPC_PATH, "quartz/");
+
+    ushell.run(cmd!("mkdir -p {}", results_dir))?;
+    ushell.run(cmd!(
+        "echo performance | sudo tee /sys/devices/system/cpu/cpu*/cpufreq/scaling_governor"
+    ))?;
+
+    let cpu_model = ushell
+        .run(cmd!("grep -m1 'model name' /proc/cpuinfo | cut -d: -f2"))?
+        .stdout
+        .trim()
+        .to_string();
+    // Run STREAM from socket 0 so only the memory of `node` is being throttled
+    let cores = ushell
+        .run(cmd!(
+            "lscpu -p=CPU,SOCKET | grep -v '#' | awk -F, '$2 == 0 {{ print $1 }}' | paste -sd,"
+        ))?
+        .stdout
+        .trim()
+        .to_string();
+    let threads = threads.unwrap_or(cores.split(',').count());
+
+    // Quartz knows where the memory controller registers are, so have it find them
+    let mc_pci_file = dir!(&user_home, "mc_pci_bus");
+    let mc_regs: Vec<String> = if let Knob::QuartzReg = knob {
+        let quartz_cfg = QuartzConfig {
+            read_bw: 1 << 20,
+            write_bw: 1 << 20,
+            latency: None,
+            model_file: None,
+            mc_pci_file: Some(mc_pci_file.clone()),
+            local_model: None,
+            debug_level: 1,
+        };
+        start_quartz(&ushell, &quartz_dir, &user_home, &quartz_cfg)?.stop()?;
+
+        ushell
+            .run(cmd!("awk '$1 == {} {{ print $2 }}' {}", node, mc_pci_file))?
+            .stdout
+            .lines()
+            .map(str::to_string)
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    let mut quartz_reg_throttle = None;
+    let mut uncore_throttle = None;
+    let mut mba_throttle = None;
+    let mut points = Vec::new();
+    let mut setting = start;
+    while setting <= end {
+        match knob {
+            Knob::QuartzReg => quartz_reg_throttle
+                .get_or_insert_with(|| QuartzRegThrottle {
+                    ushell: &ushell,
+                    regs: &mc_regs,
+                    active: false,
+                })
+                .set(setting)?,
+            Knob::Uncore => {
+                let limits = [UncoreLimit {
+                    socket: node,
+                    min_ratio: setting,
+                    max_ratio: setting,
+                }];
+                // Keep one throttle, so the limits from before the first change
+                // are the ones restored
+                match &mut uncore_throttle {
+                    Some(throttle) => throttle.set(&limits)?,
+                    None => {
+                        uncore_throttle = Some(set_uncore_ratio(
+                            &ushell,
+                            &limits,
+                            uncore_interface,
+                            &log_file,
+                        )?)
+                    }
+                }
+            }
+            Knob::Mba => {
+                let groups = [(setting, cores.clone())];
+                // Keep one throttle, which knows whether the first call mounted resctrl
+                match &mut mba_throttle {
+                    Some(throttle) => throttle.set(&groups)?,
+                    None => mba_throttle = Some(set_mba(&ushell, &groups, &log_file)?),
+                }
+            }
+        }
+
+        let mut total = 0.0;
+        for _ in 0..trials {
+            total += run_stream_read_bw(&ushell, &stream_dir, node, &cores, threads)?;
+        }
+        let bw = total / trials as f64;
+
+        println!("{:?} {} ({:#x}): {:.1} MB/s", knob, setting, setting, bw);
+        ushell.run(cmd!("echo '{} {:.6}' | tee -a {}", setting, bw, &log_file))?;
+        points.push((setting, bw));
+
+        setting += step;
+    }
+
+    // Put everything back to unthrottled
+    match knob {
+        Knob::QuartzReg => {
+            if let Some(throttle) = quartz_reg_throttle {
+                throttle.restore()?;
+            }
+        }
+        Knob::Uncore => {
+            if let Some(throttle) = uncore_throttle {
+                throttle.restore()?;
+            }
+        }
+        Knob::Mba => {
+            if let Some(throttle) = mba_throttle {
+                throttle.reset()?;
+            }
+        }
+    }
+
+    let model = BwModel {
+        cpu_model: Some(cpu_model),
+        knob: Some(knob_name.clone()),
+        points,
+    };
+    std::fs::write(output, model.format())?;
+    println!("MODEL: {}", output);
+
+    Ok(())
+}
+
+/// Run STREAM on `cores` against the memory of `node` and return its read bandwidth
+/// in MB/s. Triad reads two arrays for every one it writes, so the read bandwidth is
+/// 2/3 of what it reports.
+fn run_stream_read_bw(
+    ushell: &SshShell,
+    stream_dir: &str,
+    node: usize,
+    cores: &str,
+    threads: usize,
+) -> Result<f64, failure::Error> {
+    let out = ushell
+        .run(
+            cmd!(
+                "OMP_NUM_THREADS={} numactl --membind={} taskset -c {} ./stream",
+                threads,
+                node,
+                cores
+            )
+            .cwd(stream_dir),
+        )?
+        .stdout;
+
+    let triad = out
+        .lines()
+        .find(|l| l.starts_with("Triad:"))
+        .and_then(|l| l.split_whitespace().nth(1))
+        .ok_or_else(|| failure::format_err!("Could not find the Triad rate in the STREAM output"))?
+        .parse::<f64>()?;
+
+    Ok(triad * 2.0 / 3.0)
+}
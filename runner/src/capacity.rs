@@ -0,0 +1,272 @@
+//! Helpers for limiting how much memory of the local node workloads can use.
+
+use serde::{Deserialize, Serialize};
+
+use spurs::{cmd, Execute, SshShell, SshSpawnHandle};
+
+const CAPHOG_PID_FILE: &str = "/tmp/caphog.pid";
+const CAPHOG_OUT: &str = "/tmp/caphog.out";
+const HUGETLB_PAGE_KB: u64 = 2048;
+
+/// How to take memory away from the local node.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum CapacityMethod {
+    /// Offline memory blocks of the node through /sys/devices/system/memory.
+    Offline,
+    /// Reserve hugetlb pages on the node, which regular allocations can't use.
+    Hugetlb,
+    /// Allocate and mlock memory on the node with tools/caphog.
+    Hog,
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct LocalCapacity {
+    /// How much free memory to leave on node 0, in GB.
+    pub gb: usize,
+    pub method: CapacityMethod,
+}
+
+/// What was done to limit the local capacity.
+enum Taken {
+    Offline {
+        blocks: Vec<String>,
+    },
+    Hugetlb {
+        pages_file: String,
+        original_pages: u64,
+    },
+    Hog {
+        handle: SshSpawnHandle,
+    },
+}
+
+/// Memory taken away from the local node. It is given back when this is restored
+/// or dropped, so an early return from the experiment doesn't leave the machine
+/// short of memory.
+pub struct CapacityLimit<'s> {
+    ushell: &'s SshShell,
+    taken: Option<Taken>,
+}
+
+impl CapacityLimit<'_> {
+    /// Give back the memory taken by `limit_local_capacity`.
+    pub fn restore(mut self) -> Result<(), failure::Error> {
+        self.give_back()
+    }
+
+    fn give_back(&mut self) -> Result<(), failure::Error> {
+        match self.taken.take() {
+            Some(Taken::Offline { blocks }) => {
+                for block in blocks.iter().rev() {
+                    self.ushell
+                        .run(cmd!("echo online | sudo tee {}/state", block))?;
+                }
+            }
+            Some(Taken::Hugetlb {
+                pages_file,
+                original_pages,
+            }) => {
+                self.ushell
+                    .run(cmd!("echo {} | sudo tee {}", original_pages, pages_file))?;
+            }
+            Some(Taken::Hog { handle }) => {
+                self.ushell.run(cmd!(
+                    "sudo kill $(cat {}) && sudo rm {}",
+                    CAPHOG_PID_FILE,
+                    CAPHOG_PID_FILE
+                ))?;
+                // Being killed makes the command fail, which is expected
+                let _ = handle.join();
+            }
+            None => (),
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for CapacityLimit<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = self.give_back() {
+            println!("Failed to restore the local capacity: {}", e);
+        }
+    }
+}
+
+fn node_meminfo_kb(ushell: &SshShell, node: usize, field: &str) -> Result<u64, failure::Error> {
+    // Lines look like "Node 0 MemFree:   1234 kB"
+    let out = ushell
+        .run(cmd!(
+            "grep -w '{}:' /sys/devices/system/node/node{}/meminfo | awk '{{ print $4 }}'",
+            field,
+            node
+        ))?
+        .stdout;
+
+    Ok(out.trim().parse::<u64>()?)
+}
+
+/// Take memory away from node 0 until only `cap.gb` GB of it are free. The node's
+/// meminfo before and after is appended to `log_file`, and the free memory is
+/// checked against the limit. Returns `None` if the node already has less than
+/// that free.
+pub fn limit_local_capacity<'s>(
+    ushell: &'s SshShell,
+    cap: &LocalCapacity,
+    tools_dir: &str,
+    log_file: &str,
+) -> Result<Option<CapacityLimit<'s>>, failure::Error> {
+    let node = 0;
+    let target_kb = (cap.gb as u64) << 20;
+
+    // Give back as much of the page cache as we can so it doesn't count as used
+    ushell.run(cmd!("sync && echo 3 | sudo tee /proc/sys/vm/drop_caches"))?;
+    ushell.run(cmd!(
+        "(echo before; cat /sys/devices/system/node/node{}/meminfo) | tee -a {}",
+        node,
+        log_file
+    ))?;
+
+    let free_kb = node_meminfo_kb(ushell, node, "MemFree")?;
+    if free_kb <= target_kb {
+        println!(
+            "Node {} only has {} kB free, so there is nothing to take away",
+            node, free_kb
+        );
+        return Ok(None);
+    }
+    let excess_kb = free_kb - target_kb;
+
+    let limit = match cap.method {
+        CapacityMethod::Offline => {
+            let block_bytes = u64::from_str_radix(
+                ushell
+                    .run(cmd!("cat /sys/devices/system/memory/block_size_bytes"))?
+                    .stdout
+                    .trim(),
+                16,
+            )?;
+            let needed = ((excess_kb << 10) + block_bytes - 1) / block_bytes;
+
+            // The highest blocks are the most likely to only hold movable pages
+            let candidates = ushell
+                .run(cmd!(
+                    "ls -d /sys/devices/system/node/node{}/memory* | sort -V -r",
+                    node
+                ))?
+                .stdout;
+
+            let mut blocks = Vec::new();
+            for block in candidates.lines().map(str::trim) {
+                if blocks.len() as u64 == needed {
+                    break;
+                }
+
+                let state_file = format!("{}/state", block);
+                if ushell.run(cmd!("grep -qx online {}", state_file)).is_err() {
+                    continue;
+                }
+                // Offlining fails if the block has unmovable pages, so just try the next one
+                ushell.run(cmd!("echo offline | sudo tee {}", state_file).allow_error())?;
+                if ushell.run(cmd!("grep -qx offline {}", state_file)).is_ok() {
+                    blocks.push(block.to_string());
+                }
+            }
+
+            let offlined = blocks.len();
+            let limit = CapacityLimit {
+                ushell,
+                taken: Some(Taken::Offline { blocks }),
+            };
+            if (offlined as u64) < needed {
+                limit.restore()?;
+                return Err(failure::format_err!(
+                    "Could only offline {} of the {} memory blocks needed",
+                    offlined,
+                    needed
+                ));
+            }
+
+            limit
+        }
+        CapacityMethod::Hugetlb => {
+            let pages_file = format!(
+                "/sys/devices/system/node/node{}/hugepages/hugepages-{}kB/nr_hugepages",
+                node, HUGETLB_PAGE_KB
+            );
+            let original_pages = ushell
+                .run(cmd!("cat {}", pages_file))?
+                .stdout
+                .trim()
+                .parse::<u64>()?;
+            let pages = original_pages + (excess_kb + HUGETLB_PAGE_KB - 1) / HUGETLB_PAGE_KB;
+
+            // Ready to undo before changing anything, in case the write fails
+            let limit = CapacityLimit {
+                ushell,
+                taken: Some(Taken::Hugetlb {
+                    pages_file: pages_file.clone(),
+                    original_pages,
+                }),
+            };
+            ushell.run(cmd!("echo {} | sudo tee {}", pages, pages_file))?;
+
+            limit
+        }
+        CapacityMethod::Hog => {
+            let hog_mb = (excess_kb + 1023) >> 10;
+
+            // bash just records the pid, which exec keeps for caphog
+            let handle = ushell.spawn(cmd!(
+                "sudo bash -c 'echo $$ > {}; exec {}/caphog {} {} > {}'",
+                CAPHOG_PID_FILE,
+                tools_dir,
+                node,
+                hog_mb,
+                CAPHOG_OUT
+            ))?;
+            let limit = CapacityLimit {
+                ushell,
+                taken: Some(Taken::Hog { handle }),
+            };
+
+            // Faulting in and locking a lot of memory takes a while
+            if let Err(e) = ushell.run(cmd!(
+                "for i in $(seq 600); do grep -q locked {} && exit 0; sleep 1; done; exit 1",
+                CAPHOG_OUT
+            )) {
+                limit.restore()?;
+                return Err(e.into());
+            }
+
+            limit
+        }
+    };
+
+    ushell.run(cmd!(
+        "(echo after; cat /sys/devices/system/node/node{}/meminfo) | tee -a {}",
+        node,
+        log_file
+    ))?;
+
+    // Allow some slack for whatever the rest of the system allocated or freed meanwhile
+    let free_kb = node_meminfo_kb(ushell, node, "MemFree")?;
+    let slack_kb = (target_kb / 20).max(256 << 10);
+    if free_kb > target_kb + slack_kb {
+        limit.restore()?;
+        return Err(failure::format_err!(
+            "Node {} still has {} kB free after limiting it to {} kB",
+            node,
+            free_kb,
+            target_kb
+        ));
+    }
+    if free_kb + slack_kb < target_kb {
+        println!(
+            "WARNING: node {} only has {} kB free after limiting it to {} kB",
+            node, free_kb, target_kb
+        );
+    }
+
+    Ok(Some(limit))
+}
@@ -0,0 +1,178 @@
+//! Helpers for isolating each workload in its own cgroup v2 group.
+
+use serde::{Deserialize, Serialize};
+
+use spurs::{cmd, Execute, SshShell};
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+/// The group the workload groups are made under.
+const CGROUP_PARENT: &str = "cipp";
+const CGROUP_CONTROLLERS: &str = "+cpuset +cpu +memory";
+/// The files sampled by `cgroup_sample_cmd`.
+const CGROUP_STAT_FILES: &str = "memory.numa_stat memory.stat cpu.stat";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CgroupConfig {
+    /// The cpuset.mems of every group. Defaults to all nodes.
+    pub mems: Option<String>,
+    /// memory.max of every group, in MB.
+    pub memory_max_mb: Option<u64>,
+    /// memory.high of every group, in MB.
+    pub memory_high_mb: Option<u64>,
+}
+
+/// The group of the `i`th workload, relative to the cgroup root.
+fn cgroup_name(i: usize) -> String {
+    format!("{}/wkld{}", CGROUP_PARENT, i)
+}
+
+/// The workload groups made by `setup_cgroups`. They are removed when this is
+/// dropped, so a failed run doesn't leave them behind.
+pub struct Cgroups<'s> {
+    ushell: &'s SshShell,
+    groups: Vec<String>,
+    active: bool,
+}
+
+impl Cgroups<'_> {
+    /// The path of each workload's group.
+    pub fn paths(&self) -> &[String] {
+        &self.groups
+    }
+
+    /// Remove the groups, reporting any error.
+    pub fn teardown(mut self) -> Result<(), failure::Error> {
+        self.remove()
+    }
+
+    fn remove(&mut self) -> Result<(), failure::Error> {
+        if !self.active {
+            return Ok(());
+        }
+
+        remove_cgroups(self.ushell)?;
+        self.active = false;
+
+        Ok(())
+    }
+}
+
+impl Drop for Cgroups<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = self.remove() {
+            println!("Failed to remove cgroups: {}", e);
+        }
+    }
+}
+
+/// Make one group per workload, limited to the given cores. Groups left behind
+/// by an earlier run are removed first. The settings of each group are appended
+/// to `log_file`.
+pub fn setup_cgroups<'s>(
+    ushell: &'s SshShell,
+    cfg: &CgroupConfig,
+    cores: &[String],
+    log_file: &str,
+) -> Result<Cgroups<'s>, failure::Error> {
+    if ushell
+        .run(cmd!("test -f {}/cgroup.controllers", CGROUP_ROOT))
+        .is_err()
+    {
+        return Err(failure::format_err!(
+            "{} is not cgroup v2. Boot with systemd.unified_cgroup_hierarchy=1",
+            CGROUP_ROOT
+        ));
+    }
+
+    // Stale groups may have other settings, or still hold processes
+    remove_cgroups(ushell)?;
+
+    let parent = format!("{}/{}", CGROUP_ROOT, CGROUP_PARENT);
+    ushell.run(cmd!(
+        "echo '{}' | sudo tee {}/cgroup.subtree_control",
+        CGROUP_CONTROLLERS,
+        CGROUP_ROOT
+    ))?;
+    ushell.run(cmd!("sudo mkdir -p {}", parent))?;
+    ushell.run(cmd!(
+        "echo '{}' | sudo tee {}/cgroup.subtree_control",
+        CGROUP_CONTROLLERS,
+        parent
+    ))?;
+
+    let mems = match &cfg.mems {
+        Some(mems) => mems.clone(),
+        None => ushell
+            .run(cmd!("cat {}/cpuset.mems.effective", CGROUP_ROOT))?
+            .stdout
+            .trim()
+            .to_string(),
+    };
+
+    let mut cgroups = Cgroups {
+        ushell,
+        groups: Vec::new(),
+        active: true,
+    };
+    for (i, cores) in cores.iter().enumerate() {
+        let group = format!("{}/{}", CGROUP_ROOT, cgroup_name(i));
+
+        ushell.run(cmd!("sudo mkdir -p {}", group))?;
+        ushell.run(cmd!("echo {} | sudo tee {}/cpuset.cpus", cores, group))?;
+        ushell.run(cmd!("echo {} | sudo tee {}/cpuset.mems", mems, group))?;
+        if let Some(max) = cfg.memory_max_mb {
+            ushell.run(cmd!("echo {}M | sudo tee {}/memory.max", max, group))?;
+        }
+        if let Some(high) = cfg.memory_high_mb {
+            ushell.run(cmd!("echo {}M | sudo tee {}/memory.high", high, group))?;
+        }
+
+        ushell.run(cmd!(
+            "(echo wkld{}; cd {} && grep -H . cpuset.cpus cpuset.mems memory.max memory.high) | tee -a {}",
+            i,
+            group,
+            log_file
+        ))?;
+        cgroups.groups.push(group);
+    }
+
+    Ok(cgroups)
+}
+
+/// A command prefix that runs the rest of the command in the `i`th workload's
+/// group. Moving a process into a group takes root, so only cgexec runs as root
+/// and it hands the command back to the user.
+pub fn cgroup_exec_prefix(i: usize) -> String {
+    format!(
+        "sudo cgexec -g cpuset,cpu,memory:{} sudo -E -u $USER env PATH=$PATH ",
+        cgroup_name(i)
+    )
+}
+
+/// A command that appends a timestamped sample of the group's memory.numa_stat,
+/// memory.stat and cpu.stat to `file`. Each line is prefixed with the file it
+/// came from.
+pub fn cgroup_sample_cmd(group: &str, file: &str) -> String {
+    format!(
+        "(echo time $(date +%s.%N); cd {} && grep -H . {}) >> {}",
+        group, CGROUP_STAT_FILES, file
+    )
+}
+
+/// Remove every workload group and their parent, if they exist. Killed workloads
+/// can take a moment to leave their group, so retry for a bit.
+fn remove_cgroups(ushell: &SshShell) -> Result<(), failure::Error> {
+    let parent = format!("{}/{}", CGROUP_ROOT, CGROUP_PARENT);
+
+    ushell.run(cmd!(
+        "for group in {}/wkld*/; do \
+             [ -d \"$group\" ] || continue; \
+             for i in $(seq 10); do sudo rmdir \"$group\" && continue 2; sleep 1; done; \
+             exit 1; \
+         done",
+        parent
+    ))?;
+    ushell.run(cmd!("if [ -d {0} ]; then sudo rmdir {0}; fi", parent))?;
+
+    Ok(())
+}
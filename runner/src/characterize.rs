@@ -0,0 +1,328 @@
+//! Measure loaded latency: how the latency of each memory tier grows as an
+//! antagonist consumes more of its bandwidth.
+
+use clap::arg;
+
+use libscail::{
+    dir, get_user_home_dir,
+    output::{Parametrize, Timestamp},
+    Login,
+};
+
+use serde::{Deserialize, Serialize};
+
+use spurs::{cmd, Execute, SshShell};
+use spurs_util::escape_for_bash;
+
+const ANTAGONIST_PID_FILE: &str = "/tmp/antagonist.pid";
+const LATPROBE_OUT: &str = "/tmp/latprobe.out";
+const LATPROBE_BWMON_OUT: &str = "/tmp/latprobe.bwmon";
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+enum Antagonist {
+    Stream,
+    Gups { exp: usize, hot_exp: usize },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Parametrize)]
+struct CharacterizeConfig {
+    #[name]
+    exp: String,
+
+    #[name]
+    antagonist: Antagonist,
+    /// The nodes to measure, in order.
+    nodes: Vec<usize>,
+    /// How many more antagonist threads to add at each step.
+    thread_step: usize,
+    /// The most antagonist threads to use. Defaults to all cores of socket 0 but
+    /// the probe's.
+    max_threads: Option<usize>,
+    /// How long to measure latency at each step, in seconds.
+    duration: u64,
+    probe_size_mb: usize,
+
+    #[timestamp]
+    timestamp: Timestamp,
+}
+
+pub fn cli_options() -> clap::Command {
+    clap::Command::new("characterize")
+        .about(
+            "Measure the loaded latency of each node by pinning a latency probe to one core \
+             and ramping up a bandwidth antagonist",
+        )
+        .arg(
+            arg!(--antagonist <ANTAGONIST> "The workload generating load. Default: stream")
+                .value_parser(["stream", "gups"]),
+        )
+        .arg(
+            arg!(--exp <exp> "The log of the size of the GUPS antagonist. Default: 30")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            arg!(--hot_exp <hot_exp> "The log of the size of the GUPS antagonist's hot region. Default: --exp")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            arg!(--nodes <NODES> "Comma separated nodes to measure. Default: 0,1"),
+        )
+        .arg(
+            arg!(--thread_step <THREADS> "How many antagonist threads to add at each step. Default: 2")
+                .value_parser(clap::builder::RangedU64ValueParser::<usize>::new().range(1..)),
+        )
+        .arg(
+            arg!(--max_threads <THREADS> "The most antagonist threads to use. Default: the rest of socket 0")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            arg!(--duration <SECONDS> "How long to measure latency at each step. Default: 10")
+                .value_parser(clap::value_parser!(u64)),
+        )
+        .arg(
+            arg!(--probe_size <MB> "The size of the latency probe's buffer. Default: 1024")
+                .value_parser(clap::value_parser!(usize)),
+        )
+}
+
+pub fn run<A>(login: &Login<A>, sub_m: &clap::ArgMatches) -> Result<(), failure::Error>
+where
+    A: std::net::ToSocketAddrs + std::fmt::Display + std::fmt::Debug + Clone,
+{
+    let exp = *sub_m.get_one::<usize>("exp").unwrap_or(&30);
+    let antagonist = match sub_m.get_one::<String>("antagonist").map(String::as_str) {
+        Some("gups") => Antagonist::Gups {
+            exp,
+            hot_exp: *sub_m.get_one::<usize>("hot_exp").unwrap_or(&exp),
+        },
+        _ => Antagonist::Stream,
+    };
+    let nodes: Vec<usize> = sub_m
+        .get_one::<String>("nodes")
+        .map_or(Ok(vec![0, 1]), |nodes| {
+            nodes.split(',').map(str::parse::<usize>).collect()
+        })?;
+
+    let cfg = CharacterizeConfig {
+        exp: "cipp_characterize".into(),
+        antagonist,
+        nodes,
+        thread_step: *sub_m.get_one::<usize>("thread_step").unwrap_or(&2),
+        max_threads: sub_m.get_one::<usize>("max_threads").copied(),
+        duration: *sub_m.get_one::<u64>("duration").unwrap_or(&10),
+        probe_size_mb: *sub_m.get_one::<usize>("probe_size").unwrap_or(&1024),
+        timestamp: Timestamp::now(),
+    };
+
+    run_inner(login, &cfg)
+}
+
+fn socket_cores(ushell: &SshShell, socket: usize) -> Result<Vec<usize>, failure::Error> {
+    let out = ushell
+        .run(cmd!(
+            "lscpu -p=CPU,SOCKET | grep -v '#' | awk -F, '$2 == {} {{ print $1 }}'",
+            socket
+        ))?
+        .stdout;
+
+    Ok(out
+        .lines()
+        .map(|l| l.trim().parse::<usize>())
+        .collect::<Result<_, _>>()?)
+}
+
+fn run_inner<A>(login: &Login<A>, cfg: &CharacterizeConfig) -> Result<(), failure::Error>
+where
+    A: std::net::ToSocketAddrs + std::fmt::Display + std::fmt::Debug + Clone,
+{
+    let ushell = SshShell::with_any_key(login.username, &login.host)?;
+    let user_home = get_user_home_dir(&ushell)?;
+
+    let results_dir = dir!(&user_home, crate::RESULTS_PATH);
+    let (_output_file, params_file, _time_file, _sim_file) = cfg.gen_standard_names();
+    let tools_dir = dir!(&user_home, crate::WKSPC_PATH, "tools/");
+    let stream_dir = dir!(&user_home, crate::WORKLOADS_PATH, "stream/");
+    let gups_dir = dir!(&user_home, crate::WORKLOADS_PATH, "gups_hemem/");
+
+    ushell.run(cmd!("mkdir -p {}", results_dir))?;
+    ushell.run(cmd!(
+        "echo {} > {}",
+        escape_for_bash(&serde_json::to_string(&cfg)?),
+        dir!(&results_dir, params_file)
+    ))?;
+
+    ushell.run(cmd!(
+        "echo performance | sudo tee /sys/devices/system/cpu/cpu*/cpufreq/scaling_governor"
+    ))?;
+    ushell.run(cmd!("make ARCH={} latprobe bwmon", crate::TOOLS_ARCH).cwd(&tools_dir))?;
+
+    // The probe gets the first core of socket 0 and the antagonist the rest.
+    // bwmon runs on socket 1 so it doesn't compete with either.
+    let local_cores = socket_cores(&ushell, 0)?;
+    let probe_core = local_cores[0];
+    let antagonist_cores = &local_cores[1..];
+    let monitor_core = socket_cores(&ushell, 1)?[0];
+    let max_threads = cfg
+        .max_threads
+        .unwrap_or(antagonist_cores.len())
+        .min(antagonist_cores.len());
+
+    for &node in &cfg.nodes {
+        let curve_file = dir!(
+            &results_dir,
+            cfg.gen_file_name(&format!("latcurve.node{}", node))
+        );
+        ushell.run(cmd!(
+            "echo 'Antagonist Threads,Node BW (MB/s),Latency (ns)' > {}",
+            curve_file
+        ))?;
+
+        for threads in (0..=max_threads).step_by(cfg.thread_step) {
+            let mut antagonist = None;
+            if threads > 0 {
+                let cores = antagonist_cores[..threads]
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(",");
+                let antagonist_cmd = match cfg.antagonist {
+                    Antagonist::Stream => format!(
+                        "cd {} && OMP_NUM_THREADS={} numactl --membind={} taskset -c {} ./stream",
+                        stream_dir, threads, node, cores
+                    ),
+                    Antagonist::Gups { exp, hot_exp } => format!(
+                        "cd {} && numactl --membind={} taskset -c {} ./gups-hotset-move {} {} {} 8 {} n",
+                        gups_dir,
+                        node,
+                        cores,
+                        threads,
+                        (1usize << exp) / 8,
+                        exp,
+                        hot_exp
+                    ),
+                };
+
+                antagonist = Some(AntagonistLoop::start(&ushell, &antagonist_cmd)?);
+                // Let the antagonist ramp up
+                ushell.run(cmd!("sleep 5"))?;
+            }
+
+            ushell.run(cmd!(
+                "taskset -c {} {}/latprobe {} {} {} > {} & \
+                 sudo taskset -c {} {}/bwmon 100 {} $!; wait",
+                probe_core,
+                tools_dir,
+                node,
+                cfg.probe_size_mb,
+                cfg.duration,
+                LATPROBE_OUT,
+                monitor_core,
+                tools_dir,
+                LATPROBE_BWMON_OUT
+            ))?;
+
+            if let Some(antagonist) = antagonist {
+                antagonist.stop()?;
+            }
+
+            let latency = ushell
+                .run(cmd!("grep latency_ns {}", LATPROBE_OUT))?
+                .stdout
+                .split_whitespace()
+                .nth(1)
+                .ok_or_else(|| failure::format_err!("latprobe did not report a latency"))?
+                .parse::<f64>()?;
+            let bw = average_node_bw(
+                &ushell.run(cmd!("cat {}", LATPROBE_BWMON_OUT))?.stdout,
+                node,
+            );
+
+            println!(
+                "node {} antagonist threads {}: {:.1} MB/s {:.1} ns",
+                node, threads, bw, latency
+            );
+            ushell.run(cmd!(
+                "echo '{},{:.1},{:.2}' >> {}",
+                threads,
+                bw,
+                latency,
+                curve_file
+            ))?;
+        }
+    }
+
+    println!("RESULTS: {}", dir!(&results_dir, cfg.gen_file_name("")));
+    Ok(())
+}
+
+/// An antagonist rerun in a loop in the background. The loop is killed when this
+/// is dropped, so a failed measurement doesn't leave it loading the machine.
+struct AntagonistLoop<'s> {
+    ushell: &'s SshShell,
+    running: bool,
+}
+
+impl<'s> AntagonistLoop<'s> {
+    fn start(ushell: &'s SshShell, antagonist_cmd: &str) -> Result<Self, failure::Error> {
+        // Keep the antagonist running in its own process group so the whole
+        // loop can be killed at once
+        ushell.spawn(cmd!(
+            "setsid bash -c {}",
+            escape_for_bash(&format!(
+                "echo $$ > {}; while true; do {} > /dev/null; done",
+                ANTAGONIST_PID_FILE, antagonist_cmd
+            ))
+        ))?;
+
+        Ok(AntagonistLoop {
+            ushell,
+            running: true,
+        })
+    }
+
+    fn stop(mut self) -> Result<(), failure::Error> {
+        self.kill()
+    }
+
+    fn kill(&mut self) -> Result<(), failure::Error> {
+        if !self.running {
+            return Ok(());
+        }
+
+        self.ushell.run(cmd!(
+            "kill -- -$(cat {0}) && rm -f {0}",
+            ANTAGONIST_PID_FILE
+        ))?;
+        self.running = false;
+
+        Ok(())
+    }
+}
+
+impl Drop for AntagonistLoop<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = self.kill() {
+            println!("Failed to kill the antagonist: {}", e);
+        }
+    }
+}
+
+/// Average the total bandwidth bwmon reported for `node`.
+fn average_node_bw(bwmon_output: &str, node: usize) -> f64 {
+    let prefix = format!("Node {}:", node);
+    let samples: Vec<f64> = bwmon_output
+        .lines()
+        .filter(|l| l.starts_with(&prefix))
+        .filter_map(|l| {
+            // Node <n>: Read <r> Write <w> Total <t> MB/s
+            let fields: Vec<&str> = l.split_whitespace().collect();
+            fields.get(7).and_then(|t| t.parse::<f64>().ok())
+        })
+        .collect();
+
+    if samples.is_empty() {
+        0.0
+    } else {
+        samples.iter().sum::<f64>() / samples.len() as f64
+    }
+}
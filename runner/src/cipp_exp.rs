@@ -1,7 +1,6 @@
-use clap::{arg, ArgAction};
+use clap::{arg, ArgAction, ArgGroup};
 
 use libscail::{
-    background::{BackgroundContext, BackgroundTask},
     dir, dump_sys_info, get_user_home_dir,
     output::{Parametrize, Timestamp},
     set_kernel_printk_level, with_shell,
@@ -12,6 +11,21 @@ use libscail::{
 
 use serde::{Deserialize, Serialize};
 
+use crate::capacity::{limit_local_capacity, CapacityMethod, LocalCapacity};
+use crate::cgroup::{cgroup_exec_prefix, setup_cgroups, CgroupConfig};
+use crate::throttle::{set_mba, set_uncore_ratio, UncoreInterface, UncoreLimit};
+use crate::bw_model::BwModel;
+use crate::quartz::{quartz_preload, start_quartz, QuartzConfig, QuartzLatency};
+use crate::manifest::{write_manifest, Artifact};
+use crate::report::write_report;
+use crate::results::{index_run, DB_FILE};
+use crate::retrieve::{retrieve_results, RetrieveConfig};
+use crate::monitor::{MonitorEnv, MonitorKind, MonitorSet};
+use crate::numa_maps::PlacementTarget;
+use crate::optimize_ratio::golden_section_search;
+use crate::timeline::Timeline;
+use crate::schedule::{parse_scheduled_action, ScheduleAction, ScheduledAction, ScheduledCmd, Scheduler};
+
 use spurs::{cmd, Execute, SshShell, SshSpawnHandle};
 use spurs_util::escape_for_bash;
 
@@ -60,15 +74,88 @@ enum Strategy {
     Numactl { local: usize, remote: usize },
     Cipp { total_bw: bool },
     Linux,
+    Membind { node: usize },
+    Preferred { node: usize },
+    CapacitySplit { local_gb: usize },
 }
 
-#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 enum ThrottleType {
-    Quartz { bw: u64 },
-    Msr,
+    Quartz(QuartzConfig),
+    /// Limit the uncore frequency of each socket in `limits`. If `target_bw` is
+    /// set, the ratios were picked from a bandwidth model to get close to it.
+    Uncore {
+        limits: Vec<UncoreLimit>,
+        target_bw: Option<u64>,
+        interface: UncoreInterface,
+    },
+    /// Intel RDT Memory Bandwidth Allocation. Either one percentage for all
+    /// workloads, or one per workload, each in its own class of service.
+    Mba { percents: Vec<u64> },
     Native,
 }
 
+impl ThrottleType {
+    /// What --msr_throttle does: the uncore ratio of socket 1 fixed at 6, which
+    /// used to be hardcoded for c220g2.
+    fn msr() -> Self {
+        ThrottleType::Uncore {
+            limits: vec![UncoreLimit {
+                socket: 1,
+                min_ratio: 6,
+                max_ratio: 6,
+            }],
+            target_bw: None,
+            interface: UncoreInterface::Msr,
+        }
+    }
+}
+
+/// Deserialize a `ThrottleType`, also accepting the `Msr` and `Quartz { bw }`
+/// variants, and the `Uncore` with one ratio for all sockets, that params saved by
+/// older versions have.
+fn deserialize_throttle<'de, D>(deserializer: D) -> Result<ThrottleType, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let mut value = serde_json::Value::deserialize(deserializer)?;
+    if value == "Msr" {
+        return Ok(ThrottleType::msr());
+    }
+    if let Some(bw) = value["Quartz"]["bw"].as_u64() {
+        return Ok(ThrottleType::Quartz(QuartzConfig {
+            read_bw: bw,
+            write_bw: bw,
+            latency: None,
+            model_file: None,
+            mc_pci_file: None,
+            local_model: None,
+            debug_level: 1,
+        }));
+    }
+
+    if let Some(uncore) = value.get_mut("Uncore").and_then(|u| u.as_object_mut()) {
+        if let Some(sockets) = uncore.remove("sockets") {
+            let (min_ratio, max_ratio) = (uncore.remove("min_ratio"), uncore.remove("max_ratio"));
+            let limits: Vec<serde_json::Value> = sockets
+                .as_array()
+                .map_or(&[][..], |s| s.as_slice())
+                .iter()
+                .map(|socket| {
+                    serde_json::json!({
+                        "socket": socket,
+                        "min_ratio": min_ratio,
+                        "max_ratio": max_ratio,
+                    })
+                })
+                .collect();
+            uncore.insert("limits".into(), limits.into());
+        }
+    }
+
+    serde_json::from_value(value).map_err(serde::de::Error::custom)
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Parametrize)]
 struct Config {
     #[name]
@@ -78,6 +165,9 @@ struct Config {
     workloads: Vec<Workload>,
     #[name]
     strategy: Strategy,
+    /// Per-workload overrides of `strategy`, indexed like `workloads`.
+    #[serde(default)]
+    wkld_strategies: Vec<Option<Strategy>>,
 
     kill_after_first_done: bool,
     perf_stat: bool,
@@ -85,16 +175,253 @@ struct Config {
     disable_thp: bool,
     disable_aslr: bool,
     flame_graph: bool,
-    bwmon: bool,
-    meminfo: bool,
-    memlat: bool,
+    /// Monitors to run on top of the defaults of the strategies in use.
+    #[serde(default)]
+    monitors: Vec<MonitorKind>,
+    /// Run only the monitors in `monitors`.
+    #[serde(default)]
+    no_default_monitors: bool,
     time: bool,
+    #[serde(deserialize_with = "deserialize_throttle")]
     throttle: ThrottleType,
+    /// Run each workload in its own cgroup v2 group.
+    #[serde(default)]
+    cgroups: Option<CgroupConfig>,
+    /// Limit how much of the local node's memory the workloads can use.
+    #[serde(default)]
+    local_capacity: Option<LocalCapacity>,
+    /// Placement changes to apply at fixed times after the workloads start.
+    #[serde(default)]
+    schedule: Vec<ScheduledAction>,
+    /// Where to copy the results to locally, if anywhere.
+    #[serde(default)]
+    retrieve: Option<RetrieveConfig>,
 
     #[timestamp]
     timestamp: Timestamp,
 }
 
+impl Workload {
+    /// Whether `wkld_metric` is a throughput rather than a time.
+    fn higher_is_better(&self) -> bool {
+        matches!(self, Workload::Stream)
+    }
+
+    /// The name the workload's process shows up as, e.g. to pkill.
+    fn proc_name(&self) -> &'static str {
+        match self {
+            Workload::Merci { .. } => "eval_baseline",
+            Workload::GapbsTc { .. } => "tc",
+            Workload::GapbsPr { .. } => "pr",
+            Workload::Gups { .. } => "gups-hotset-mov",
+            Workload::CloverLeaf { .. } => "omp-cloverleaf",
+            Workload::Redis { .. } => "redis-server",
+            Workload::Stream => "stream",
+            Workload::SpecBwaves { .. } => "speed_bwaves_ba",
+            Workload::SpecLbm { .. } => "lbm_s_base.mark",
+        }
+    }
+}
+
+impl Strategy {
+    /// The monitors a run with this strategy gets unless told otherwise.
+    fn default_monitors(&self) -> Vec<MonitorKind> {
+        match self {
+            // Colloid's tiering decisions are based on the measured latency
+            Strategy::Colloid => vec![MonitorKind::ColloidLatency, MonitorKind::Vmstat],
+            // The placement is static, so check that we got it
+            Strategy::Numactl { .. } | Strategy::Bwmfs { .. } | Strategy::CapacitySplit { .. } => {
+                vec![MonitorKind::Vmstat, MonitorKind::NumaMaps { period: 5 }]
+            }
+            _ => vec![MonitorKind::Vmstat],
+        }
+    }
+}
+
+impl Config {
+    /// The monitors to run: the defaults of every strategy in use, unless
+    /// disabled, plus the ones asked for.
+    fn monitors(&self, strategies: &[Strategy]) -> Result<Vec<MonitorKind>, failure::Error> {
+        let mut monitors: Vec<MonitorKind> = Vec::new();
+        let defaults = if self.no_default_monitors {
+            Vec::new()
+        } else {
+            strategies.iter().flat_map(Strategy::default_monitors).collect()
+        };
+        for m in defaults.into_iter().chain(self.monitors.iter().cloned()) {
+            // An explicit numa_maps period overrides the default one
+            if let MonitorKind::NumaMaps { .. } = m {
+                monitors.retain(|m| !matches!(m, MonitorKind::NumaMaps { .. }));
+            }
+            if !monitors.contains(&m) {
+                monitors.push(m);
+            }
+        }
+
+        // memlat measures the latency for Colloid instead of colloid-mon
+        if monitors.contains(&MonitorKind::Memlat) {
+            monitors.retain(|m| *m != MonitorKind::ColloidLatency);
+        }
+        let colloid = strategies.iter().any(|s| matches!(s, Strategy::Colloid));
+        let colloid_lat = monitors
+            .iter()
+            .any(|m| matches!(m, MonitorKind::ColloidLatency | MonitorKind::Memlat));
+        if colloid && !colloid_lat {
+            return Err(failure::format_err!(
+                "Colloid needs the colloid_latency or memlat monitor"
+            ));
+        }
+
+        if self.cgroups.is_some() && !monitors.contains(&MonitorKind::CgroupStat) {
+            monitors.push(MonitorKind::CgroupStat);
+        }
+
+        Ok(monitors)
+    }
+
+    /// Resolve the strategy each workload runs under, falling back to the global
+    /// strategy if the workload has no override. BWMFS strategies are returned
+    /// with only the ratio of that workload.
+    fn wkld_strategies(&self) -> Result<Vec<Strategy>, failure::Error> {
+        // Only the CLI checks this, not configs that were loaded or generated
+        if let Strategy::Bwmfs { ratios } = &self.strategy {
+            if ratios.len() != self.workloads.len() {
+                return Err(failure::format_err!(
+                    "BWMFS has {} ratios but there are {} workloads",
+                    ratios.len(),
+                    self.workloads.len()
+                ));
+            }
+        }
+
+        let strategies: Vec<Strategy> = (0..self.workloads.len())
+            .map(|i| match self.wkld_strategies.get(i) {
+                Some(Some(s)) => s.clone(),
+                _ => match &self.strategy {
+                    Strategy::Bwmfs { ratios } => Strategy::Bwmfs {
+                        ratios: vec![ratios[i]],
+                    },
+                    s => s.clone(),
+                },
+            })
+            .collect();
+
+        for (i, s) in self.wkld_strategies.iter().enumerate() {
+            match s {
+                Some(Strategy::Tpp) | Some(Strategy::Colloid) => {
+                    return Err(failure::format_err!(
+                        "Workload {} cannot use {:?}: it applies to the whole system",
+                        i,
+                        s.as_ref().unwrap()
+                    ));
+                }
+                Some(Strategy::Bwmfs { ratios }) if ratios.len() != 1 => {
+                    return Err(failure::format_err!(
+                        "Workload {} must have exactly one BWMFS ratio",
+                        i
+                    ));
+                }
+                _ => (),
+            }
+        }
+
+        // The weighted interleave weights and the CIPP controller are shared by
+        // every workload that uses them, so they must agree
+        let numactl_ratios: Vec<(usize, usize)> = strategies
+            .iter()
+            .filter_map(|s| match s {
+                Strategy::Numactl { local, remote } => Some((*local, *remote)),
+                _ => None,
+            })
+            .collect();
+        let cipp_variants: Vec<bool> = strategies
+            .iter()
+            .filter_map(|s| match s {
+                Strategy::Cipp { total_bw } => Some(*total_bw),
+                _ => None,
+            })
+            .collect();
+
+        if !numactl_ratios.is_empty() && !cipp_variants.is_empty() {
+            return Err(failure::format_err!(
+                "CIPP adjusts the global weighted interleave weights, so it cannot be \
+                 combined with a numactl ratio. Use BWMFS for a fixed ratio instead."
+            ));
+        }
+        if numactl_ratios.windows(2).any(|w| w[0] != w[1]) {
+            return Err(failure::format_err!(
+                "Weighted interleave weights are global, so all workloads using numactl \
+                 must have the same ratio. Use BWMFS for per-workload ratios."
+            ));
+        }
+        if cipp_variants.windows(2).any(|w| w[0] != w[1]) {
+            return Err(failure::format_err!(
+                "All workloads using CIPP must use the same variant"
+            ));
+        }
+
+        Ok(strategies)
+    }
+
+    /// Make sure every scheduled action has something to act on.
+    fn check_schedule(&self, strategies: &[Strategy]) -> Result<(), failure::Error> {
+        let uses_cipp = strategies
+            .iter()
+            .any(|s| matches!(s, Strategy::Cipp { .. }));
+
+        for a in &self.schedule {
+            match a.action {
+                ScheduleAction::BwmfsWeights { mount, .. } => {
+                    let is_bwmfs = mount >= 1
+                        && matches!(strategies.get(mount - 1), Some(Strategy::Bwmfs { .. }));
+                    if !is_bwmfs {
+                        return Err(failure::format_err!(
+                            "Scheduled action at {}s changes bwmmfs{}, which is not used by any workload",
+                            a.at_secs,
+                            mount
+                        ));
+                    }
+                }
+                ScheduleAction::StartCipp | ScheduleAction::StopCipp if !uses_cipp => {
+                    return Err(failure::format_err!(
+                        "Scheduled action at {}s controls CIPP, but no workload uses CIPP",
+                        a.at_secs
+                    ));
+                }
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// If the schedule starts the CIPP controller before ever stopping it, the
+    /// controller should not run until then.
+    fn cipp_starts_scheduled(&self) -> bool {
+        let mut actions: Vec<&ScheduledAction> = self.schedule.iter().collect();
+        actions.sort_by_key(|a| a.at_secs);
+        actions
+            .iter()
+            .find_map(|a| match a.action {
+                ScheduleAction::StartCipp => Some(true),
+                ScheduleAction::StopCipp => Some(false),
+                _ => None,
+            })
+            .unwrap_or(false)
+    }
+}
+
+/// Returns the weighted interleave weights that the given strategies need, if any.
+/// Assumes the strategies were validated by `Config::wkld_strategies`.
+fn interleave_weights(strategies: &[Strategy]) -> Option<(usize, usize)> {
+    strategies.iter().find_map(|s| match s {
+        Strategy::Numactl { local, remote } => Some((*local, *remote)),
+        // CIPP starts with everything local and adjusts the weights itself
+        Strategy::Cipp { .. } => Some((100, 0)),
+        _ => None,
+    })
+}
+
 pub fn cli_options() -> clap::Command {
     clap::Command::new("cipp_exp")
         .about("Run an experiment for cipp")
@@ -120,6 +447,38 @@ pub fn cli_options() -> clap::Command {
             .action(ArgAction::SetTrue).conflicts_with("colloid").conflicts_with("tpp").conflicts_with("bwmfs").conflicts_with("numactl"))
         .arg(arg!(--cipp_total_bw "Use the total BW varient of CIPP")
             .action(ArgAction::SetTrue).requires("cipp"))
+        .group(
+            ArgGroup::new("static_placement")
+                .args(["membind", "preferred", "capacity_split"])
+                .conflicts_with_all(["tpp", "colloid", "bwmfs", "numactl", "cipp"]),
+        )
+        .arg(arg!(--wkld_strategy <WKLD_STRATEGY>
+            "Override the strategy of one workload, as <workload index>=<strategy>. The strategy \
+            is one of linux, membind:<node>, preferred:<node>, capacity_split:<local GB>, \
+            numactl:<local>:<remote>, bwmfs:<local>:<remote>, cipp, or cipp_total_bw.")
+            .action(ArgAction::Append))
+        .arg(arg!(--schedule <SCHEDULE>
+            "Apply an action at a time after the workloads start, as <seconds>=<action>. The action \
+            is one of interleave:<local>:<remote>, bwmfs:<mount>:<local>:<remote>, \
+            numa_balancing:<mode>, cipp_start, or cipp_stop.")
+            .action(ArgAction::Append))
+        .arg(arg!(--membind <NODE> "Bind all workload memory to the specified NUMA node")
+            .value_parser(clap::value_parser!(usize)))
+        .arg(arg!(--preferred <NODE> "Prefer allocating workload memory from the specified NUMA node")
+            .value_parser(clap::value_parser!(usize)))
+        .arg(arg!(--capacity_split <LOCAL_GB>
+            "Place the first LOCAL_GB of each workload's memory on the local node, then spill to the remote node")
+            .value_parser(clap::value_parser!(usize)))
+        .arg(arg!(--optimize_ratio <RANGE>
+            "Instead of a single run, search <min>:<max> for the numactl local ratio (out of 100) \
+            that gives the first workload the best performance")
+            .conflicts_with_all(["tpp", "colloid", "bwmfs", "numactl", "cipp", "static_placement", "wkld_strategy", "schedule"]))
+        .arg(arg!(--optimize_tol <TOL> "Stop searching once the ratio is known within TOL. Default: 5")
+            .value_parser(clap::value_parser!(usize))
+            .requires("optimize_ratio"))
+        .arg(arg!(--optimize_trials <TRIALS> "How many times to run each ratio. Default: 3")
+            .value_parser(clap::builder::RangedU64ValueParser::<usize>::new().range(1..))
+            .requires("optimize_ratio"))
         .arg(arg!(--memlat "Use memlat with Colloid")
             .action(ArgAction::SetTrue).requires("colloid"))
         .arg(
@@ -135,18 +494,150 @@ pub fn cli_options() -> clap::Command {
                 .action(ArgAction::SetTrue),
         )
         .arg(
-            arg!(--time "Run the workloads with GNU time")
+            arg!(--numa_maps <SECS>
+                "Snapshot each workload's numa_maps every SECS seconds and check its placement")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            arg!(--monitor <NAME>
+                "Run the given monitor on top of the strategy's defaults. One of colloid_latency, \
+                memlat, bwmon, meminfo, numa_maps, vmstat, cgroup_stat, rapl, perf_mem, tiertrace, \
+                damon_vaddr or damon_paddr")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            arg!(--perf_mem <WKLDS>
+                "Sample the memory accesses of the given comma separated workload indices with \
+                perf mem, and attribute them to a node, region and symbol"),
+        )
+        .arg(
+            arg!(--local_results <DIR> "The local directory to copy the results to")
+                .default_value("results"),
+        )
+        .arg(
+            arg!(--no_retrieve "Leave the results on the remote instead of copying them back")
                 .action(ArgAction::SetTrue)
+                .conflicts_with("local_results"),
         )
         .arg(
-            arg!(--quartz <QUARTZ_BW> "Use Quartz to limit the memory bandwidth (MB/s)")
+            arg!(--compress_over <MB> "Gzip results bigger than MB before copying them. Default: 64")
                 .value_parser(clap::value_parser!(u64))
-                .conflicts_with("msr_throttle"),
+                .default_value("64"),
         )
         .arg(
-            arg!(--msr_throttle "Write to the MSR_UNCORE_RATIO_LIMIT register to lower uncore frequency")
+            arg!(--no_default_monitors "Only run the monitors that are asked for")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--time "Run the workloads with GNU time")
                 .action(ArgAction::SetTrue)
-                .conflicts_with("quartz"),
+        )
+        .arg(
+            arg!(--cgroup "Run each workload in its own cgroup v2 group and sample its memory and CPU stats")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--cgroup_mems <NODES> "The cpuset.mems of each workload's group. Default: all nodes")
+                .requires("cgroup"),
+        )
+        .arg(
+            arg!(--cgroup_memory_max <MB> "The memory.max of each workload's group")
+                .value_parser(clap::value_parser!(u64))
+                .requires("cgroup"),
+        )
+        .arg(
+            arg!(--cgroup_memory_high <MB> "The memory.high of each workload's group")
+                .value_parser(clap::value_parser!(u64))
+                .requires("cgroup"),
+        )
+        .arg(
+            arg!(--local_capacity <GB> "Limit the free memory of the local node to GB before running the workloads")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            arg!(--local_capacity_method <METHOD> "How to take memory away from the local node")
+                .value_parser(["offline", "hugetlb", "hog"])
+                .default_value("hugetlb"),
+        )
+        .group(
+            ArgGroup::new("throttle")
+                .args(["quartz", "msr_throttle", "uncore", "uncore_bw", "mba", "mba_bw"]),
+        )
+        .arg(
+            arg!(--quartz <QUARTZ_BW> "Use Quartz to limit the memory read bandwidth (MB/s)")
+                .value_parser(clap::value_parser!(u64)),
+        )
+        .arg(
+            arg!(--quartz_write_bw <MBPS> "The write bandwidth Quartz should limit to. Default: --quartz")
+                .value_parser(clap::value_parser!(u64))
+                .requires("quartz"),
+        )
+        .arg(
+            arg!(--quartz_lat <LATENCY>
+                "Also have Quartz emulate <read ns>:<write ns> memory latency for the workloads")
+                .requires("quartz"),
+        )
+        .arg(
+            arg!(--quartz_epoch <US> "How often Quartz injects latency, in us. Default: 10000")
+                .value_parser(clap::value_parser!(u64))
+                .requires("quartz_lat"),
+        )
+        .arg(
+            arg!(--quartz_model <PATH> "Where Quartz caches its bandwidth model on the remote")
+                .requires("quartz"),
+        )
+        .arg(
+            arg!(--quartz_mc_pci <PATH> "Where Quartz caches the memory controller PCI addresses on the remote")
+                .requires("quartz"),
+        )
+        .arg(
+            arg!(--quartz_debug <LEVEL> "Quartz's debug level. Default: 1")
+                .value_parser(clap::value_parser!(u64))
+                .requires("quartz"),
+        )
+        .arg(
+            arg!(--msr_throttle "Shorthand for --uncore 1=6:6 --uncore_interface msr")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--uncore <RATIOS>
+                "Limit the uncore frequency of each socket to <socket>=<min ratio>:<max ratio>, \
+                comma separated, in units of 100MHz. <min ratio>:<max ratio> alone applies to \
+                --uncore_sockets"),
+        )
+        .arg(
+            arg!(--uncore_bw <MBPS>
+                "Limit the uncore frequency to get close to MBPS of read bandwidth, using --bw_model")
+                .value_parser(clap::value_parser!(u64))
+                .requires("bw_model"),
+        )
+        .arg(
+            arg!(--bw_model <FILE>
+                "A local bandwidth model file made by calibrate_bw, for --uncore_bw, --mba_bw or \
+                --quartz, which uses it instead of measuring its own"),
+        )
+        .arg(
+            arg!(--uncore_sockets <SOCKETS>
+                "Comma separated sockets to throttle the uncore of with --uncore_bw or a single \
+                --uncore ratio. Default: 1"),
+        )
+        .arg(
+            arg!(--uncore_interface <INTERFACE> "How to set the uncore frequency")
+                .value_parser(["msr", "sysfs", "auto"])
+                .default_value("auto"),
+        )
+        .arg(
+            arg!(--mba <PERCENT>
+                "Use Intel RDT MBA to limit the memory bandwidth of the workload cores to PERCENT. \
+                Pass once for all workloads or once per workload.")
+                .value_parser(clap::value_parser!(u64).range(10..=100))
+                .action(ArgAction::Append),
+        )
+        .arg(
+            arg!(--mba_bw <MBPS>
+                "Use Intel RDT MBA to get close to MBPS of read bandwidth, using --bw_model")
+                .value_parser(clap::value_parser!(u64))
+                .requires("bw_model"),
         )
         .subcommand(
             clap::Command::new("merci")
@@ -300,6 +791,7 @@ pub fn cli_options() -> clap::Command {
                         .required(true)
                 )
         )
+        .subcommand(crate::characterize::cli_options())
 }
 
 fn get_remote_start_addr(ushell: &SshShell) -> Result<usize, ScailError> {
@@ -341,6 +833,10 @@ pub fn run(sub_m: &clap::ArgMatches) -> Result<(), failure::Error> {
         host: host.as_str(),
     };
 
+    if let Some(("characterize", sub_m)) = sub_m.subcommand() {
+        return crate::characterize::run(&login, sub_m);
+    }
+
     let perf_stat = sub_m.get_flag("perf_stat");
     let perf_counters = sub_m.get_many("perf_counter").map_or(
         Vec::new(),
@@ -353,7 +849,8 @@ pub fn run(sub_m: &clap::ArgMatches) -> Result<(), failure::Error> {
     let memlat = sub_m.get_flag("memlat");
     let parse_ratio = |r: &String| {
         let expect_msg =
-            "--bwmfs or --numactl should be of the format <local weight>:<remote weight>";
+            "--bwmfs, --numactl, --uncore, --quartz_lat and --optimize_ratio should be of the format \
+            <first>:<second>";
         let mut split = r.split(":");
         let local = split
             .next()
@@ -381,12 +878,128 @@ pub fn run(sub_m: &clap::ArgMatches) -> Result<(), failure::Error> {
     let mut kill_after_first_done = true;
     let cipp = sub_m.get_flag("cipp");
     let cipp_total_bw = sub_m.get_flag("cipp_total_bw");
+    let membind_node = sub_m.get_one::<usize>("membind").copied();
+    let preferred_node = sub_m.get_one::<usize>("preferred").copied();
+    let capacity_split_gb = sub_m.get_one::<usize>("capacity_split").copied();
+    let wkld_strategy_overrides = sub_m
+        .get_many::<String>("wkld_strategy")
+        .map_or(Ok(Vec::new()), |overrides| {
+            overrides.map(|o| parse_wkld_strategy(o)).collect::<Result<Vec<_>, _>>()
+        })?;
+    let schedule = sub_m
+        .get_many::<String>("schedule")
+        .map_or(Ok(Vec::new()), |actions| {
+            actions.map(|a| parse_scheduled_action(a)).collect::<Result<Vec<_>, _>>()
+        })?;
     let flame_graph = sub_m.get_flag("flame_graph");
-    let bwmon = sub_m.get_flag("bwmon");
-    let meminfo = sub_m.get_flag("meminfo");
+    let mut monitors = sub_m
+        .get_many::<String>("monitor")
+        .map_or(Ok(Vec::new()), |names| {
+            names.map(|n| MonitorKind::from_name(n)).collect::<Result<Vec<_>, _>>()
+        })?;
+    if memlat {
+        monitors.push(MonitorKind::Memlat);
+    }
+    if sub_m.get_flag("bwmon") {
+        monitors.push(MonitorKind::Bwmon);
+    }
+    if sub_m.get_flag("meminfo") {
+        monitors.push(MonitorKind::Meminfo);
+    }
+    if let Some(&period) = sub_m.get_one::<usize>("numa_maps") {
+        monitors.push(MonitorKind::NumaMaps { period });
+    }
+    if let Some(wklds) = sub_m.get_one::<String>("perf_mem") {
+        monitors.push(MonitorKind::PerfMem {
+            wklds: wklds.split(',').map(str::parse::<usize>).collect::<Result<_, _>>()?,
+        });
+    }
+    let no_default_monitors = sub_m.get_flag("no_default_monitors");
+    let retrieve = if sub_m.get_flag("no_retrieve") {
+        None
+    } else {
+        Some(RetrieveConfig {
+            local_dir: sub_m.get_one::<String>("local_results").unwrap().clone(),
+            compress_over_mb: sub_m.get_one::<u64>("compress_over").copied(),
+        })
+    };
     let time = sub_m.get_flag("time");
     let quartz_bw = sub_m.get_one::<u64>("quartz").copied();
+    let quartz_write_bw = sub_m.get_one::<u64>("quartz_write_bw").copied();
+    let quartz_lat = sub_m.get_one::<String>("quartz_lat").map(parse_ratio);
+    let quartz_epoch = *sub_m.get_one::<u64>("quartz_epoch").unwrap_or(&10000);
+    let quartz_model = sub_m.get_one::<String>("quartz_model").cloned();
+    let quartz_mc_pci = sub_m.get_one::<String>("quartz_mc_pci").cloned();
+    let quartz_debug = *sub_m.get_one::<u64>("quartz_debug").unwrap_or(&1);
     let msr_throttle = sub_m.get_flag("msr_throttle");
+    let uncore_bw = sub_m.get_one::<u64>("uncore_bw").copied();
+    let bw_model = sub_m.get_one::<String>("bw_model");
+    let uncore_sockets: Vec<usize> = sub_m
+        .get_one::<String>("uncore_sockets")
+        .map_or(Ok(vec![1]), |sockets| {
+            sockets.split(',').map(str::parse::<usize>).collect()
+        })?;
+    let uncore_limits = |min_ratio: u64, max_ratio: u64| -> Vec<UncoreLimit> {
+        uncore_sockets
+            .iter()
+            .map(|&socket| UncoreLimit {
+                socket,
+                min_ratio,
+                max_ratio,
+            })
+            .collect()
+    };
+    let uncore_ratios: Option<Vec<UncoreLimit>> = sub_m.get_one::<String>("uncore").map(|r| {
+        if !r.contains('=') {
+            let (min_ratio, max_ratio) = parse_ratio(r);
+            return uncore_limits(min_ratio as u64, max_ratio as u64);
+        }
+
+        r.split(',')
+            .map(|limit| {
+                let (socket, ratios) = limit
+                    .split_once('=')
+                    .expect("--uncore should be of the format <socket>=<min>:<max>,...");
+                let (min_ratio, max_ratio) = parse_ratio(&ratios.to_string());
+                UncoreLimit {
+                    socket: socket
+                        .parse()
+                        .expect("--uncore should be of the format <socket>=<min>:<max>,..."),
+                    min_ratio: min_ratio as u64,
+                    max_ratio: max_ratio as u64,
+                }
+            })
+            .collect()
+    });
+    let uncore_interface = match sub_m.get_one::<String>("uncore_interface").unwrap().as_str() {
+        "msr" => UncoreInterface::Msr,
+        "sysfs" => UncoreInterface::Sysfs,
+        _ => UncoreInterface::Auto,
+    };
+    let cgroups = if sub_m.get_flag("cgroup") {
+        Some(CgroupConfig {
+            mems: sub_m.get_one::<String>("cgroup_mems").cloned(),
+            memory_max_mb: sub_m.get_one::<u64>("cgroup_memory_max").copied(),
+            memory_high_mb: sub_m.get_one::<u64>("cgroup_memory_high").copied(),
+        })
+    } else {
+        None
+    };
+    let local_capacity = sub_m.get_one::<usize>("local_capacity").map(|&gb| LocalCapacity {
+        gb,
+        method: match sub_m.get_one::<String>("local_capacity_method").unwrap().as_str() {
+            "offline" => CapacityMethod::Offline,
+            "hog" => CapacityMethod::Hog,
+            _ => CapacityMethod::Hugetlb,
+        },
+    });
+    let optimize_range = sub_m.get_one::<String>("optimize_ratio").map(parse_ratio);
+    let optimize_tol = *sub_m.get_one::<usize>("optimize_tol").unwrap_or(&5);
+    let optimize_trials = *sub_m.get_one::<usize>("optimize_trials").unwrap_or(&3);
+    let mba_percents: Vec<u64> = sub_m
+        .get_many::<u64>("mba")
+        .map_or(Vec::new(), |percents| percents.copied().collect());
+    let mba_bw = sub_m.get_one::<u64>("mba_bw").copied();
 
     let workloads = match sub_m.subcommand() {
         Some(("merci", sub_m)) => {
@@ -511,14 +1124,81 @@ pub fn run(sub_m: &clap::ArgMatches) -> Result<(), failure::Error> {
         Strategy::Numactl { local, remote }
     } else if cipp {
         Strategy::Cipp { total_bw: cipp_total_bw }
+    } else if let Some(node) = membind_node {
+        Strategy::Membind { node }
+    } else if let Some(node) = preferred_node {
+        Strategy::Preferred { node }
+    } else if let Some(local_gb) = capacity_split_gb {
+        Strategy::CapacitySplit { local_gb }
     } else {
         Strategy::Linux
     };
 
+    let mut wkld_strategies: Vec<Option<Strategy>> = vec![None; workloads.len()];
+    for (i, strategy) in wkld_strategy_overrides {
+        if i >= workloads.len() {
+            return Err(failure::format_err!(
+                "--wkld_strategy index {} is out of range for {} workloads",
+                i,
+                workloads.len()
+            ));
+        }
+        wkld_strategies[i] = Some(strategy);
+    }
+
     let throttle = if let Some(bw) = quartz_bw {
-        ThrottleType::Quartz { bw }
+        ThrottleType::Quartz(QuartzConfig {
+            read_bw: bw,
+            write_bw: quartz_write_bw.unwrap_or(bw),
+            latency: quartz_lat.map(|(read, write)| QuartzLatency {
+                read_ns: read as u64,
+                write_ns: write as u64,
+                epoch_us: quartz_epoch,
+            }),
+            model_file: quartz_model,
+            mc_pci_file: quartz_mc_pci,
+            local_model: bw_model.cloned(),
+            debug_level: quartz_debug,
+        })
     } else if msr_throttle {
-        ThrottleType::Msr
+        ThrottleType::msr()
+    } else if let Some(limits) = uncore_ratios {
+        ThrottleType::Uncore {
+            limits,
+            target_bw: None,
+            interface: uncore_interface,
+        }
+    } else if let Some(bw) = uncore_bw {
+        let model = BwModel::load(bw_model.unwrap())?;
+        model.check_knob("uncore")?;
+        let ratio = model.setting_for(bw as f64);
+        println!("Using uncore ratio {} for {} MB/s", ratio, bw);
+
+        ThrottleType::Uncore {
+            limits: uncore_limits(ratio, ratio),
+            target_bw: Some(bw),
+            interface: uncore_interface,
+        }
+    } else if mba_percents.len() != 0 {
+        if mba_percents.len() != 1 && mba_percents.len() != workloads.len() {
+            panic!(
+                "Must have either one MBA percentage or one for each workload ({})",
+                workloads.len()
+            );
+        }
+
+        ThrottleType::Mba {
+            percents: mba_percents,
+        }
+    } else if let Some(bw) = mba_bw {
+        let model = BwModel::load(bw_model.unwrap())?;
+        model.check_knob("mba")?;
+        let percent = model.setting_for(bw as f64);
+        println!("Using MBA {}% for {} MB/s", percent, bw);
+
+        ThrottleType::Mba {
+            percents: vec![percent],
+        }
     } else {
         ThrottleType::Native
     };
@@ -527,27 +1207,164 @@ pub fn run(sub_m: &clap::ArgMatches) -> Result<(), failure::Error> {
         exp: "cipp_exp".into(),
         workloads,
         strategy,
+        wkld_strategies,
         kill_after_first_done,
         perf_stat,
         perf_counters,
         disable_thp,
         disable_aslr,
         flame_graph,
-        bwmon,
-        meminfo,
-        memlat,
+        monitors,
+        no_default_monitors,
         time,
         throttle,
+        cgroups,
+        local_capacity,
+        schedule,
+        retrieve,
         timestamp: Timestamp::now(),
     };
 
+    if let Some((lo, hi)) = optimize_range {
+        return optimize_interleave_ratio(&login, cfg, lo, hi, optimize_tol, optimize_trials);
+    }
+
     run_inner(&login, &cfg)
 }
 
+/// Search for the numactl weighted interleave ratio that gives the first workload
+/// the best performance, running the whole experiment for every measurement.
+fn optimize_interleave_ratio<A>(
+    login: &Login<A>,
+    mut cfg: Config,
+    lo: usize,
+    hi: usize,
+    tol: usize,
+    trials: usize,
+) -> Result<(), failure::Error>
+where
+    A: std::net::ToSocketAddrs + std::fmt::Display + std::fmt::Debug + Clone,
+{
+    if hi > 100 {
+        return Err(failure::format_err!(
+            "--optimize_ratio is a local percentage, so it can be at most 100"
+        ));
+    }
+
+    // Workloads whose output we don't parse are measured by their runtime
+    cfg.time = true;
+    // The search minimizes, so negate metrics where higher is better
+    let sign = if cfg.workloads[0].higher_is_better() { -1.0 } else { 1.0 };
+    let summary_file_name = cfg.gen_file_name("optimize_ratio");
+
+    let result = golden_section_search(lo, hi, tol, trials, |local| {
+        cfg.strategy = Strategy::Numactl {
+            local,
+            remote: 100 - local,
+        };
+        cfg.timestamp = Timestamp::now();
+        run_inner(login, &cfg)?;
+
+        let ushell = SshShell::with_any_key(login.username, &login.host)?;
+        Ok(sign * wkld_metric(&ushell, &cfg)?)
+    })?;
+
+    let ushell = SshShell::with_any_key(login.username, &login.host)?;
+    let user_home = get_user_home_dir(&ushell)?;
+    let summary_file = dir!(&user_home, crate::RESULTS_PATH, summary_file_name);
+
+    ushell.run(cmd!(
+        "echo 'Local Ratio,Trials,Mean,95% CI' > {}",
+        summary_file
+    ))?;
+    for e in &result.evaluations {
+        ushell.run(cmd!(
+            "echo '{},{},{:.3},{:.3}' >> {}",
+            e.ratio,
+            e.samples.len(),
+            sign * e.mean(),
+            e.ci95(),
+            summary_file
+        ))?;
+    }
+
+    // What measuring every TOL-th ratio as many times would have cost
+    let sweep_runs = ((hi - lo).div_ceil(tol.max(1)) + 1) * trials;
+    println!(
+        "OPTIMAL RATIO: {}:{} ({:.3} +/- {:.3}), comparable from {} to {}, in {}..{}",
+        result.best.ratio,
+        100 - result.best.ratio,
+        sign * result.best.mean(),
+        result.best.ci95(),
+        result.bounds.0,
+        result.bounds.1,
+        result.bracket.0,
+        result.bracket.1,
+    );
+    println!(
+        "OPTIMIZE: {} runs, {} fewer than the {} of sweeping {}:{} every {}",
+        result.runs,
+        sweep_runs.saturating_sub(result.runs),
+        sweep_runs,
+        lo,
+        hi,
+        tol.max(1)
+    );
+    println!("RESULTS: {}", summary_file);
+
+    Ok(())
+}
+
+/// The performance of the first workload of the finished run described by `cfg`:
+/// what the workload reports if we know how to parse it, otherwise its runtime.
+fn wkld_metric(ushell: &SshShell, cfg: &Config) -> Result<f64, failure::Error> {
+    let results_dir = dir!(get_user_home_dir(ushell)?, crate::RESULTS_PATH);
+    let wkld = &cfg.workloads[0];
+
+    let out = match wkld {
+        Workload::CloverLeaf { .. } => ushell.run(cmd!(
+            "grep 'Wall clock' {} | tail -n1 | grep -oE '[0-9]+\\.[0-9]+'",
+            dir!(&results_dir, cfg.gen_file_name("clover"))
+        ))?,
+        Workload::GapbsTc { .. } | Workload::GapbsPr { .. } => ushell.run(cmd!(
+            "grep 'Average Time' {} | grep -oE '[0-9]+\\.[0-9]+'",
+            dir!(&results_dir, cfg.gen_file_name("gapbs"))
+        ))?,
+        Workload::Stream => ushell.run(cmd!(
+            "grep 'Triad:' {} | awk '{{ print $2 }}'",
+            dir!(&results_dir, cfg.gen_file_name("stream"))
+        ))?,
+        _ => {
+            // GNU time reports the elapsed time as [h:]m:s.cs
+            let elapsed = ushell
+                .run(cmd!(
+                    "grep -oE '[0-9:.]+elapsed' {}.{}",
+                    dir!(&results_dir, cfg.gen_file_name("time")),
+                    wkld.proc_name()
+                ))?
+                .stdout;
+            let elapsed = elapsed.trim().trim_end_matches("elapsed");
+
+            return elapsed.split(':').try_fold(0.0, |secs, field| {
+                Ok(secs * 60.0 + field.parse::<f64>()?)
+            });
+        }
+    };
+
+    out.stdout
+        .trim()
+        .parse::<f64>()
+        .map_err(|_| failure::format_err!("Could not parse the {:?} output: {}", wkld, out.stdout))
+}
+
 fn run_inner<A>(login: &Login<A>, cfg: &Config) -> Result<(), failure::Error>
 where
     A: std::net::ToSocketAddrs + std::fmt::Display + std::fmt::Debug + Clone,
 {
+    let wkld_strategies = cfg.wkld_strategies()?;
+    cfg.check_schedule(&wkld_strategies)?;
+    let monitor_kinds = cfg.monitors(&wkld_strategies)?;
+
     let ushell = SshShell::with_any_key(login.username, &login.host)?;
     let user_home = get_user_home_dir(&ushell)?;
 
@@ -558,9 +1375,7 @@ where
     let perf_record_file = "/tmp/perf.data";
     let perf_stat_file = dir!(&results_dir, cfg.gen_file_name("perf_stat"));
     let flame_graph_file = dir!(&results_dir, cfg.gen_file_name("flamegraph.svg"));
-    let colloid_lat_file = dir!(&results_dir, cfg.gen_file_name("colloid.lat"));
     let cipp_file = dir!(&results_dir, cfg.gen_file_name("cipp"));
-    let bwmon_file = dir!(&results_dir, cfg.gen_file_name("bwmon"));
     let merci_file = dir!(&results_dir, cfg.gen_file_name("merci"));
     let gapbs_file = dir!(&results_dir, cfg.gen_file_name("gapbs"));
     let gups_file = dir!(&results_dir, cfg.gen_file_name("gups"));
@@ -568,11 +1383,45 @@ where
     let ycsb_file = dir!(&results_dir, cfg.gen_file_name("ycsb"));
     let stream_file = dir!(&results_dir, cfg.gen_file_name("stream"));
     let spec_file = dir!(&results_dir, cfg.gen_file_name("spec"));
-    let vmstat_file = dir!(&results_dir, cfg.gen_file_name("vmstat"));
-    let pgmigrate_file = dir!(&results_dir, cfg.gen_file_name("pgmigrate"));
     let damo_status_file = dir!(&results_dir, cfg.gen_file_name("damo_status"));
-    let meminfo_file_stub = dir!(&results_dir, cfg.gen_file_name("meminfo"));
+    let schedule_file = dir!(&results_dir, cfg.gen_file_name("schedule"));
+    let mba_file = dir!(&results_dir, cfg.gen_file_name("mba"));
+    let uncore_file = dir!(&results_dir, cfg.gen_file_name("uncore"));
+    let capacity_file = dir!(&results_dir, cfg.gen_file_name("capacity"));
+    let cgroup_file = dir!(&results_dir, cfg.gen_file_name("cgroup"));
     let time_file_stub = dir!(&results_dir, cfg.gen_file_name("time"));
+    let events_file = dir!(&results_dir, cfg.gen_file_name("events.jsonl"));
+    let manifest_file = dir!(&results_dir, cfg.gen_file_name("manifest.json"));
+
+    let artifact = |path: &str, role: &str| Artifact {
+        path: path.into(),
+        role: role.into(),
+        local_path: None,
+        sha256: None,
+        compressed: false,
+    };
+    // Only the ones that exist at the end make it into the manifest
+    let mut artifacts = vec![
+        artifact(&dir!(&results_dir, &params_file), "config"),
+        artifact(&events_file, "timeline"),
+        artifact(&perf_stat_file, "perf_stat"),
+        artifact(&flame_graph_file, "flame_graph"),
+        artifact(perf_record_file, "perf_record"),
+        artifact(&cipp_file, "cipp"),
+        artifact(&merci_file, "workload"),
+        artifact(&gapbs_file, "workload"),
+        artifact(&gups_file, "workload"),
+        artifact(&clover_file, "workload"),
+        artifact(&ycsb_file, "workload"),
+        artifact(&stream_file, "workload"),
+        artifact(&spec_file, "workload"),
+        artifact(&damo_status_file, "damo_status"),
+        artifact(&schedule_file, "schedule"),
+        artifact(&mba_file, "throttle"),
+        artifact(&uncore_file, "throttle"),
+        artifact(&capacity_file, "capacity"),
+        artifact(&cgroup_file, "cgroup"),
+    ];
 
     let colloid_dir = dir!(&user_home, crate::KERNEL_PATH);
     let tools_dir = dir!(&user_home, crate::WKSPC_PATH, "tools/");
@@ -608,8 +1457,6 @@ where
     let num_threads = tctx.num_threads_on_socket(0);
     let max_cores_per_wkld = num_threads / cfg.workloads.len();
 
-    let mut bgctx = BackgroundContext::new(&ushell);
-
     ushell.run(cmd!("mkdir -p {}", results_dir))?;
     ushell.run(cmd!(
         "echo {} > {}",
@@ -617,8 +1464,12 @@ where
         dir!(&results_dir, params_file)
     ))?;
 
+    let timeline = Timeline::start(&ushell, &events_file)?;
+
     // For now, always initially pin memory to local NUMA node
     let mut cmd_prefixes: Vec<String> = vec![String::new(); cfg.workloads.len()];
+    // The libraries to preload into each workload, with the variables they need
+    let mut preloads: Vec<Vec<(String, String)>> = vec![Vec::new(); cfg.workloads.len()];
 
     // Determine how many threads/cores each workload should have
     let cores_per_wkld: Vec<usize> = cfg
@@ -687,21 +1538,7 @@ where
     // the remote NUMA for monitoring processes.
     let remote_core = tctx.next().unwrap();
 
-    let proc_names: Vec<&str> = cfg
-        .workloads
-        .iter()
-        .map(|&wkld| match wkld {
-            Workload::Merci { .. } => "eval_baseline",
-            Workload::GapbsTc { .. } => "tc",
-            Workload::GapbsPr { .. } => "pr",
-            Workload::Gups { .. } => "gups-hotset-mov",
-            Workload::CloverLeaf { .. } => "omp-cloverleaf",
-            Workload::Redis { .. } => "redis-server",
-            Workload::Stream => "stream",
-            Workload::SpecBwaves { .. } => "speed_bwaves_ba",
-            Workload::SpecLbm { .. } => "lbm_s_base.mark",
-        })
-        .collect();
+    let proc_names: Vec<&str> = cfg.workloads.iter().map(Workload::proc_name).collect();
 
     let (
         transparent_hugepage_enabled,
@@ -731,106 +1568,119 @@ where
         "echo performance | sudo tee /sys/devices/system/cpu/cpu*/cpufreq/scaling_governor"
     ))?;
 
-    match cfg.throttle {
-        ThrottleType::Quartz { bw } => {
-            let quartz_build_dir = dir!(&quartz_dir, "build");
-            let nvmemul_ini = dir!(&quartz_dir, "nvmemul.ini");
-            let tmp_nvmemul_ini = "/tmp/nvmemul.ini";
-            let quartz_lib_path = format!("{}/build/src/lib/libnvmemul.so", &quartz_dir);
-            let quartz_envs = format!(
-                "LD_PRELOAD={} NVMEMUL_INI={} ",
-                &quartz_lib_path, tmp_nvmemul_ini
-            );
-
-            // Make sure Quartz is built.
-            // We can't do this in setup_wkspc because it requires the kernel being installed
-            ushell.run(cmd!("make all").cwd(&quartz_build_dir))?;
-
-            // Need to escaped the slashes for sed
-            let escaped_user_home = user_home.replace("/", "\\/");
-
-            ushell.run(cmd!("cp {} {}", nvmemul_ini, tmp_nvmemul_ini))?;
-            ushell.run(cmd!(
-                "sed -i 's/read = .*/read = {}/' {}",
-                bw,
-                &tmp_nvmemul_ini
-            ))?;
-            ushell.run(cmd!(
-                "sed -i 's/write = .*/write = {}/' {}",
-                bw,
-                &tmp_nvmemul_ini
-            ))?;
-            // Quartz caches the throttle register to bandwidth map and register
-            // addresses in the below files, so put them somewhere they will persist
-            // between reboots
-            ushell.run(cmd!(
-                "sed -i 's/model = .*/model = \\\"{}\\/bandwidth_model\\\"/' {}",
-                &escaped_user_home,
-                &tmp_nvmemul_ini
-            ))?;
-            ushell.run(cmd!(
-                "sed -i 's/mc_pci = .*/mc_pci = \\\"{}\\/mc_pci_bus\\\"/' {}",
-                &escaped_user_home,
-                &tmp_nvmemul_ini
-            ))?;
-
-            // Log that we set up the ini file correctly
-            ushell.run(cmd!("cat {}", &tmp_nvmemul_ini))?;
-
-            // Load the kernel module
-            ushell.run(cmd!("sudo {}/scripts/setupdev.sh load", &quartz_dir))?;
-            // Gotta do some permission stuff
-            ushell.run(cmd!("echo 2 | sudo tee /sys/devices/cpu/rdpmc"))?;
-
-            // Have to prerun Quartz twice to make sure the register and bandwidth
-            // map files are populated
-            ushell.run(cmd!("{} sleep 1", quartz_envs))?;
-            ushell.run(cmd!("{} sleep 1", quartz_envs))?;
-
-            // Throttle bandwidth with quartz for 2 hours
-            // This should be enough for our workloads.
-            ushell.spawn(cmd!("{} sleep 7200", quartz_envs))?;
-        }
-        ThrottleType::Msr => {
-            // TODO: This is specific to c220g2. Make it generic
-            with_shell! { ushell =>
-                cmd!("sudo modprobe msr"),
-                //cmd!("sudo wrmsr -p 0 0x620 0x1e1e"),
-                cmd!("sudo wrmsr -p 10 0x620 0x606"),
+    let mut uncore_throttle = None;
+    let mut quartz_throttle = None;
+    let mut mba_throttle = None;
+    match &cfg.throttle {
+        ThrottleType::Quartz(quartz_cfg) => {
+            quartz_throttle = Some(start_quartz(&ushell, &quartz_dir, &user_home, quartz_cfg)?);
+
+            // Latency is only emulated for processes Quartz is loaded into
+            if quartz_cfg.latency.is_some() {
+                for preload in &mut preloads {
+                    preload.push(quartz_preload(&quartz_dir));
+                }
             }
         }
+        ThrottleType::Uncore { limits, interface, .. } => {
+            uncore_throttle = Some(set_uncore_ratio(&ushell, limits, *interface, &uncore_file)?);
+        }
+        ThrottleType::Mba { percents } => {
+            // With one percentage, all of the workloads share a class of service
+            let groups: Vec<(u64, String)> = if percents.len() == 1 {
+                vec![(percents[0], pin_cores_strs.join(","))]
+            } else {
+                percents
+                    .iter()
+                    .copied()
+                    .zip(pin_cores_strs.iter().cloned())
+                    .collect()
+            };
+
+            mba_throttle = Some(set_mba(&ushell, &groups, &mba_file)?);
+        }
         ThrottleType::Native => (),
     }
+    if !matches!(cfg.throttle, ThrottleType::Native) {
+        timeline.record(
+            &ushell,
+            "throttle_start",
+            serde_json::json!({ "throttle": &cfg.throttle }),
+        )?;
+    }
+
+    let capacity_limit = match &cfg.local_capacity {
+        Some(cap) => limit_local_capacity(&ushell, cap, &tools_dir, &capacity_file)?,
+        None => None,
+    };
+    if let Some(cap) = &cfg.local_capacity {
+        timeline.record(
+            &ushell,
+            "local_capacity_limit",
+            serde_json::json!({ "local_capacity": cap }),
+        )?;
+    }
 
     if cfg.time {
         for (i, name) in proc_names.iter().enumerate() {
             let time_file = format!("{}.{}", time_file_stub, name);
+            artifacts.push(artifact(&time_file, "time"));
             // Have to use full path because "time" is also a shell
             // command, which takes priority
             cmd_prefixes[i].push_str(&format!("/usr/bin/time -o {} ", time_file));
         }
     }
 
-    // Record memory access latencies as the workload runs
-    if cfg.memlat {
-        ushell.run(cmd!("make").cwd(dir!(&colloid_dir, "colloid-perf")))?;
-        let remote_mem_pfn_start = remote_mem_start / 4096;
-        ushell.run(cmd!("sudo insmod {}/colloid-perf/colloid-perf.ko", colloid_dir))?;
-        ushell.spawn(cmd!("sudo taskset -c {} {}/memlat {} 10 {}", remote_core, &tools_dir,
-            remote_mem_pfn_start, &colloid_lat_file))?;
-    } else {
-        ushell.run(cmd!("make").cwd(dir!(&colloid_dir, "colloid-mon")))?;
-        ushell.run(cmd!("sudo insmod {}/colloid-mon/colloid-mon.ko", colloid_dir))?;
-
-        bgctx.spawn(BackgroundTask {
-            name: "colloid_latency",
-            period: 1, // Seconds
-            cmd: format!("cat /sys/kernel/colloid/latency >> {}", &colloid_lat_file),
-            ensure_started: colloid_lat_file,
-        })?;
+    let mut cgroups = None;
+    if let Some(cgroup_cfg) = &cfg.cgroups {
+        let groups = setup_cgroups(&ushell, cgroup_cfg, &pin_cores_strs, &cgroup_file)?;
+        for i in 0..groups.paths().len() {
+            // Everything else in the prefix should run inside the group too
+            cmd_prefixes[i].insert_str(0, &cgroup_exec_prefix(i));
+        }
+        cgroups = Some(groups);
     }
 
-    // Use whatever tiering strategy specified
+    // The monitors are set up before the strategies since Colloid's tiering
+    // needs the latency from colloid-mon
+    let results_file = |suffix: &str| dir!(&results_dir, cfg.gen_file_name(suffix));
+    let targets: Vec<Option<PlacementTarget>> = wkld_strategies
+        .iter()
+        .map(|s| match s {
+            Strategy::Numactl { local, remote } => Some(PlacementTarget::Ratio(*local, *remote)),
+            Strategy::Bwmfs { ratios } => Some(PlacementTarget::Ratio(ratios[0].0, ratios[0].1)),
+            Strategy::CapacitySplit { local_gb } => Some(PlacementTarget::LocalGb(*local_gb)),
+            _ => None,
+        })
+        .collect();
+    let mut monitors = MonitorSet::new(
+        &ushell,
+        &monitor_kinds,
+        MonitorEnv {
+            tools_dir: &tools_dir,
+            colloid_dir: &colloid_dir,
+            results_file: &results_file,
+            proc_names: &proc_names,
+            remote_core,
+            remote_mem_start,
+            damo_dir: &damo_dir,
+            damon_in_use: wkld_strategies
+                .iter()
+                .any(|s| matches!(s, Strategy::Cipp { .. })),
+            cgroups: cgroups.as_ref().map_or(&[], |c| c.paths()),
+            targets: &targets,
+            timeline: &timeline,
+        },
+    );
+    monitors.setup()?;
+    timeline.record(
+        &ushell,
+        "monitors_setup",
+        serde_json::json!({ "monitors": &monitor_kinds }),
+    )?;
+
+    // Tpp and Colloid change how the kernel tiers memory for the whole system,
+    // so they can only be used as the global strategy
     match &cfg.strategy {
         Strategy::Tpp => {
             ushell.run(cmd!("make").cwd(dir!(&colloid_dir, "tierinit")))?;
@@ -852,14 +1702,70 @@ where
                 cmd!("echo 6 | sudo tee /proc/sys/kernel/numa_balancing"),
             }
         }
-        Strategy::Bwmfs { ratios } => {
-            let bandwidthmfs_dir = dir!(kernel_dir, "BandwidthMMFS");
+        _ => (),
+    }
+
+    // Each workload using BWMFS gets its own mount with its own ratio
+    if wkld_strategies
+        .iter()
+        .any(|s| matches!(s, Strategy::Bwmfs { .. }))
+    {
+        let bandwidthmfs_dir = dir!(kernel_dir, "BandwidthMMFS");
 
-            ushell.run(cmd!("make").cwd(&bandwidthmfs_dir))?;
-            ushell.run(cmd!("sudo insmod {}/bandwidth.ko", &bandwidthmfs_dir))?;
-            ushell.run(cmd!("echo 1 | sudo tee /sys/kernel/mm/fbmm/state"))?;
+        ushell.run(cmd!("make").cwd(&bandwidthmfs_dir))?;
+        ushell.run(cmd!("sudo insmod {}/bandwidth.ko", &bandwidthmfs_dir))?;
+        ushell.run(cmd!("echo 1 | sudo tee /sys/kernel/mm/fbmm/state"))?;
+    }
 
-            for (i, (local, remote)) in ratios.iter().enumerate() {
+    // The weighted interleave weights are global, so set them once for all
+    // of the workloads using them.
+    if let Some((local, remote)) = interleave_weights(&wkld_strategies) {
+        ushell.run(cmd!(
+            "echo {} | sudo tee /sys/kernel/mm/mempolicy/weighted_interleave/node0",
+            local,
+        ))?;
+        ushell.run(cmd!(
+            "echo {} | sudo tee /sys/kernel/mm/mempolicy/weighted_interleave/node1",
+            remote,
+        ))?;
+    }
+
+    // Only one CIPP controller runs, managing every workload that uses it
+    let cipp_exe = wkld_strategies.iter().find_map(|s| match s {
+        Strategy::Cipp { total_bw: true } => Some("cipp_total_bw"),
+        Strategy::Cipp { total_bw: false } => Some("cipp"),
+        _ => None,
+    });
+    if let Some(cipp_exe) = cipp_exe {
+        let damo_yaml_file = dir!(&user_home, "cipp.yaml");
+
+        ushell.run(cmd!(
+            "sudo {}/gen_interleave.py -o {} -a {}",
+            &damo_dir,
+            &damo_yaml_file,
+            remote_mem_start,
+        ))?;
+        ushell.run(cmd!("sudo {}/damo start {}", &damo_dir, &damo_yaml_file))?;
+        ushell.run(cmd!("sudo taskset -cp {} $(pgrep kdamond)", &all_cores_str))?;
+
+        ushell.run(cmd!("echo 0 | sudo tee /proc/sys/kernel/numa_balancing"))?;
+
+        if !cfg.cipp_starts_scheduled() {
+            ushell.spawn(cmd!(
+                "sudo {}/{} 100 6000 30000 > {}",
+                &tools_dir,
+                cipp_exe,
+                &cipp_file
+            ))?;
+            timeline.record(&ushell, "cipp_start", serde_json::json!({ "exe": cipp_exe }))?;
+        }
+    }
+
+    for (i, strategy) in wkld_strategies.iter().enumerate() {
+        match strategy {
+            Strategy::Tpp | Strategy::Colloid => (),
+            Strategy::Bwmfs { ratios } => {
+                let (local, remote) = ratios[0];
                 let mount_dir = dir!(&user_home, format!("bwmfs{}", i + 1));
 
                 ushell.run(cmd!("mkdir -p {}", mount_dir))?;
@@ -882,61 +1788,22 @@ where
 
                 cmd_prefixes[i].push_str(&format!("{}/fbmm_wrapper {} ", &tools_dir, mount_dir));
             }
-        }
-        Strategy::Numactl { local, remote } => {
-            ushell.run(cmd!(
-                "echo {} | sudo tee /sys/kernel/mm/mempolicy/weighted_interleave/node0",
-                local,
-            ))?;
-            ushell.run(cmd!(
-                "echo {} | sudo tee /sys/kernel/mm/mempolicy/weighted_interleave/node1",
-                remote,
-            ))?;
-
-            for prefix in &mut cmd_prefixes {
-                prefix.push_str(&format!("{}/numactl -w 0,1 ", &numactl_dir));
+            Strategy::Numactl { .. } | Strategy::Cipp { .. } => {
+                cmd_prefixes[i].push_str(&format!("{}/numactl -w 0,1 ", &numactl_dir));
             }
-        }
-        Strategy::Cipp { total_bw } => {
-            let damo_yaml_file = dir!(&user_home, "cipp.yaml");
-            let cipp_exe = if *total_bw { "cipp_total_bw" } else { "cipp" };
-
-            ushell.run(cmd!(
-                "sudo {}/gen_interleave.py -o {} -a {}",
-                &damo_dir,
-                &damo_yaml_file,
-                remote_mem_start,
-            ))?;
-            ushell.run(cmd!("sudo {}/damo start {}", &damo_dir, &damo_yaml_file))?;
-            ushell.run(cmd!("sudo taskset -cp {} $(pgrep kdamond)", &all_cores_str))?;
-
-            ushell.run(cmd!(
-                "echo 100 | sudo tee /sys/kernel/mm/mempolicy/weighted_interleave/node0"
-            ))?;
-            ushell.run(cmd!(
-                "echo 0 | sudo tee /sys/kernel/mm/mempolicy/weighted_interleave/node1"
-            ))?;
-
-            ushell.run(cmd!("echo 0 | sudo tee /proc/sys/kernel/numa_balancing"))?;
-
-            ushell.spawn(cmd!(
-                "sudo {}/{} 100 6000 30000 > {}",
-                &tools_dir,
-                cipp_exe,
-                &cipp_file
-            ))?;
-
-            for prefix in &mut cmd_prefixes {
-                prefix.push_str(&format!("{}/numactl -w 0,1 ", &numactl_dir));
+            Strategy::Linux | Strategy::Membind { .. } | Strategy::Preferred { .. } => {
+                cmd_prefixes[i].push_str(&static_placement_prefix(strategy));
             }
-        }
-        Strategy::Linux => {
-            for prefix in &mut cmd_prefixes {
-                prefix.push_str("numactl --preferred=0 ");
+            Strategy::CapacitySplit { local_gb } => {
+                preloads[i].push(capsplit_preload(*local_gb, &tools_dir));
             }
         }
     }
 
+    for (i, preload) in preloads.iter().enumerate() {
+        cmd_prefixes[i].push_str(&preload_prefix(preload));
+    }
+
     for (i, cores_str) in pin_cores_strs.iter().enumerate() {
         // The Redis code in libscail does its own pinning, so ignore it here
         if matches!(&cfg.workloads[i], Workload::Redis { .. }) {
@@ -945,23 +1812,6 @@ where
         cmd_prefixes[i].push_str(&format!("taskset -c {} ", cores_str));
     }
 
-    if cfg.meminfo {
-        for name in &proc_names {
-            let meminfo_file = format!("{}.{}", meminfo_file_stub, name);
-            bgctx.spawn(BackgroundTask {
-                name: "meminfo",
-                period: 5, // Seconds
-                // TODO: Below is currently hardcoded local memory range for c220g2.
-                // We should make this more general
-                cmd: format!(
-                    "sudo {}/meminfo $(pgrep -x {} | sort -n | head -n1) 0x100000000 0x1480000000 >> {}",
-                    tools_dir, name, &meminfo_file
-                ),
-                ensure_started: meminfo_file,
-            })?;
-        }
-    }
-
     if cfg.perf_stat {
         // TODO: Have this be per workload, like meminfo
         cmd_prefixes[0].push_str(&gen_perf_command_prefix(
@@ -978,14 +1828,26 @@ where
         ));
     }
 
-    // Keep track of how many pages are migrated
-    bgctx.spawn(BackgroundTask {
-        name: "pgmigrate",
-        period: 1, // Seconds
-        cmd: format!("cat /proc/vmstat | grep \"\\(pgmigrate_success\\|pgdemote\\)\" >> {}", &pgmigrate_file),
-        ensure_started: pgmigrate_file,
-    })?;
+    timeline.record(
+        &ushell,
+        "strategy_setup",
+        serde_json::json!({ "strategies": &wkld_strategies }),
+    )?;
+
+    monitors.start()?;
 
+    let ycsb_loads = cfg.workloads.iter().any(|w| {
+        matches!(
+            w,
+            Workload::Redis {
+                load_before_wklds: true,
+                ..
+            }
+        )
+    });
+    if ycsb_loads {
+        timeline.record(&ushell, "ycsb_load_start", serde_json::Value::Null)?;
+    }
     // For YCSB workloads, we should start and load data onto the servers
     // before running the workloads, since that will take several minutes
     let mut ycsb_sessions: Vec<Option<YcsbSession<fn(&SshShell) -> Result<(), ScailError>>>> = cfg
@@ -1035,122 +1897,210 @@ where
             _ => None,
         })
         .collect();
+    if ycsb_loads {
+        timeline.record(&ushell, "ycsb_load_end", serde_json::Value::Null)?;
+    }
+
+    let schedule_cmds: Vec<ScheduledCmd> = cfg
+        .schedule
+        .iter()
+        .map(|a| {
+            let cmd = match a.action {
+                ScheduleAction::InterleaveWeights { local, remote } => format!(
+                    "echo {} | sudo tee /sys/kernel/mm/mempolicy/weighted_interleave/node0 && \
+                     echo {} | sudo tee /sys/kernel/mm/mempolicy/weighted_interleave/node1",
+                    local, remote
+                ),
+                ScheduleAction::BwmfsWeights { mount, local, remote } => format!(
+                    "echo {} | sudo tee /sys/fs/bwmmfs{}/node0/weight && \
+                     echo {} | sudo tee /sys/fs/bwmmfs{}/node1/weight",
+                    local, mount, remote, mount
+                ),
+                ScheduleAction::NumaBalancing { mode } => {
+                    format!("echo {} | sudo tee /proc/sys/kernel/numa_balancing", mode)
+                }
+                // Append so we don't lose the output from before a stop
+                ScheduleAction::StartCipp => format!(
+                    "sudo {}/{} 100 6000 30000 >> {}",
+                    &tools_dir,
+                    cipp_exe.unwrap(),
+                    &cipp_file
+                ),
+                ScheduleAction::StopCipp => format!("sudo pkill -x {}", cipp_exe.unwrap()),
+            };
+
+            ScheduledCmd {
+                at: std::time::Duration::from_secs(a.at_secs),
+                desc: format!("{:?}", a.action),
+                cmd,
+                background: matches!(a.action, ScheduleAction::StartCipp),
+            }
+        })
+        .collect();
+    let scheduler = if schedule_cmds.is_empty() {
+        None
+    } else {
+        Some(Scheduler::start(
+            login.username,
+            &login.host.to_string(),
+            schedule_cmds,
+            schedule_file,
+            timeline.clone(),
+        )?)
+    };
 
     let handles: Vec<_> = cfg
         .workloads
         .iter()
         .enumerate()
-        .map(|(i, &wkld)| match wkld {
-            Workload::Merci { runs, cores, delay } => {
-                let cores = cores.unwrap_or(max_cores_per_wkld);
-                run_merci(
-                    &ushell,
-                    &merci_dir,
-                    runs,
-                    cores,
-                    delay,
-                    &cmd_prefixes[i],
-                    &merci_file,
-                )
-            }
-            Workload::GapbsTc { runs } => {
-                run_gapbs_tc(&ushell, &gapbs_dir, runs, &cmd_prefixes[i], &gapbs_file)
-            }
-            Workload::GapbsPr { runs } => {
-                run_gapbs_pr(&ushell, &gapbs_dir, runs, &cmd_prefixes[i], &gapbs_file)
-            }
-            Workload::Gups { threads, exp, hot_exp, num_updates } => {
-                run_gups(
-                    &ushell,
-                    &gups_dir,
-                    threads,
-                    exp,
-                    hot_exp,
-                    num_updates,
-                    &cmd_prefixes[i],
-                    &gups_file,
-                )
-            }
-            Workload::CloverLeaf { delay, .. } => {
-                run_clover(
-                    &ushell,
-                    &clover_dir,
-                    delay,
-                    &cmd_prefixes[i],
-                    &clover_file,
-                )
-            }
-            Workload::Redis { load_before_wklds, .. } => {
-                match &mut ycsb_sessions[i] {
-                    Some(ycsb) => {
-                        if !load_before_wklds {
-                            ushell.run(cmd!("sleep 60"))?;
-                            ycsb.start_and_load(&ushell)?;
-                        }
-
-                        Ok(ycsb.run_handle(&ushell)?)
-                    },
-                    None => Err(ScailError::InvalidValueError { msg: "YCSB Session does not exist for Reids".to_string() }.into()),
+        .map(|(i, &wkld)| {
+            let handle = match wkld {
+                Workload::Merci { runs, cores, delay } => {
+                    let cores = cores.unwrap_or(max_cores_per_wkld);
+                    run_merci(
+                        &ushell,
+                        &merci_dir,
+                        runs,
+                        cores,
+                        delay,
+                        &cmd_prefixes[i],
+                        &merci_file,
+                    )
                 }
-            }
-            Workload::Stream => {
-                run_stream(
-                    &ushell,
-                    &stream_dir,
-                    &cmd_prefixes[i],
-                    &stream_file,
-                )
-            }
-            Workload::SpecBwaves { threads } => {
-                run_spec(
-                    &ushell,
-                    &spec_dir,
-                    "bwaves_s",
-                    threads,
-                    &cmd_prefixes[i],
-                    &spec_file,
-                )
-            }
-            Workload::SpecLbm { threads } => {
-                run_spec(
+                Workload::GapbsTc { runs } => {
+                    run_gapbs_tc(&ushell, &gapbs_dir, runs, &cmd_prefixes[i], &gapbs_file)
+                }
+                Workload::GapbsPr { runs } => {
+                    run_gapbs_pr(&ushell, &gapbs_dir, runs, &cmd_prefixes[i], &gapbs_file)
+                }
+                Workload::Gups { threads, exp, hot_exp, num_updates } => {
+                    run_gups(
+                        &ushell,
+                        &gups_dir,
+                        threads,
+                        exp,
+                        hot_exp,
+                        num_updates,
+                        &cmd_prefixes[i],
+                        &gups_file,
+                    )
+                }
+                Workload::CloverLeaf { delay, .. } => {
+                    run_clover(
+                        &ushell,
+                        &clover_dir,
+                        delay,
+                        &cmd_prefixes[i],
+                        &clover_file,
+                    )
+                }
+                Workload::Redis { load_before_wklds, .. } => {
+                    match &mut ycsb_sessions[i] {
+                        Some(ycsb) => {
+                            if !load_before_wklds {
+                                ushell.run(cmd!("sleep 60"))?;
+                                ycsb.start_and_load(&ushell)?;
+                            }
+
+                            Ok(ycsb.run_handle(&ushell)?)
+                        },
+                        None => Err(ScailError::InvalidValueError { msg: "YCSB Session does not exist for Reids".to_string() }.into()),
+                    }
+                }
+                Workload::Stream => {
+                    run_stream(
+                        &ushell,
+                        &stream_dir,
+                        &cmd_prefixes[i],
+                        &stream_file,
+                    )
+                }
+                Workload::SpecBwaves { threads } => {
+                    run_spec(
+                        &ushell,
+                        &spec_dir,
+                        "bwaves_s",
+                        threads,
+                        &cmd_prefixes[i],
+                        &spec_file,
+                    )
+                }
+                Workload::SpecLbm { threads } => {
+                    run_spec(
+                        &ushell,
+                        &spec_dir,
+                        "lbm_s",
+                        threads,
+                        &cmd_prefixes[i],
+                        &spec_file,
+                    )
+                }
+            };
+
+            // Record each start right after it, since starting a workload can
+            // take a while, e.g. to load YCSB
+            if handle.is_ok() {
+                timeline.record(
                     &ushell,
-                    &spec_dir,
-                    "lbm_s",
-                    threads,
-                    &cmd_prefixes[i],
-                    &spec_file,
-                )
+                    "workload_start",
+                    serde_json::json!({ "wkld": i, "name": proc_names[i] }),
+                )?;
             }
+            handle
         })
         .collect();
 
-    if cfg.bwmon {
-        // Attach bwmon to only the first workload since it will track bw for the
-        // whole system.
-        // TODO: have while loop that waits for app to start
-        ushell.run(cmd!("sleep 10"))?;
-        ushell.spawn(cmd!(
-            "sudo {}/bwmon 100 {} $(pgrep -x {})",
-            tools_dir,
-            bwmon_file,
-            proc_names[0]
-        ))?;
-    }
-
     // Wait for the first workload to finish then kill the rest
     for (i, handle) in handles.into_iter().enumerate() {
         if cfg.kill_after_first_done && i != 0 {
+            timeline.record(
+                &ushell,
+                "workload_kill",
+                serde_json::json!({ "wkld": i, "name": proc_names[i] }),
+            )?;
             ushell.run(cmd!("sudo pkill {}", proc_names[i]).allow_error())?;
         }
         match handle {
             Ok(h) => h.join().1?,
             Err(e) => return Err(e),
         };
+        timeline.record(
+            &ushell,
+            "workload_end",
+            serde_json::json!({ "wkld": i, "name": proc_names[i] }),
+        )?;
     }
 
-    ushell.run(cmd!("cat /proc/vmstat | tee {}", &vmstat_file))?;
+    if let Some(scheduler) = scheduler {
+        scheduler.stop()?;
+    }
+
+    artifacts.extend(monitors.finish()?);
+
+    if let Some(throttle) = uncore_throttle {
+        throttle.restore()?;
+    }
+    if let Some(throttle) = quartz_throttle {
+        throttle.stop()?;
+    }
+
+    if let Some(throttle) = mba_throttle {
+        throttle.reset()?;
+    }
+
+    if !matches!(cfg.throttle, ThrottleType::Native) {
+        timeline.record(&ushell, "throttle_stop", serde_json::Value::Null)?;
+    }
 
-    if let Strategy::Cipp { .. } = &cfg.strategy {
+    if let Some(limit) = capacity_limit {
+        limit.restore()?;
+        timeline.record(&ushell, "local_capacity_restore", serde_json::Value::Null)?;
+    }
+
+    if wkld_strategies
+        .iter()
+        .any(|s| matches!(s, Strategy::Cipp { .. }))
+    {
         ushell.run(cmd!("sudo {}/damo status | tee {}", &damo_dir, &damo_status_file))?;
     }
 
@@ -1165,12 +2115,118 @@ where
         ))?;
     }
 
-    bgctx.notify_and_join_all()?;
+    if let Some(cgroups) = cgroups {
+        cgroups.teardown()?;
+    }
+
+    timeline.record(&ushell, "done", serde_json::Value::Null)?;
+
+    let wkspc_dir = dir!(&user_home, crate::WKSPC_PATH);
+    let workloads_dir = dir!(&user_home, crate::WORKLOADS_PATH);
+    let colloid_repo_dir = dir!(&user_home, "colloid");
+    let mut manifest = write_manifest(
+        &ushell,
+        &manifest_file,
+        &[
+            ("workspace", &wkspc_dir),
+            ("kernel", &kernel_dir),
+            ("damo", &damo_dir),
+            ("workloads", &workloads_dir),
+            ("colloid", &colloid_repo_dir),
+        ],
+        artifacts,
+    )?;
+
+    if let Some(retrieve) = &cfg.retrieve {
+        let local_dir = retrieve_results(
+            &ushell,
+            login.username,
+            login.hostname,
+            retrieve,
+            cfg.gen_file_name("").trim_end_matches('.'),
+            &mut manifest,
+            &manifest_file,
+        )?;
+        index_run(&std::path::Path::new(&retrieve.local_dir).join(DB_FILE), &local_dir)?;
+        println!("REPORT: {}", write_report(&local_dir)?.display());
+    }
 
     println!("RESULTS: {}", dir!(&results_dir, cfg.gen_file_name("")));
     Ok(())
 }
 
+/// Parse a `--wkld_strategy` value of the form `<workload index>=<strategy>`.
+fn parse_wkld_strategy(arg: &str) -> Result<(usize, Strategy), failure::Error> {
+    let (idx, strategy) = arg.split_once('=').ok_or_else(|| {
+        failure::format_err!("--wkld_strategy should be <workload index>=<strategy>")
+    })?;
+    let idx = idx.parse::<usize>()?;
+
+    let mut split = strategy.split(':');
+    let name = split.next().unwrap();
+    let args = split
+        .map(str::parse::<usize>)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let strategy = match (name, args.as_slice()) {
+        ("linux", []) => Strategy::Linux,
+        ("tpp", []) => Strategy::Tpp,
+        ("colloid", []) => Strategy::Colloid,
+        ("cipp", []) => Strategy::Cipp { total_bw: false },
+        ("cipp_total_bw", []) => Strategy::Cipp { total_bw: true },
+        ("membind", &[node]) => Strategy::Membind { node },
+        ("preferred", &[node]) => Strategy::Preferred { node },
+        ("capacity_split", &[local_gb]) => Strategy::CapacitySplit { local_gb },
+        ("numactl", &[local, remote]) => Strategy::Numactl { local, remote },
+        ("bwmfs", &[local, remote]) => Strategy::Bwmfs {
+            ratios: vec![(local, remote)],
+        },
+        _ => {
+            return Err(failure::format_err!(
+                "Unknown workload strategy \"{}\"",
+                strategy
+            ))
+        }
+    };
+
+    Ok((idx, strategy))
+}
+
+/// Returns the command prefix that applies a static placement policy, i.e. one
+/// that needs no setup beyond how the workload is launched.
+fn static_placement_prefix(strategy: &Strategy) -> String {
+    match strategy {
+        Strategy::Linux => "numactl --preferred=0 ".into(),
+        Strategy::Membind { node } => format!("numactl --membind={} ", node),
+        Strategy::Preferred { node } => format!("numactl --preferred={} ", node),
+        _ => unreachable!("{:?} is not a static placement strategy", strategy),
+    }
+}
+
+/// The library that splits a workload's memory at `local_gb`, and the
+/// environment variables it needs. The workload's output gets how much went to
+/// each node.
+fn capsplit_preload(local_gb: usize, tools_dir: &str) -> (String, String) {
+    (
+        dir!(tools_dir, "libcapsplit.so"),
+        format!("CAPSPLIT_LOCAL_GB={} CAPSPLIT_VERBOSE=1", local_gb),
+    )
+}
+
+/// Returns the command prefix that preloads each (library, environment
+/// variables) of `preloads`. They have to share one LD_PRELOAD, since each
+/// `env LD_PRELOAD=` would replace the ones before it. This uses env rather than
+/// a bare assignment so it still works after other prefixes like /usr/bin/time.
+fn preload_prefix(preloads: &[(String, String)]) -> String {
+    if preloads.is_empty() {
+        return String::new();
+    }
+
+    let libs: Vec<&str> = preloads.iter().map(|(lib, _)| lib.as_str()).collect();
+    let envs: Vec<&str> = preloads.iter().map(|(_, envs)| envs.as_str()).collect();
+    format!("env LD_PRELOAD={} {} ", libs.join(":"), envs.join(" "))
+}
+
 fn connect_and_setup_host<A>(login: &Login<A>) -> Result<SshShell, failure::Error>
 where
     A: std::net::ToSocketAddrs + std::fmt::Display + std::fmt::Debug + Clone,
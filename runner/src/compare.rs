@@ -0,0 +1,404 @@
+//! Compare the strategies of the indexed runs: the speedup of each over a baseline
+//! on each workload, the geometric mean across workloads, and whether the
+//! difference is significant across trials. The runs of a strategy with different
+//! parameters, e.g. BWMFS ratios, are compared as separate columns.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+use clap::arg;
+
+use crate::optimize_ratio::t_crit_95;
+use crate::results::{
+    csv_field, db_arg, filter_args, open_db, query_metrics, query_runs, Filters,
+};
+
+/// The metrics the workloads report, in order of preference, and whether higher
+/// is better.
+const METRICS: &[(&str, bool)] = &[
+    ("triad_mb_s", true),
+    ("avg_time_s", false),
+    ("wall_clock_s", false),
+    ("runtime_s", false),
+    ("elapsed_s", false),
+];
+
+pub fn cli_options() -> clap::Command {
+    filter_args(
+        clap::Command::new("compare")
+            .about("Compare the speedup of strategies over a baseline across the indexed runs")
+            .arg_required_else_help(true)
+            .disable_version_flag(true)
+            .arg(db_arg())
+            .arg(arg!(<baseline>
+                "The strategy to normalize to, e.g. linux, or its column if it ran with different parameters"))
+            .arg(arg!(--metric <METRIC>
+                "The metric to compare, e.g. wall_clock_s. Default: what each workload reports"))
+            .arg(
+                arg!(--format <FORMAT> "The table format. Default: markdown")
+                    .value_parser(["markdown", "csv"])
+                    .default_value("markdown"),
+            )
+            .arg(arg!(--output <FILE> "Write the table to FILE instead of printing it")),
+    )
+}
+
+/// The config fields that parameterize a strategy: the strategies and schedule of
+/// the runner's configs, and the interleave ratio and bandwidth mode of the bash
+/// scripts'. Runs are pooled when these match, whatever their workloads.
+const STRATEGY_FIELDS: &[&str] = &["strategy", "wkld_strategies", "schedule", "ratio", "total_bw"];
+
+/// The strategy parameters in a run's config, leaving out the empty ones so that
+/// configs from before a field existed match those that leave it at its default.
+/// The strategy itself is only named by the run, so only its parameters are kept.
+fn config_key(config: &str) -> Result<serde_json::Map<String, serde_json::Value>, failure::Error> {
+    let mut config: serde_json::Value = serde_json::from_str(config)?;
+    config["strategy"] = match config["strategy"].take() {
+        serde_json::Value::Object(o) => o.into_values().next().unwrap_or_default(),
+        _ => serde_json::Value::Null,
+    };
+
+    Ok(STRATEGY_FIELDS
+        .iter()
+        .filter_map(|&field| match config.get(field)? {
+            serde_json::Value::Null => None,
+            serde_json::Value::Array(a) if a.is_empty() => None,
+            v => Some((field.to_string(), v.clone())),
+        })
+        .collect())
+}
+
+/// A column name for each config key: the strategy, followed by the fields that
+/// set it apart from the other configs of the same strategy, if there are any.
+fn config_labels(
+    configs: &BTreeMap<String, (String, serde_json::Map<String, serde_json::Value>)>,
+) -> BTreeMap<String, String> {
+    let show = |v: Option<&serde_json::Value>| match v {
+        Some(serde_json::Value::String(s)) => s.clone(),
+        Some(v) => v.to_string(),
+        None => "-".into(),
+    };
+
+    configs
+        .iter()
+        .map(|(key, (strategy, config))| {
+            let others: Vec<_> = configs
+                .values()
+                .filter(|(s, _)| s == strategy)
+                .map(|(_, c)| c)
+                .collect();
+            let fields: BTreeSet<&String> = others.iter().flat_map(|c| c.keys()).collect();
+            let differing: Vec<String> = fields
+                .into_iter()
+                .filter(|f| others.iter().any(|c| c.get(*f) != config.get(*f)))
+                .map(|f| format!("{}={}", f, show(config.get(f))))
+                .collect();
+
+            let label = if differing.is_empty() {
+                strategy.clone()
+            } else {
+                format!("{} ({})", strategy, differing.join(", "))
+            };
+            (key.clone(), label)
+        })
+        .collect()
+}
+
+/// The samples, one per run, of a workload's metric under one strategy.
+#[derive(Clone, Debug, Default)]
+struct Samples(Vec<f64>);
+
+impl Samples {
+    fn mean(&self) -> f64 {
+        self.0.iter().sum::<f64>() / self.0.len() as f64
+    }
+
+    fn var(&self) -> f64 {
+        let mean = self.mean();
+        self.0.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / (self.0.len() - 1) as f64
+    }
+}
+
+/// Welch's t-test of whether the means of `a` and `b` differ. Returns the t
+/// statistic and whether the difference is significant at 95%, or None if either
+/// has too few samples to tell.
+fn welch_t_test(a: &Samples, b: &Samples) -> Option<(f64, bool)> {
+    let (na, nb) = (a.0.len() as f64, b.0.len() as f64);
+    if na < 2.0 || nb < 2.0 {
+        return None;
+    }
+
+    let (sa, sb) = (a.var() / na, b.var() / nb);
+    if sa + sb == 0.0 {
+        return Some((0.0, a.mean() != b.mean()));
+    }
+    let t = (a.mean() - b.mean()) / (sa + sb).sqrt();
+    let dof = (sa + sb).powi(2) / (sa.powi(2) / (na - 1.0) + sb.powi(2) / (nb - 1.0));
+
+    Some((t, t.abs() > t_crit_95(dof.floor() as usize)))
+}
+
+/// One cell of the table: a strategy on a workload, relative to the baseline.
+#[derive(Clone, Debug)]
+struct Comparison {
+    samples: Samples,
+    speedup: Option<f64>,
+    test: Option<(f64, bool)>,
+}
+
+pub fn run(sub_m: &clap::ArgMatches) -> Result<(), failure::Error> {
+    let conn = open_db(&PathBuf::from(sub_m.get_one::<String>("db").unwrap()))?;
+    let baseline = sub_m.get_one::<String>("baseline").unwrap();
+    let baseline_strategy = baseline
+        .split(" (")
+        .next()
+        .unwrap_or(baseline)
+        .to_lowercase();
+    let metric = sub_m.get_one::<String>("metric");
+    let mut filters = Filters::from_matches(sub_m)?;
+    if !filters.strategies.is_empty() && !filters.strategies.contains(&baseline_strategy) {
+        filters.strategies.push(baseline_strategy.clone());
+    }
+
+    // config key -> (strategy, its parameters)
+    let mut configs = BTreeMap::new();
+    // (workload, metric) -> config key -> one sample per run
+    let mut samples: BTreeMap<(String, String), BTreeMap<String, Samples>> = BTreeMap::new();
+    for run in query_runs(&conn, &filters)? {
+        let metrics = query_metrics(&conn, run.id)?;
+        let config = config_key(&run.config)?;
+        let key = format!("{} {}", run.strategy, serde_json::Value::Object(config.clone()));
+        configs
+            .entry(key.clone())
+            .or_insert_with(|| (run.strategy.clone(), config));
+
+        // GNU time measures the same workloads that report their own metrics
+        let has_own = metrics
+            .iter()
+            .any(|(label, _, _)| !label.starts_with("time."));
+        let labels: BTreeSet<&String> = metrics
+            .iter()
+            .map(|(label, _, _)| label)
+            .filter(|label| !has_own || !label.starts_with("time."))
+            .collect();
+
+        for label in labels {
+            let value = |name: &str| {
+                metrics
+                    .iter()
+                    .find(|(l, n, _)| l == label && n == name)
+                    .map(|(_, _, v)| *v)
+            };
+            let found = match metric {
+                Some(metric) => value(metric).map(|v| (metric.clone(), v)),
+                None => METRICS
+                    .iter()
+                    .find_map(|(name, _)| value(name).map(|v| (name.to_string(), v))),
+            };
+            if let Some((name, v)) = found {
+                samples
+                    .entry((label.clone(), name))
+                    .or_default()
+                    .entry(key.clone())
+                    .or_default()
+                    .0
+                    .push(v);
+            }
+        }
+    }
+
+    if samples.is_empty() {
+        return Err(failure::format_err!("No runs with metrics to compare"));
+    }
+
+    // Name the columns by their configs from here on
+    let labels = config_labels(&configs);
+    let samples: BTreeMap<(String, String), BTreeMap<String, Samples>> = samples
+        .into_iter()
+        .map(|(row, by_key)| {
+            let by_label = by_key
+                .into_iter()
+                .map(|(key, samples)| (labels[&key].clone(), samples))
+                .collect();
+            (row, by_label)
+        })
+        .collect();
+
+    let baseline = if labels.values().any(|l| l == baseline) {
+        baseline.clone()
+    } else {
+        let matching: Vec<&String> = configs
+            .iter()
+            .filter(|(_, (strategy, _))| *strategy == baseline_strategy)
+            .map(|(key, _)| &labels[key])
+            .collect();
+        match matching.as_slice() {
+            [] => baseline_strategy.clone(),
+            [label] => label.to_string(),
+            _ => {
+                return Err(failure::format_err!(
+                    "{} ran with different parameters, pick one of: {}",
+                    baseline,
+                    matching
+                        .iter()
+                        .map(|l| format!("\"{}\"", l))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ))
+            }
+        }
+    };
+
+    let mut strategies: Vec<String> = samples
+        .values()
+        .flat_map(|by_strategy| by_strategy.keys().cloned())
+        .filter(|s| *s != baseline)
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    strategies.insert(0, baseline.clone());
+
+    let mut rows = vec![];
+    for ((workload, name), by_strategy) in &samples {
+        let higher_is_better = METRICS
+            .iter()
+            .find(|(m, _)| m == name)
+            .map_or(name.ends_with("_mb_s"), |(_, higher)| *higher);
+        let base = by_strategy.get(&baseline);
+
+        let cells: Vec<Option<Comparison>> = strategies
+            .iter()
+            .map(|strategy| {
+                let samples = by_strategy.get(strategy)?.clone();
+                let speedup = base.map(|base| {
+                    if higher_is_better {
+                        samples.mean() / base.mean()
+                    } else {
+                        base.mean() / samples.mean()
+                    }
+                });
+                let test = base.and_then(|base| welch_t_test(&samples, base));
+                Some(Comparison {
+                    samples,
+                    speedup,
+                    test,
+                })
+            })
+            .collect();
+        rows.push((workload, name, cells));
+    }
+
+    // Across the workloads that ran with both the strategy and the baseline
+    let geomeans: Vec<Option<f64>> = (0..strategies.len())
+        .map(|i| {
+            let speedups: Vec<f64> = rows
+                .iter()
+                .filter_map(|(_, _, cells)| cells[i].as_ref()?.speedup)
+                .collect();
+            if speedups.is_empty() {
+                None
+            } else {
+                Some((speedups.iter().map(|s| s.ln()).sum::<f64>() / speedups.len() as f64).exp())
+            }
+        })
+        .collect();
+
+    let table = match sub_m.get_one::<String>("format").unwrap().as_str() {
+        "csv" => {
+            let mut csv = String::from(
+                "Workload,Metric,Strategy,Trials,Mean,Baseline Trials,Baseline Mean,Speedup,t,Significant\n",
+            );
+            for (workload, name, cells) in &rows {
+                let base = cells[0].as_ref();
+                for (strategy, cell) in strategies.iter().zip(cells) {
+                    let cell = match cell {
+                        Some(cell) => cell,
+                        None => continue,
+                    };
+                    csv.push_str(&format!(
+                        "{},{},{},{},{},{},{},{},{},{}\n",
+                        workload,
+                        name,
+                        csv_field(strategy),
+                        cell.samples.0.len(),
+                        cell.samples.mean(),
+                        base.map_or(String::new(), |b| b.samples.0.len().to_string()),
+                        base.map_or(String::new(), |b| b.samples.mean().to_string()),
+                        cell.speedup.map_or(String::new(), |s| format!("{:.4}", s)),
+                        cell.test
+                            .map_or(String::new(), |(t, _)| format!("{:.3}", t)),
+                        cell.test.map_or(String::new(), |(_, sig)| sig.to_string()),
+                    ));
+                }
+            }
+            for (strategy, geomean) in strategies.iter().zip(&geomeans) {
+                if let Some(geomean) = geomean {
+                    csv.push_str(&format!(
+                        "geomean,speedup,{},,,,,{:.4},,\n",
+                        csv_field(strategy),
+                        geomean
+                    ));
+                }
+            }
+            csv
+        }
+
+        _ => {
+            let mut md = format!(
+                "Speedup over {} (mean of n trials; * significant at 95%, Welch's t-test)\n\n",
+                baseline
+            );
+            md.push_str(&format!(
+                "| Workload | Metric | {} |\n",
+                strategies.join(" | ")
+            ));
+            md.push_str(&format!("|---|---|{}\n", "---:|".repeat(strategies.len())));
+            for (workload, name, cells) in &rows {
+                let cells: Vec<String> = cells
+                    .iter()
+                    .map(|cell| match cell {
+                        None => "-".into(),
+                        Some(cell) => match cell.speedup {
+                            Some(speedup) => format!(
+                                "{:.2}x{} (n={})",
+                                speedup,
+                                if cell.test.map_or(false, |(_, sig)| sig) {
+                                    "*"
+                                } else {
+                                    ""
+                                },
+                                cell.samples.0.len()
+                            ),
+                            // No baseline to normalize to
+                            None => {
+                                format!("{:.2} (n={})", cell.samples.mean(), cell.samples.0.len())
+                            }
+                        },
+                    })
+                    .collect();
+                md.push_str(&format!(
+                    "| {} | {} | {} |\n",
+                    workload,
+                    name,
+                    cells.join(" | ")
+                ));
+            }
+            let geomeans: Vec<String> = geomeans
+                .iter()
+                .map(|g| g.map_or("-".into(), |g| format!("{:.2}x", g)))
+                .collect();
+            md.push_str(&format!("| **geomean** | | {} |\n", geomeans.join(" | ")));
+            md
+        }
+    };
+
+    match sub_m.get_one::<String>("output") {
+        Some(output) => {
+            std::fs::write(output, &table)?;
+            println!("RESULTS: {}", output);
+        }
+        None => print!("{}", table),
+    }
+
+    Ok(())
+}
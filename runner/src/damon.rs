@@ -0,0 +1,249 @@
+//! Record access patterns with DAMON's `damo record` and turn the recording into a
+//! heatmap and a working set size time series.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use spurs::{cmd, Execute, SshShell};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum DamonTarget {
+    /// The virtual address space of each workload.
+    Vaddr,
+    /// The physical address range of the remote node.
+    Paddr,
+}
+
+/// A command that records the access patterns of the processes `pids` (shell
+/// expressions, see `pid_of`) to `record_file` once they have all started. It
+/// stops when they all exit. The wall clock time recording started is written to
+/// `start_file`. It has to run as root.
+pub fn damo_record_vaddr_cmd(
+    damo_dir: &str,
+    pids: &[String],
+    record_file: &str,
+    start_file: &str,
+) -> String {
+    let waits: Vec<String> = pids
+        .iter()
+        .map(|pid| format!("while [ -z \"{}\" ]; do sleep 0.1; done; ", pid))
+        .collect();
+    let targets: Vec<String> = pids
+        .iter()
+        .map(|pid| format!("--target_pid {}", pid))
+        .collect();
+
+    format!(
+        "{}date +%s.%N > {}; exec {}/damo record --out {} {}",
+        waits.concat(),
+        start_file,
+        damo_dir,
+        record_file,
+        targets.join(" ")
+    )
+}
+
+/// A command that records the accesses to the physical range `start..end` to
+/// `record_file` until killed. If DAMON is already running, e.g. for CIPP, it
+/// records what that is monitoring instead. The wall clock time recording started
+/// is written to `start_file`. It has to run as root.
+pub fn damo_record_paddr_cmd(
+    damo_dir: &str,
+    start: usize,
+    end: usize,
+    damon_in_use: bool,
+    record_file: &str,
+    start_file: &str,
+) -> String {
+    if damon_in_use {
+        format!(
+            "date +%s.%N > {}; exec {}/damo record --out {} ongoing",
+            start_file, damo_dir, record_file
+        )
+    } else {
+        format!(
+            "date +%s.%N > {}; exec {}/damo record --out {} --ops paddr --regions {}-{}",
+            start_file, damo_dir, record_file, start, end
+        )
+    }
+}
+
+/// The end of the physical memory of `node`.
+pub fn node_phys_end(ushell: &SshShell, node: usize) -> Result<usize, failure::Error> {
+    let block_size = ushell
+        .run(cmd!("cat /sys/devices/system/memory/block_size_bytes"))?
+        .stdout;
+    let block_size = usize::from_str_radix(block_size.trim(), 16)?;
+    let last_block = ushell
+        .run(cmd!(
+            "ls -d /sys/devices/system/node/node{}/memory[0-9]* | sed 's/.*memory//' | sort -n | tail -n1",
+            node
+        ))?
+        .stdout
+        .trim()
+        .parse::<usize>()?;
+
+    Ok((last_block + 1) * block_size)
+}
+
+/// Dump the recording in `record_file` as text to `raw_file`.
+pub fn damo_report_raw(
+    ushell: &SshShell,
+    damo_dir: &str,
+    record_file: &str,
+    raw_file: &str,
+) -> Result<(), failure::Error> {
+    ushell.run(cmd!(
+        "sudo {}/damo report raw --raw_number --input {} > {}",
+        damo_dir,
+        record_file,
+        raw_file
+    ))?;
+    Ok(())
+}
+
+#[derive(Clone, Debug)]
+struct DamonRegion {
+    start: u64,
+    end: u64,
+    nr_accesses: u64,
+}
+
+#[derive(Clone, Debug)]
+struct DamonSnapshot {
+    /// When the snapshot started, in ns on damo's clock.
+    time_ns: u64,
+    regions: Vec<DamonRegion>,
+}
+
+/// Parse the output of `damo report raw --raw_number` into the snapshots of each
+/// target, in the order the targets first appear.
+fn parse_damo_raw(contents: &str) -> Vec<(String, Vec<DamonSnapshot>)> {
+    let mut targets: Vec<(String, Vec<DamonSnapshot>)> = Vec::new();
+    let mut time_ns = 0;
+    let mut current: Option<usize> = None;
+
+    for line in contents.lines().map(str::trim) {
+        if let Some(t) = line.strip_prefix("monitoring_start:") {
+            time_ns = t.trim().parse().unwrap_or(time_ns);
+        } else if let Some(id) = line.strip_prefix("target_id:") {
+            let id = id.trim().to_string();
+            let idx = match targets.iter().position(|(t, _)| *t == id) {
+                Some(idx) => idx,
+                None => {
+                    targets.push((id, Vec::new()));
+                    targets.len() - 1
+                }
+            };
+            targets[idx].1.push(DamonSnapshot {
+                time_ns,
+                regions: Vec::new(),
+            });
+            current = Some(idx);
+        } else if let Some(idx) = current {
+            // <start>-<end>(<size>): <nr_accesses> [<age>]
+            let region = line.split_once('(').and_then(|(range, rest)| {
+                let (start, end) = range.split_once('-')?;
+                let nr_accesses = rest.split_once(':')?.1.split_whitespace().next()?;
+                Some(DamonRegion {
+                    start: u64::from_str_radix(start.trim(), 16).ok()?,
+                    end: u64::from_str_radix(end.trim(), 16).ok()?,
+                    nr_accesses: nr_accesses.parse().ok()?,
+                })
+            });
+            if let (Some(region), Some(snapshot)) = (region, targets[idx].1.last_mut()) {
+                snapshot.regions.push(region);
+            }
+        }
+    }
+
+    targets
+}
+
+/// Turn the recording dumped to `raw_file` into, for each target, a heatmap of
+/// the access frequency of each region over time and a time series of the
+/// working set size: the bytes in regions that were accessed at all. Each target
+/// gets its own `<stub>.<label>` files, labeled with `labels` in the order the
+/// targets appear. Times are in seconds since `time_zero`, taking the first
+/// snapshot to be when the wall clock time in `start_file` was read.
+pub fn summarize_damon(
+    ushell: &SshShell,
+    raw_file: &str,
+    start_file: &str,
+    heatmap_stub: &str,
+    wss_stub: &str,
+    labels: &[String],
+    time_zero: f64,
+) -> Result<(), failure::Error> {
+    let targets = parse_damo_raw(&ushell.run(cmd!("cat {}", raw_file))?.stdout);
+    if targets.is_empty() {
+        println!("WARNING: no DAMON snapshots in {}", raw_file);
+        return Ok(());
+    }
+
+    // damo's clock has its own zero, so line its first snapshot up with when
+    // recording started
+    let start = ushell.run(cmd!("cat {}", start_file))?.stdout;
+    let start = start
+        .trim()
+        .parse::<f64>()
+        .map_err(|_| failure::format_err!("Could not parse the DAMON start time: {}", start))?;
+    let first_ns = targets
+        .iter()
+        .flat_map(|(_, snapshots)| snapshots.iter().map(|s| s.time_ns))
+        .min()
+        .unwrap_or(0);
+    let secs = |time_ns: u64| start - time_zero + (time_ns - first_ns) as f64 / 1e9;
+
+    for (i, (id, snapshots)) in targets.iter().enumerate() {
+        let label = labels
+            .get(i)
+            .cloned()
+            .unwrap_or_else(|| format!("target{}", id));
+
+        let mut heatmap = String::from("Time (s),Start,End,Accesses\n");
+        let mut wss_by_time: BTreeMap<u64, u64> = BTreeMap::new();
+        for snapshot in snapshots {
+            for r in &snapshot.regions {
+                heatmap.push_str(&format!(
+                    "{:.3},{:#x},{:#x},{}\n",
+                    secs(snapshot.time_ns),
+                    r.start,
+                    r.end,
+                    r.nr_accesses
+                ));
+            }
+            let wss: u64 = snapshot
+                .regions
+                .iter()
+                .filter(|r| r.nr_accesses > 0)
+                .map(|r| r.end - r.start)
+                .sum();
+            *wss_by_time.entry(snapshot.time_ns).or_insert(0) += wss;
+        }
+
+        let mut wss = String::from("Time (s),WSS (MB)\n");
+        for (time_ns, bytes) in &wss_by_time {
+            wss.push_str(&format!(
+                "{:.3},{:.1}\n",
+                secs(*time_ns),
+                *bytes as f64 / (1 << 20) as f64
+            ));
+        }
+
+        crate::write_remote_file(ushell, &format!("{}.{}", heatmap_stub, label), &heatmap)?;
+        crate::write_remote_file(ushell, &format!("{}.{}", wss_stub, label), &wss)?;
+
+        if let Some(max) = wss_by_time.values().max() {
+            println!(
+                "DAMON: {} peak WSS {:.1} MB over {} snapshots",
+                label,
+                *max as f64 / (1 << 20) as f64,
+                snapshots.len()
+            );
+        }
+    }
+
+    Ok(())
+}
@@ -1,6 +1,29 @@
+mod bw_model;
+mod calibrate_bw;
+mod capacity;
+mod cgroup;
+mod characterize;
 mod cipp_exp;
+mod compare;
+mod damon;
+mod manifest;
+mod monitor;
+mod numa_maps;
+mod optimize_ratio;
+mod perf_mem;
+mod plot;
+mod quartz;
+mod rapl;
+mod report;
+mod results;
+mod retrieve;
+mod schedule;
 mod setup_kernel;
 mod setup_wkspc;
+mod throttle;
+mod tiertrace;
+mod timeline;
+mod vmstat;
 
 use clap::arg;
 
@@ -8,6 +31,32 @@ const RESULTS_PATH: &str = "results/";
 const KERNEL_PATH: &str = "kernel/";
 const WORKLOADS_PATH: &str = "workloads/";
 const WKSPC_PATH: &str = "research-workspace/";
+/// The ARCH the tools are built for, which picks the perf events they use.
+const TOOLS_ARCH: &str = "haswell";
+
+/// Write `contents` to `path` on the remote, a chunk of lines per command so that
+/// large files don't make for huge commands.
+fn write_remote_file(
+    ushell: &spurs::SshShell,
+    path: &str,
+    contents: &str,
+) -> Result<(), failure::Error> {
+    use spurs::{cmd, Execute};
+
+    const LINES_PER_CMD: usize = 1000;
+
+    ushell.run(cmd!("truncate -s 0 {}", path))?;
+    let lines: Vec<&str> = contents.lines().collect();
+    for chunk in lines.chunks(LINES_PER_CMD) {
+        ushell.run(cmd!(
+            "echo {} >> {}",
+            spurs_util::escape_for_bash(&chunk.join("\n")),
+            path
+        ))?;
+    }
+
+    Ok(())
+}
 
 fn run() -> Result<(), failure::Error> {
     let matches = clap::Command::new("runner")
@@ -15,6 +64,11 @@ fn run() -> Result<(), failure::Error> {
         .subcommand(crate::setup_wkspc::cli_options())
         .subcommand(crate::setup_kernel::cli_options())
         .subcommand(crate::cipp_exp::cli_options())
+        .subcommand(crate::calibrate_bw::cli_options())
+        .subcommand(crate::results::cli_options())
+        .subcommand(crate::compare::cli_options())
+        .subcommand(crate::plot::cli_options())
+        .subcommand(crate::report::cli_options())
         .subcommand_required(true)
         .disable_version_flag(true)
         .get_matches();
@@ -23,6 +77,11 @@ fn run() -> Result<(), failure::Error> {
         Some(("setup_wkspc", sub_m)) => crate::setup_wkspc::run(sub_m),
         Some(("setup_kernel", sub_m)) => crate::setup_kernel::run(sub_m),
         Some(("cipp_exp", sub_m)) => crate::cipp_exp::run(sub_m),
+        Some(("calibrate_bw", sub_m)) => crate::calibrate_bw::run(sub_m),
+        Some(("results", sub_m)) => crate::results::run(sub_m),
+        Some(("compare", sub_m)) => crate::compare::run(sub_m),
+        Some(("plot", sub_m)) => crate::plot::run(sub_m),
+        Some(("report", sub_m)) => crate::report::run(sub_m),
         _ => {
             unreachable!();
         }
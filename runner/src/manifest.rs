@@ -0,0 +1,190 @@
+//! A manifest for each run recording every file it produced and what code and
+//! system produced them.
+
+use std::path::{Path, PathBuf};
+
+use libscail::get_git_hash;
+
+use serde::{Deserialize, Serialize};
+
+use spurs::{cmd, Execute, SshShell};
+use spurs_util::escape_for_bash;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Artifact {
+    pub path: String,
+    /// What produced the file, e.g. `config`, `workload` or `monitor:vmstat`.
+    pub role: String,
+    /// Where the file was copied to locally, if it was.
+    #[serde(default)]
+    pub local_path: Option<String>,
+    /// SHA-256 of the copy.
+    #[serde(default)]
+    pub sha256: Option<String>,
+    /// Whether the copy is gzipped.
+    #[serde(default)]
+    pub compressed: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RepoInfo {
+    pub name: String,
+    pub path: String,
+    /// None if the repo could not be found.
+    pub git_hash: Option<String>,
+    /// Whether there are uncommitted changes to tracked files.
+    pub dirty: Option<bool>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SystemInfo {
+    #[serde(default)]
+    pub hostname: Option<String>,
+    pub kernel_release: String,
+    pub cmdline: String,
+    pub cpu_model: String,
+    pub microcode: String,
+    pub thp_enabled: String,
+    pub thp_defrag: String,
+    pub aslr: String,
+    /// The scaling governor of each distinct setting across the CPUs.
+    pub governors: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RunnerVersion {
+    pub version: String,
+    pub git_hash: Option<String>,
+    pub dirty: Option<bool>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    pub runner: RunnerVersion,
+    pub repos: Vec<RepoInfo>,
+    pub system: SystemInfo,
+    pub artifacts: Vec<Artifact>,
+    /// The local directory the artifacts were copied to, if they were.
+    #[serde(default)]
+    pub local_dir: Option<String>,
+}
+
+impl Manifest {
+    /// The uncompressed copies in `run_dir` of the artifacts with `role`, for a
+    /// manifest read from the run's local directory, which may have been moved.
+    pub fn local_files(&self, run_dir: &Path, role: &str) -> Vec<PathBuf> {
+        self.artifacts
+            .iter()
+            .filter(|a| a.role == role && !a.compressed)
+            .filter_map(|a| a.local_path.as_ref())
+            .map(|p| run_dir.join(Path::new(p).file_name().unwrap_or_default()))
+            .filter(|p| p.exists())
+            .collect()
+    }
+}
+
+/// The version of this runner, and the commit it was built from if it was built
+/// from a git checkout on this machine.
+fn runner_version() -> RunnerVersion {
+    let git = |args: &[&str]| {
+        std::process::Command::new("git")
+            .arg("-C")
+            .arg(env!("CARGO_MANIFEST_DIR"))
+            .args(args)
+            .output()
+            .ok()
+            .filter(|out| out.status.success())
+            .map(|out| String::from_utf8_lossy(&out.stdout).trim().to_string())
+    };
+
+    RunnerVersion {
+        version: env!("CARGO_PKG_VERSION").into(),
+        git_hash: git(&["rev-parse", "HEAD"]),
+        dirty: git(&["status", "--porcelain", "--untracked-files=no"]).map(|s| !s.is_empty()),
+    }
+}
+
+fn repo_info(ushell: &SshShell, name: &str, path: &str) -> RepoInfo {
+    let dirty = ushell
+        .run(cmd!(
+            "git -C {} status --porcelain --untracked-files=no",
+            path
+        ))
+        .ok()
+        .map(|out| !out.stdout.trim().is_empty());
+
+    RepoInfo {
+        name: name.into(),
+        path: path.into(),
+        git_hash: get_git_hash(ushell, path).ok(),
+        dirty,
+    }
+}
+
+fn system_info(ushell: &SshShell) -> Result<SystemInfo, failure::Error> {
+    let out = |cmd: &str| -> Result<String, failure::Error> {
+        Ok(ushell
+            .run(cmd!("{}", cmd).allow_error())?
+            .stdout
+            .trim()
+            .to_string())
+    };
+
+    Ok(SystemInfo {
+        hostname: Some(out("hostname")?),
+        kernel_release: out("uname -r")?,
+        cmdline: out("cat /proc/cmdline")?,
+        cpu_model: out("grep -m1 '^model name' /proc/cpuinfo | cut -d: -f2-")?,
+        microcode: out("grep -m1 '^microcode' /proc/cpuinfo | cut -d: -f2-")?,
+        thp_enabled: out("cat /sys/kernel/mm/transparent_hugepage/enabled")?,
+        thp_defrag: out("cat /sys/kernel/mm/transparent_hugepage/defrag")?,
+        aslr: out("cat /proc/sys/kernel/randomize_va_space")?,
+        governors: out("cat /sys/devices/system/cpu/cpu*/cpufreq/scaling_governor | sort -u")?
+            .lines()
+            .map(String::from)
+            .collect(),
+    })
+}
+
+/// Write a manifest of the run to `manifest_file`. `repos` are the (name, path) of
+/// the repos on the remote the run used. Only the artifacts that exist are listed.
+pub fn write_manifest(
+    ushell: &SshShell,
+    manifest_file: &str,
+    repos: &[(&str, &str)],
+    artifacts: Vec<Artifact>,
+) -> Result<Manifest, failure::Error> {
+    let existing = ushell
+        .run(cmd!(
+            "for f in {}; do [ -e \"$f\" ] && echo \"$f\"; done; true",
+            artifacts
+                .iter()
+                .map(|a| escape_for_bash(&a.path))
+                .collect::<Vec<_>>()
+                .join(" ")
+        ))?
+        .stdout;
+    let existing: Vec<&str> = existing.lines().collect();
+
+    let manifest = Manifest {
+        runner: runner_version(),
+        repos: repos
+            .iter()
+            .map(|(name, path)| repo_info(ushell, name, path))
+            .collect(),
+        system: system_info(ushell)?,
+        artifacts: artifacts
+            .into_iter()
+            .filter(|a| existing.contains(&a.path.as_str()))
+            .collect(),
+        local_dir: None,
+    };
+
+    crate::write_remote_file(
+        ushell,
+        manifest_file,
+        &serde_json::to_string_pretty(&manifest)?,
+    )?;
+
+    Ok(manifest)
+}
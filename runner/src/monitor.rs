@@ -0,0 +1,857 @@
+//! Monitors record what the system and the workloads are doing during a run. Each
+//! one says how to set itself up, what to run while the workloads run, what to do
+//! once they stop, which files it produces, and how to summarize them. A
+//! `MonitorSet` drives all of them through the same lifecycle.
+
+use libscail::background::{BackgroundContext, BackgroundTask};
+
+use serde::{Deserialize, Serialize};
+
+use spurs::{cmd, Execute, SshShell, SshSpawnHandle};
+use spurs_util::escape_for_bash;
+
+use crate::damon::{
+    damo_record_paddr_cmd, damo_record_vaddr_cmd, damo_report_raw, node_phys_end, summarize_damon,
+    DamonTarget,
+};
+use crate::manifest::Artifact;
+use crate::numa_maps::{numa_maps_sample_cmd, summarize_numa_maps, PlacementTarget};
+use crate::perf_mem::{
+    perf_mem_dump, perf_mem_maps_cmd, perf_mem_nodes_cmd, perf_mem_record_cmd, summarize_perf_mem,
+};
+use crate::rapl::{rapl_domains_cmd, rapl_sample_cmd, summarize_rapl};
+use crate::tiertrace::{summarize_tiertrace, tiertrace_cmd};
+use crate::timeline::Timeline;
+use crate::vmstat::{summarize_vmstat_series, vmstat_diff, vmstat_sample_cmd};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum MonitorKind {
+    /// Load colloid-mon and sample the latency it measures. Colloid's tiering
+    /// uses that latency, so it also needs this (or `Memlat`) to work.
+    ColloidLatency,
+    /// Load colloid-perf and record memory access latencies with tools/memlat.
+    Memlat,
+    /// Record the bandwidth of each node with tools/bwmon.
+    Bwmon,
+    /// Record the local/remote breakdown of each workload with tools/meminfo.
+    Meminfo,
+    /// Snapshot each workload's numa_maps every `period` seconds.
+    NumaMaps { period: usize },
+    /// Sample the tiering counters of /proc/vmstat and each node's vmstat.
+    Vmstat,
+    /// Sample the stats of each workload's cgroup.
+    CgroupStat,
+    /// Sample the package and DRAM energy counters of each socket.
+    Rapl,
+    /// Sample the memory accesses of the given workloads with `perf mem`.
+    PerfMem { wklds: Vec<usize> },
+    /// Trace page migrations and NUMA hint faults with eBPF.
+    Tiertrace,
+    /// Record access patterns with DAMON.
+    Damon { target: DamonTarget },
+}
+
+impl MonitorKind {
+    /// Parse the name of a monitor, as given to --monitor.
+    pub fn from_name(name: &str) -> Result<Self, failure::Error> {
+        Ok(match name {
+            "colloid_latency" => MonitorKind::ColloidLatency,
+            "memlat" => MonitorKind::Memlat,
+            "bwmon" => MonitorKind::Bwmon,
+            "meminfo" => MonitorKind::Meminfo,
+            "numa_maps" => MonitorKind::NumaMaps { period: 5 },
+            "vmstat" => MonitorKind::Vmstat,
+            "cgroup_stat" => MonitorKind::CgroupStat,
+            "rapl" => MonitorKind::Rapl,
+            "perf_mem" => MonitorKind::PerfMem { wklds: vec![0] },
+            "tiertrace" => MonitorKind::Tiertrace,
+            "damon" | "damon_vaddr" => MonitorKind::Damon {
+                target: DamonTarget::Vaddr,
+            },
+            "damon_paddr" => MonitorKind::Damon {
+                target: DamonTarget::Paddr,
+            },
+            _ => return Err(failure::format_err!("Unknown monitor \"{}\"", name)),
+        })
+    }
+
+    fn build(&self) -> Box<dyn Monitor> {
+        match self {
+            MonitorKind::ColloidLatency => Box::new(ColloidLatency),
+            MonitorKind::Memlat => Box::new(Memlat),
+            MonitorKind::Bwmon => Box::new(Bwmon),
+            MonitorKind::Meminfo => Box::new(Meminfo),
+            MonitorKind::NumaMaps { period } => Box::new(NumaMaps { period: *period }),
+            MonitorKind::Vmstat => Box::new(Vmstat),
+            MonitorKind::CgroupStat => Box::new(CgroupStat),
+            MonitorKind::Rapl => Box::new(Rapl),
+            MonitorKind::PerfMem { wklds } => Box::new(PerfMem {
+                wklds: wklds.clone(),
+            }),
+            MonitorKind::Tiertrace => Box::new(Tiertrace),
+            MonitorKind::Damon { target } => Box::new(Damon {
+                target: target.clone(),
+            }),
+        }
+    }
+}
+
+/// What monitors need to know about the run.
+pub struct MonitorEnv<'a> {
+    pub tools_dir: &'a str,
+    pub colloid_dir: &'a str,
+    /// The path of the results file with the given suffix.
+    pub results_file: &'a dyn Fn(&str) -> String,
+    /// The process name of each workload.
+    pub proc_names: &'a [&'a str],
+    /// A core on the remote socket for monitoring processes.
+    pub remote_core: usize,
+    /// The physical address remote memory starts at.
+    pub remote_mem_start: usize,
+    pub damo_dir: &'a str,
+    /// Whether the strategy already runs DAMON itself, like CIPP does.
+    pub damon_in_use: bool,
+    /// The cgroup of each workload, if they have one.
+    pub cgroups: &'a [String],
+    /// Where each workload's memory should end up, if its placement is static.
+    pub targets: &'a [Option<PlacementTarget>],
+    /// Monitors log when they start and stop here, and time their series from its
+    /// zero.
+    pub timeline: &'a Timeline,
+}
+
+impl MonitorEnv<'_> {
+    /// Which instance (from 1) of its name the `i`th workload is, since workloads
+    /// can share a name and `pid_of` tells them apart by age.
+    pub fn nth_instance(&self, i: usize) -> usize {
+        let name = self.proc_names[i];
+        self.proc_names[..i].iter().filter(|&&n| n == name).count() + 1
+    }
+}
+
+/// A shell expression for the pid of the `nth` (from 1) oldest process named
+/// `proc_name`. It is empty if there is no such process.
+pub fn pid_of(proc_name: &str, nth: usize) -> String {
+    format!("$(pgrep -x {} | sort -n | sed -n {}p)", proc_name, nth)
+}
+
+/// How often bwmon samples the bandwidth.
+pub const BWMON_INTERVAL_MS: usize = 100;
+
+pub enum MonitorTask {
+    /// A command to run every so often.
+    Periodic(BackgroundTask),
+    /// A long running command, run as root. It should exec the process that does
+    /// the monitoring so it can be killed when the monitors stop.
+    Process(String),
+}
+
+pub trait Monitor {
+    fn name(&self) -> &'static str;
+
+    /// Build or load whatever the monitor needs, before anything is started.
+    fn setup(&self, _ushell: &SshShell, _env: &MonitorEnv) -> Result<(), failure::Error> {
+        Ok(())
+    }
+
+    /// What to run while the workloads run.
+    fn start(
+        &self,
+        ushell: &SshShell,
+        env: &MonitorEnv,
+    ) -> Result<Vec<MonitorTask>, failure::Error>;
+
+    /// Called after the workloads and every monitor's tasks are done.
+    fn stop(&self, _ushell: &SshShell, _env: &MonitorEnv) -> Result<(), failure::Error> {
+        Ok(())
+    }
+
+    /// The files the monitor produces.
+    fn artifacts(&self, env: &MonitorEnv) -> Vec<String>;
+
+    /// Summarize what was recorded, once everything has stopped.
+    fn parse(&self, _ushell: &SshShell, _env: &MonitorEnv) -> Result<(), failure::Error> {
+        Ok(())
+    }
+}
+
+struct ColloidLatency;
+
+impl Monitor for ColloidLatency {
+    fn name(&self) -> &'static str {
+        "colloid_latency"
+    }
+
+    fn setup(&self, ushell: &SshShell, env: &MonitorEnv) -> Result<(), failure::Error> {
+        ushell.run(cmd!("make").cwd(format!("{}/colloid-mon", env.colloid_dir)))?;
+        ushell.run(cmd!(
+            "sudo insmod {}/colloid-mon/colloid-mon.ko",
+            env.colloid_dir
+        ))?;
+        Ok(())
+    }
+
+    fn start(
+        &self,
+        _ushell: &SshShell,
+        env: &MonitorEnv,
+    ) -> Result<Vec<MonitorTask>, failure::Error> {
+        let lat_file = (env.results_file)("colloid.lat");
+        Ok(vec![MonitorTask::Periodic(BackgroundTask {
+            name: "colloid_latency",
+            period: 1, // Seconds
+            cmd: format!(
+                "echo \"time $(date +%s.%N) $(tr '\\n' ' ' < /sys/kernel/colloid/latency)\" >> {}",
+                &lat_file
+            ),
+            ensure_started: lat_file,
+        })])
+    }
+
+    fn artifacts(&self, env: &MonitorEnv) -> Vec<String> {
+        vec![(env.results_file)("colloid.lat")]
+    }
+}
+
+struct Memlat;
+
+impl Monitor for Memlat {
+    fn name(&self) -> &'static str {
+        "memlat"
+    }
+
+    fn setup(&self, ushell: &SshShell, env: &MonitorEnv) -> Result<(), failure::Error> {
+        ushell.run(cmd!("make").cwd(format!("{}/colloid-perf", env.colloid_dir)))?;
+        ushell.run(cmd!(
+            "sudo insmod {}/colloid-perf/colloid-perf.ko",
+            env.colloid_dir
+        ))?;
+        Ok(())
+    }
+
+    fn start(
+        &self,
+        _ushell: &SshShell,
+        env: &MonitorEnv,
+    ) -> Result<Vec<MonitorTask>, failure::Error> {
+        Ok(vec![MonitorTask::Process(format!(
+            "exec taskset -c {} {}/memlat {} 10 {}",
+            env.remote_core,
+            env.tools_dir,
+            env.remote_mem_start / 4096,
+            (env.results_file)("colloid.lat")
+        ))])
+    }
+
+    fn artifacts(&self, env: &MonitorEnv) -> Vec<String> {
+        vec![(env.results_file)("colloid.lat")]
+    }
+}
+
+struct Bwmon;
+
+impl Monitor for Bwmon {
+    fn name(&self) -> &'static str {
+        "bwmon"
+    }
+
+    fn start(
+        &self,
+        _ushell: &SshShell,
+        env: &MonitorEnv,
+    ) -> Result<Vec<MonitorTask>, failure::Error> {
+        // Attach bwmon to only the first workload since it will track bw for the
+        // whole system. It exits when the workload does.
+        Ok(vec![MonitorTask::Process(format!(
+            "while ! pgrep -x {} > /dev/null; do sleep 0.1; done; \
+             exec taskset -c {} {}/bwmon {} {} {}",
+            env.proc_names[0],
+            env.remote_core,
+            env.tools_dir,
+            BWMON_INTERVAL_MS,
+            (env.results_file)("bwmon"),
+            pid_of(env.proc_names[0], 1)
+        ))])
+    }
+
+    fn artifacts(&self, env: &MonitorEnv) -> Vec<String> {
+        vec![(env.results_file)("bwmon")]
+    }
+}
+
+struct Meminfo;
+
+impl Monitor for Meminfo {
+    fn name(&self) -> &'static str {
+        "meminfo"
+    }
+
+    fn start(
+        &self,
+        _ushell: &SshShell,
+        env: &MonitorEnv,
+    ) -> Result<Vec<MonitorTask>, failure::Error> {
+        Ok(env
+            .proc_names
+            .iter()
+            .map(|name| {
+                let meminfo_file = format!("{}.{}", (env.results_file)("meminfo"), name);
+                MonitorTask::Periodic(BackgroundTask {
+                    name: "meminfo",
+                    period: 5, // Seconds
+                    // TODO: Below is currently hardcoded local memory range for c220g2.
+                    // We should make this more general
+                    cmd: format!(
+                        "sudo {}/meminfo {} 0x100000000 0x1480000000 >> {}",
+                        env.tools_dir,
+                        pid_of(name, 1),
+                        &meminfo_file
+                    ),
+                    ensure_started: meminfo_file,
+                })
+            })
+            .collect())
+    }
+
+    fn artifacts(&self, env: &MonitorEnv) -> Vec<String> {
+        env.proc_names
+            .iter()
+            .map(|name| format!("{}.{}", (env.results_file)("meminfo"), name))
+            .collect()
+    }
+}
+
+struct NumaMaps {
+    period: usize,
+}
+
+impl NumaMaps {
+    fn samples_file(env: &MonitorEnv, i: usize) -> String {
+        format!("{}.wkld{}", (env.results_file)("numa_maps"), i)
+    }
+
+    fn frac_file(env: &MonitorEnv, i: usize) -> String {
+        format!("{}.frac", Self::samples_file(env, i))
+    }
+}
+
+impl Monitor for NumaMaps {
+    fn name(&self) -> &'static str {
+        "numa_maps"
+    }
+
+    fn start(
+        &self,
+        _ushell: &SshShell,
+        env: &MonitorEnv,
+    ) -> Result<Vec<MonitorTask>, failure::Error> {
+        Ok(env
+            .proc_names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let samples_file = Self::samples_file(env, i);
+                MonitorTask::Periodic(BackgroundTask {
+                    name: "numa_maps",
+                    period: self.period,
+                    cmd: numa_maps_sample_cmd(&pid_of(name, env.nth_instance(i)), &samples_file),
+                    ensure_started: samples_file,
+                })
+            })
+            .collect())
+    }
+
+    fn artifacts(&self, env: &MonitorEnv) -> Vec<String> {
+        (0..env.proc_names.len())
+            .flat_map(|i| [Self::samples_file(env, i), Self::frac_file(env, i)])
+            .collect()
+    }
+
+    fn parse(&self, ushell: &SshShell, env: &MonitorEnv) -> Result<(), failure::Error> {
+        for i in 0..env.proc_names.len() {
+            summarize_numa_maps(
+                ushell,
+                &Self::samples_file(env, i),
+                &Self::frac_file(env, i),
+                env.targets[i],
+                env.timeline.zero(),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+struct Vmstat;
+
+impl Monitor for Vmstat {
+    fn name(&self) -> &'static str {
+        "vmstat"
+    }
+
+    fn start(
+        &self,
+        ushell: &SshShell,
+        env: &MonitorEnv,
+    ) -> Result<Vec<MonitorTask>, failure::Error> {
+        let samples_file = (env.results_file)("vmstat_samples");
+
+        ushell.run(cmd!(
+            "cat /proc/vmstat > {}",
+            (env.results_file)("vmstat_begin")
+        ))?;
+        Ok(vec![MonitorTask::Periodic(BackgroundTask {
+            name: "vmstat",
+            period: 1, // Seconds
+            cmd: vmstat_sample_cmd(&samples_file),
+            ensure_started: samples_file,
+        })])
+    }
+
+    fn stop(&self, ushell: &SshShell, env: &MonitorEnv) -> Result<(), failure::Error> {
+        ushell.run(cmd!("cat /proc/vmstat > {}", (env.results_file)("vmstat")))?;
+        Ok(())
+    }
+
+    fn artifacts(&self, env: &MonitorEnv) -> Vec<String> {
+        [
+            "vmstat_begin",
+            "vmstat",
+            "vmstat_samples",
+            "vmstat_series",
+            "vmstat_diff",
+        ]
+        .iter()
+        .map(|suffix| (env.results_file)(suffix))
+        .collect()
+    }
+
+    fn parse(&self, ushell: &SshShell, env: &MonitorEnv) -> Result<(), failure::Error> {
+        summarize_vmstat_series(
+            ushell,
+            &(env.results_file)("vmstat_samples"),
+            &(env.results_file)("vmstat_series"),
+            env.timeline.zero(),
+        )?;
+        vmstat_diff(
+            ushell,
+            &(env.results_file)("vmstat_begin"),
+            &(env.results_file)("vmstat"),
+            &(env.results_file)("vmstat_diff"),
+        )
+    }
+}
+
+struct CgroupStat;
+
+impl Monitor for CgroupStat {
+    fn name(&self) -> &'static str {
+        "cgroup_stat"
+    }
+
+    fn start(
+        &self,
+        _ushell: &SshShell,
+        env: &MonitorEnv,
+    ) -> Result<Vec<MonitorTask>, failure::Error> {
+        Ok(env
+            .cgroups
+            .iter()
+            .enumerate()
+            .map(|(i, group)| {
+                let stat_file = format!("{}.wkld{}", (env.results_file)("cgroup_stat"), i);
+                MonitorTask::Periodic(BackgroundTask {
+                    name: "cgroup_stat",
+                    period: 5, // Seconds
+                    cmd: crate::cgroup::cgroup_sample_cmd(group, &stat_file),
+                    ensure_started: stat_file,
+                })
+            })
+            .collect())
+    }
+
+    fn artifacts(&self, env: &MonitorEnv) -> Vec<String> {
+        (0..env.cgroups.len())
+            .map(|i| format!("{}.wkld{}", (env.results_file)("cgroup_stat"), i))
+            .collect()
+    }
+}
+
+struct Rapl;
+
+impl Monitor for Rapl {
+    fn name(&self) -> &'static str {
+        "rapl"
+    }
+
+    fn setup(&self, ushell: &SshShell, env: &MonitorEnv) -> Result<(), failure::Error> {
+        let domains_file = (env.results_file)("rapl_domains");
+        ushell.run(cmd!("{}", rapl_domains_cmd(&domains_file)))?;
+        if ushell.run(cmd!("test -s {}", domains_file)).is_err() {
+            return Err(failure::format_err!(
+                "No RAPL domains found. Is the intel_rapl_msr module loaded?"
+            ));
+        }
+        Ok(())
+    }
+
+    fn start(
+        &self,
+        _ushell: &SshShell,
+        env: &MonitorEnv,
+    ) -> Result<Vec<MonitorTask>, failure::Error> {
+        let samples_file = (env.results_file)("rapl_samples");
+        Ok(vec![MonitorTask::Periodic(BackgroundTask {
+            name: "rapl",
+            period: 1, // Seconds
+            cmd: rapl_sample_cmd(&(env.results_file)("rapl_domains"), &samples_file),
+            ensure_started: samples_file,
+        })])
+    }
+
+    fn artifacts(&self, env: &MonitorEnv) -> Vec<String> {
+        ["rapl_domains", "rapl_samples", "rapl_series", "rapl_energy"]
+            .iter()
+            .map(|suffix| (env.results_file)(suffix))
+            .collect()
+    }
+
+    fn parse(&self, ushell: &SshShell, env: &MonitorEnv) -> Result<(), failure::Error> {
+        summarize_rapl(
+            ushell,
+            &(env.results_file)("rapl_domains"),
+            &(env.results_file)("rapl_samples"),
+            &(env.results_file)("rapl_series"),
+            &(env.results_file)("rapl_energy"),
+            env.timeline.zero(),
+        )
+    }
+}
+
+struct PerfMem {
+    wklds: Vec<usize>,
+}
+
+impl PerfMem {
+    /// The raw samples can be huge, so keep them out of the results.
+    fn data_file(i: usize) -> String {
+        format!("/tmp/perf_mem.wkld{}.data", i)
+    }
+
+    fn results_file(env: &MonitorEnv, i: usize, suffix: &str) -> String {
+        format!("{}.wkld{}.{}", (env.results_file)("perf_mem"), i, suffix)
+    }
+}
+
+impl Monitor for PerfMem {
+    fn name(&self) -> &'static str {
+        "perf_mem"
+    }
+
+    fn setup(&self, ushell: &SshShell, env: &MonitorEnv) -> Result<(), failure::Error> {
+        if let Some(i) = self.wklds.iter().find(|&&i| i >= env.proc_names.len()) {
+            return Err(failure::format_err!("perf_mem: there is no workload {}", i));
+        }
+        ushell.run(cmd!(
+            "{}",
+            perf_mem_nodes_cmd(&(env.results_file)("perf_mem_nodes"))
+        ))?;
+        Ok(())
+    }
+
+    fn start(
+        &self,
+        _ushell: &SshShell,
+        env: &MonitorEnv,
+    ) -> Result<Vec<MonitorTask>, failure::Error> {
+        let mut tasks = Vec::new();
+        for &i in &self.wklds {
+            let pid = pid_of(env.proc_names[i], env.nth_instance(i));
+            let maps_file = Self::results_file(env, i, "maps");
+
+            tasks.push(MonitorTask::Process(perf_mem_record_cmd(
+                &pid,
+                &Self::data_file(i),
+            )));
+            tasks.push(MonitorTask::Periodic(BackgroundTask {
+                name: "perf_mem_maps",
+                period: 5, // Seconds
+                cmd: perf_mem_maps_cmd(&pid, &maps_file),
+                ensure_started: maps_file,
+            }));
+        }
+        Ok(tasks)
+    }
+
+    fn stop(&self, ushell: &SshShell, env: &MonitorEnv) -> Result<(), failure::Error> {
+        for &i in &self.wklds {
+            perf_mem_dump(
+                ushell,
+                &Self::data_file(i),
+                &Self::results_file(env, i, "dump"),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn artifacts(&self, env: &MonitorEnv) -> Vec<String> {
+        let mut artifacts = vec![(env.results_file)("perf_mem_nodes")];
+        for &i in &self.wklds {
+            artifacts.push(Self::data_file(i));
+            for suffix in ["maps", "dump", "regions", "symbols"] {
+                artifacts.push(Self::results_file(env, i, suffix));
+            }
+        }
+        artifacts
+    }
+
+    fn parse(&self, ushell: &SshShell, env: &MonitorEnv) -> Result<(), failure::Error> {
+        for &i in &self.wklds {
+            summarize_perf_mem(
+                ushell,
+                &Self::results_file(env, i, "dump"),
+                &(env.results_file)("perf_mem_nodes"),
+                &Self::results_file(env, i, "maps"),
+                &Self::results_file(env, i, "regions"),
+                &Self::results_file(env, i, "symbols"),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+struct Tiertrace;
+
+impl Monitor for Tiertrace {
+    fn name(&self) -> &'static str {
+        "tiertrace"
+    }
+
+    fn start(
+        &self,
+        _ushell: &SshShell,
+        env: &MonitorEnv,
+    ) -> Result<Vec<MonitorTask>, failure::Error> {
+        Ok(vec![MonitorTask::Process(tiertrace_cmd(
+            env.tools_dir,
+            &(env.results_file)("tiertrace"),
+            &(env.results_file)("tiertrace_lat"),
+        ))])
+    }
+
+    fn artifacts(&self, env: &MonitorEnv) -> Vec<String> {
+        ["tiertrace", "tiertrace_lat", "tiertrace_summary"]
+            .iter()
+            .map(|suffix| (env.results_file)(suffix))
+            .collect()
+    }
+
+    fn parse(&self, ushell: &SshShell, env: &MonitorEnv) -> Result<(), failure::Error> {
+        summarize_tiertrace(
+            ushell,
+            &(env.results_file)("tiertrace"),
+            &(env.results_file)("tiertrace_summary"),
+            env.proc_names,
+            env.timeline.zero(),
+        )
+    }
+}
+
+struct Damon {
+    target: DamonTarget,
+}
+
+impl Damon {
+    /// What each DAMON target is called, in the order they are recorded.
+    fn labels(&self, env: &MonitorEnv) -> Vec<String> {
+        match self.target {
+            DamonTarget::Vaddr => (0..env.proc_names.len())
+                .map(|i| format!("wkld{}", i))
+                .collect(),
+            DamonTarget::Paddr => vec!["remote".into()],
+        }
+    }
+}
+
+impl Monitor for Damon {
+    fn name(&self) -> &'static str {
+        "damon"
+    }
+
+    fn setup(&self, _ushell: &SshShell, env: &MonitorEnv) -> Result<(), failure::Error> {
+        if self.target == DamonTarget::Vaddr && env.damon_in_use {
+            return Err(failure::format_err!(
+                "DAMON is already used by the strategy, so only the damon_paddr monitor can be used"
+            ));
+        }
+        Ok(())
+    }
+
+    fn start(
+        &self,
+        ushell: &SshShell,
+        env: &MonitorEnv,
+    ) -> Result<Vec<MonitorTask>, failure::Error> {
+        let record_file = (env.results_file)("damon.data");
+        let start_file = (env.results_file)("damon_start");
+        let cmd = match self.target {
+            DamonTarget::Vaddr => {
+                let pids: Vec<String> = env
+                    .proc_names
+                    .iter()
+                    .enumerate()
+                    .map(|(i, name)| pid_of(name, env.nth_instance(i)))
+                    .collect();
+                damo_record_vaddr_cmd(env.damo_dir, &pids, &record_file, &start_file)
+            }
+            DamonTarget::Paddr => damo_record_paddr_cmd(
+                env.damo_dir,
+                env.remote_mem_start,
+                node_phys_end(ushell, 1)?,
+                env.damon_in_use,
+                &record_file,
+                &start_file,
+            ),
+        };
+        Ok(vec![MonitorTask::Process(cmd)])
+    }
+
+    fn stop(&self, ushell: &SshShell, env: &MonitorEnv) -> Result<(), failure::Error> {
+        damo_report_raw(
+            ushell,
+            env.damo_dir,
+            &(env.results_file)("damon.data"),
+            &(env.results_file)("damon_raw"),
+        )
+    }
+
+    fn artifacts(&self, env: &MonitorEnv) -> Vec<String> {
+        let mut artifacts = vec![
+            (env.results_file)("damon.data"),
+            (env.results_file)("damon_start"),
+            (env.results_file)("damon_raw"),
+        ];
+        for label in self.labels(env) {
+            artifacts.push(format!("{}.{}", (env.results_file)("damon_heatmap"), label));
+            artifacts.push(format!("{}.{}", (env.results_file)("damon_wss"), label));
+        }
+        artifacts
+    }
+
+    fn parse(&self, ushell: &SshShell, env: &MonitorEnv) -> Result<(), failure::Error> {
+        summarize_damon(
+            ushell,
+            &(env.results_file)("damon_raw"),
+            &(env.results_file)("damon_start"),
+            &(env.results_file)("damon_heatmap"),
+            &(env.results_file)("damon_wss"),
+            &self.labels(env),
+            env.timeline.zero(),
+        )
+    }
+}
+
+/// The monitors of a run, and what they have started.
+pub struct MonitorSet<'s, 'a> {
+    ushell: &'s SshShell,
+    env: MonitorEnv<'a>,
+    monitors: Vec<Box<dyn Monitor>>,
+    bgctx: BackgroundContext<'s>,
+    /// The pid file and handle of each running `MonitorTask::Process`.
+    processes: Vec<(String, SshSpawnHandle)>,
+}
+
+impl<'s, 'a> MonitorSet<'s, 'a> {
+    pub fn new(ushell: &'s SshShell, kinds: &[MonitorKind], env: MonitorEnv<'a>) -> Self {
+        MonitorSet {
+            ushell,
+            env,
+            monitors: kinds.iter().map(MonitorKind::build).collect(),
+            bgctx: BackgroundContext::new(ushell),
+            processes: Vec::new(),
+        }
+    }
+
+    pub fn setup(&self) -> Result<(), failure::Error> {
+        for monitor in &self.monitors {
+            monitor.setup(self.ushell, &self.env)?;
+        }
+        Ok(())
+    }
+
+    pub fn start(&mut self) -> Result<(), failure::Error> {
+        for monitor in &self.monitors {
+            for (i, task) in monitor
+                .start(self.ushell, &self.env)?
+                .into_iter()
+                .enumerate()
+            {
+                // Samples without timestamps of their own can be anchored to
+                // when their task started
+                let details = match task {
+                    MonitorTask::Periodic(task) => {
+                        let details = serde_json::json!({
+                            "monitor": monitor.name(),
+                            "task": task.name,
+                            "period": task.period,
+                        });
+                        self.bgctx.spawn(task)?;
+                        details
+                    }
+                    MonitorTask::Process(cmd) => {
+                        let pid_file = format!("/tmp/monitor_{}_{}.pid", monitor.name(), i);
+                        let handle = self.ushell.spawn(cmd!(
+                            "sudo bash -c {}",
+                            escape_for_bash(&format!("echo $$ > {}; {}", pid_file, cmd))
+                        ))?;
+                        self.processes.push((pid_file, handle));
+                        serde_json::json!({ "monitor": monitor.name(), "task": "process" })
+                    }
+                };
+                self.env
+                    .timeline
+                    .record(self.ushell, "monitor_start", details)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Stop everything the monitors started, then let each finish up and
+    /// summarize what it recorded. Returns every monitor's artifacts.
+    pub fn finish(mut self) -> Result<Vec<Artifact>, failure::Error> {
+        for (pid_file, handle) in self.processes.drain(..) {
+            // The process may have already exited on its own
+            self.ushell.run(
+                cmd!("sudo kill $(cat {}); sudo rm -f {}", pid_file, pid_file).allow_error(),
+            )?;
+            // Being killed makes the command fail, which is expected
+            let _ = handle.join();
+        }
+        self.bgctx.notify_and_join_all()?;
+
+        for monitor in &self.monitors {
+            monitor.stop(self.ushell, &self.env)?;
+            self.env.timeline.record(
+                self.ushell,
+                "monitor_stop",
+                serde_json::json!({ "monitor": monitor.name() }),
+            )?;
+        }
+        for monitor in &self.monitors {
+            monitor.parse(self.ushell, &self.env)?;
+        }
+
+        Ok(self
+            .monitors
+            .iter()
+            .flat_map(|m| {
+                m.artifacts(&self.env).into_iter().map(|path| Artifact {
+                    path,
+                    role: format!("monitor:{}", m.name()),
+                    local_path: None,
+                    sha256: None,
+                    compressed: false,
+                })
+            })
+            .collect())
+    }
+}
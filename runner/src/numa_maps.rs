@@ -0,0 +1,158 @@
+//! Track where each workload's memory actually is by periodically summing
+//! /proc/<pid>/numa_maps, and check it against the placement we asked for.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use spurs::{cmd, Execute, SshShell};
+
+/// How far the achieved local fraction can be from the target before we warn.
+const PLACEMENT_TOLERANCE: f64 = 0.05;
+
+/// Sum each node's resident memory in a numa_maps file, in kB. The N<node>= counts
+/// are in pages of the mapping's kernelpagesize_kB, so huge pages are weighted.
+const NUMA_MAPS_AWK: &str = "{ kps = 4; \
+    for (i = 1; i <= NF; i++) if ($i ~ /^kernelpagesize_kB=/) { split($i, a, \"=\"); kps = a[2] } \
+    for (i = 1; i <= NF; i++) if ($i ~ /^N[0-9]+=/) { split(substr($i, 2), a, \"=\"); kb[a[1]] += a[2] * kps } } \
+    END { for (n in kb) printf \" N%s=%d\", n, kb[n] }";
+
+/// Where a workload's memory should end up, if its placement is static.
+#[derive(Clone, Copy, Debug)]
+pub enum PlacementTarget {
+    /// A local:remote split.
+    Ratio(usize, usize),
+    /// The first this many GB local, the rest remote.
+    LocalGb(usize),
+}
+
+impl PlacementTarget {
+    /// The fraction of `total_kb` of resident memory that should be local.
+    fn local_frac(&self, total_kb: u64) -> f64 {
+        match *self {
+            PlacementTarget::Ratio(local, remote) => local as f64 / (local + remote) as f64,
+            PlacementTarget::LocalGb(gb) if total_kb > 0 => {
+                ((gb << 20) as f64 / total_kb as f64).min(1.0)
+            }
+            PlacementTarget::LocalGb(_) => 1.0,
+        }
+    }
+}
+
+/// One snapshot of where a process's memory is.
+#[derive(Clone, Debug)]
+pub struct NumaMapsSample {
+    /// Seconds since the epoch.
+    pub time: f64,
+    /// Resident kB per node.
+    pub node_kb: BTreeMap<usize, u64>,
+}
+
+impl NumaMapsSample {
+    /// The fraction of the resident memory on `node`.
+    pub fn fraction(&self, node: usize) -> f64 {
+        let total: u64 = self.node_kb.values().sum();
+        if total == 0 {
+            return 0.0;
+        }
+
+        *self.node_kb.get(&node).unwrap_or(&0) as f64 / total as f64
+    }
+}
+
+/// A command that appends a line of `time <secs> N<node>=<kB> ...` to `file` for
+/// the process `pid` (a shell expression, see `pid_of`), if it is running. `file`
+/// is created either way, since the task only counts as started once it exists
+/// and the monitors start before the workloads.
+pub fn numa_maps_sample_cmd(pid: &str, file: &str) -> String {
+    format!(
+        "touch {}; \
+         pid={}; \
+         if [ -n \"$pid\" ]; then \
+         echo \"time $(date +%s.%N)$(sudo cat /proc/$pid/numa_maps | awk '{}')\" >> {}; \
+         fi",
+        file, pid, NUMA_MAPS_AWK, file
+    )
+}
+
+pub fn parse_numa_maps_samples(contents: &str) -> Vec<NumaMapsSample> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            if fields.next()? != "time" {
+                return None;
+            }
+            let time = fields.next()?.parse::<f64>().ok()?;
+            let node_kb = fields
+                .filter_map(|f| {
+                    let (node, kb) = f.strip_prefix('N')?.split_once('=')?;
+                    Some((node.parse::<usize>().ok()?, kb.parse::<u64>().ok()?))
+                })
+                .collect();
+
+            Some(NumaMapsSample { time, node_kb })
+        })
+        .collect()
+}
+
+/// Turn the samples in `samples_file` into the fraction of memory on each node
+/// over time, in seconds since the wall clock time `time_zero`, written as a CSV
+/// to `frac_file`. If there is a `target`, warn if the final split is too far from
+/// it.
+pub fn summarize_numa_maps(
+    ushell: &SshShell,
+    samples_file: &str,
+    frac_file: &str,
+    target: Option<PlacementTarget>,
+    time_zero: f64,
+) -> Result<(), failure::Error> {
+    let samples = parse_numa_maps_samples(
+        &ushell
+            .run(cmd!("cat {}", samples_file).allow_error())?
+            .stdout,
+    );
+    let last = match samples.last() {
+        Some(last) => last.clone(),
+        None => {
+            println!("WARNING: no numa_maps samples in {}", samples_file);
+            return Ok(());
+        }
+    };
+
+    let nodes: Vec<usize> = samples
+        .iter()
+        .flat_map(|s| s.node_kb.keys().copied())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+
+    let mut csv = String::from("Time (s)");
+    for node in &nodes {
+        csv.push_str(&format!(",Node {} Frac", node));
+    }
+    csv.push('\n');
+    for sample in &samples {
+        csv.push_str(&format!("{:.1}", sample.time - time_zero));
+        for &node in &nodes {
+            csv.push_str(&format!(",{:.4}", sample.fraction(node)));
+        }
+        csv.push('\n');
+    }
+    crate::write_remote_file(ushell, frac_file, &csv)?;
+
+    if let Some(target) = target {
+        let target_frac = target.local_frac(last.node_kb.values().sum());
+        let local_frac = last.fraction(0);
+        println!(
+            "Placement of {}: {:.3} local, target {:.3}",
+            samples_file, local_frac, target_frac
+        );
+        if (local_frac - target_frac).abs() > PLACEMENT_TOLERANCE {
+            println!(
+                "WARNING: {:.3} of the memory is local, but the {:?} target is {:.3}",
+                local_frac, target, target_frac
+            );
+        }
+    }
+
+    Ok(())
+}
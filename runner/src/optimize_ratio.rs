@@ -0,0 +1,211 @@
+//! Search for the best static interleave ratio of a workload. Running a workload is
+//! expensive and noisy, so rather than sweeping every ratio, we treat the cost (e.g.
+//! runtime) as a unimodal function of the local ratio and narrow in on its minimum
+//! with a golden-section search. Each probe is run once to steer the search, and
+//! only the ratios left in the final bracket are repeated to estimate the noise.
+
+use std::collections::BTreeMap;
+
+/// The two-sided 95% t critical values for 1 to 10 degrees of freedom.
+const T_CRIT_95: [f64; 10] = [
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228,
+];
+
+/// The two-sided 95% t critical value for `dof` degrees of freedom. Past the
+/// table, the Cornish-Fisher expansion of the t quantile around the normal one is
+/// within 0.001 of it.
+pub fn t_crit_95(dof: usize) -> f64 {
+    match dof {
+        0 => f64::INFINITY,
+        1..=10 => T_CRIT_95[dof - 1],
+        _ => {
+            const Z: f64 = 1.959964;
+            let n = dof as f64;
+            let g1 = (Z.powi(3) + Z) / 4.0;
+            let g2 = (5.0 * Z.powi(5) + 16.0 * Z.powi(3) + 3.0 * Z) / 96.0;
+            let g3 = (3.0 * Z.powi(7) + 19.0 * Z.powi(5) + 17.0 * Z.powi(3) - 15.0 * Z) / 384.0;
+            let g4 = (79.0 * Z.powi(9) + 776.0 * Z.powi(7) + 1482.0 * Z.powi(5)
+                - 1920.0 * Z.powi(3)
+                - 945.0 * Z)
+                / 92160.0;
+            Z + g1 / n + g2 / n.powi(2) + g3 / n.powi(3) + g4 / n.powi(4)
+        }
+    }
+}
+
+/// The costs measured for one ratio.
+#[derive(Clone, Debug)]
+pub struct Evaluation {
+    pub ratio: usize,
+    pub samples: Vec<f64>,
+}
+
+impl Evaluation {
+    pub fn mean(&self) -> f64 {
+        self.samples.iter().sum::<f64>() / self.samples.len() as f64
+    }
+
+    /// The standard error of the mean.
+    pub fn std_err(&self) -> f64 {
+        let n = self.samples.len();
+        if n < 2 {
+            return f64::INFINITY;
+        }
+
+        let mean = self.mean();
+        let var = self.samples.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / (n - 1) as f64;
+        (var / n as f64).sqrt()
+    }
+
+    /// Half the width of the 95% confidence interval of the mean.
+    pub fn ci95(&self) -> f64 {
+        t_crit_95(self.samples.len() - 1) * self.std_err()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SearchResult {
+    /// The confirmed ratio with the lowest mean cost.
+    pub best: Evaluation,
+    /// The lowest and highest confirmed ratios whose mean cost is not significantly
+    /// (at 95%) worse than the best's.
+    pub bounds: (usize, usize),
+    /// Every ratio that was evaluated, in order of ratio. Only the confirmed ones,
+    /// those in the final bracket, were measured every trial.
+    pub evaluations: Vec<Evaluation>,
+    /// The final bracket of the search, which the optimum is in if the cost is
+    /// unimodal.
+    pub bracket: (usize, usize),
+    /// How many times the workload was run.
+    pub runs: usize,
+}
+
+/// Find the ratio in `[lo, hi]` minimizing the cost returned by `eval`, stopping
+/// once the bracket is at most `tol` wide. Every probe is measured once; then the
+/// ratios in the final bracket are measured until they have `trials` samples, one
+/// trial of each at a time so that drift affects them alike.
+pub fn golden_section_search<F>(
+    lo: usize,
+    hi: usize,
+    tol: usize,
+    trials: usize,
+    mut eval: F,
+) -> Result<SearchResult, failure::Error>
+where
+    F: FnMut(usize) -> Result<f64, failure::Error>,
+{
+    if lo >= hi {
+        return Err(failure::format_err!(
+            "The ratio range {}:{} is empty",
+            lo,
+            hi
+        ));
+    }
+
+    let mut evaluations: BTreeMap<usize, Evaluation> = BTreeMap::new();
+    let mut measure = |evaluations: &mut BTreeMap<usize, Evaluation>, ratio: usize| {
+        let e = evaluations.entry(ratio).or_insert(Evaluation {
+            ratio,
+            samples: Vec::new(),
+        });
+        let sample = eval(ratio)?;
+        println!(
+            "OPTIMIZE: ratio {} trial {}: {:.3}",
+            ratio,
+            e.samples.len(),
+            sample
+        );
+        e.samples.push(sample);
+        Ok::<_, failure::Error>(())
+    };
+    let mut cost = |evaluations: &mut BTreeMap<usize, Evaluation>, ratio: usize| {
+        if !evaluations.contains_key(&ratio) {
+            measure(evaluations, ratio)?;
+        }
+        Ok::<_, failure::Error>(evaluations[&ratio].mean())
+    };
+
+    // Probes closer than this can't be told apart by a search on integers, so the
+    // last few ratios are compared directly
+    let tol = tol.max(2);
+    let (mut a, mut b) = (lo, hi);
+    let step = ((5f64.sqrt() - 1.0) / 2.0 * (b - a) as f64).round() as usize;
+    let (mut c, mut d) = (b - step, a + step);
+    while b - a > tol {
+        // Rounding can make the probes meet on small brackets; keep them apart and
+        // inside it, which the loop condition leaves room for
+        if c >= d {
+            c = a + (b - a) / 2;
+            d = c + 1;
+        }
+
+        // Keep the surviving probe and mirror it, so each step costs one new ratio
+        if cost(&mut evaluations, c)? <= cost(&mut evaluations, d)? {
+            b = d;
+            d = c;
+            c = a + b - d;
+        } else {
+            a = c;
+            c = d;
+            d = a + b - c;
+        }
+    }
+    if b - a <= 2 {
+        for ratio in a..=b {
+            cost(&mut evaluations, ratio)?;
+        }
+    }
+    // The probes never reach the ends of the range, which is where the optimum is
+    // if e.g. all local is best
+    for end in [lo, hi] {
+        if (a..=b).contains(&end) {
+            cost(&mut evaluations, end)?;
+        }
+    }
+
+    // Repeat the ratios in the final bracket, or the best so far if none are in it
+    let mut confirmed: Vec<usize> = evaluations.range(a..=b).map(|(&r, _)| r).collect();
+    if confirmed.is_empty() {
+        let best = evaluations
+            .values()
+            .min_by(|x, y| x.mean().total_cmp(&y.mean()))
+            .unwrap();
+        confirmed.push(best.ratio);
+    }
+    for _ in 1..trials {
+        for &ratio in &confirmed {
+            measure(&mut evaluations, ratio)?;
+        }
+    }
+
+    let runs = evaluations.values().map(|e| e.samples.len()).sum();
+    let best = confirmed
+        .iter()
+        .map(|r| &evaluations[r])
+        .min_by(|x, y| x.mean().total_cmp(&y.mean()))
+        .unwrap()
+        .clone();
+
+    let comparable: Vec<usize> = confirmed
+        .iter()
+        .map(|r| &evaluations[r])
+        .filter(|e| {
+            let dof = (best.samples.len() - 1).min(e.samples.len() - 1);
+            let se = (best.std_err().powi(2) + e.std_err().powi(2)).sqrt();
+            e.mean() - best.mean() <= t_crit_95(dof) * se
+        })
+        .map(|e| e.ratio)
+        .collect();
+    let bounds = (
+        *comparable.iter().min().unwrap(),
+        *comparable.iter().max().unwrap(),
+    );
+
+    Ok(SearchResult {
+        best,
+        bounds,
+        evaluations: evaluations.into_values().collect(),
+        bracket: (a, b),
+        runs,
+    })
+}
@@ -0,0 +1,344 @@
+//! Sample memory accesses with `perf mem` (load latency PEBS on Intel) and work out
+//! which node and tier each sampled access was served from. The samples are
+//! grouped by the mapping of the data address and by the symbol of the code doing
+//! the access, so we can see which data structures ended up in the wrong tier.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use spurs::{cmd, Execute, SshShell};
+
+/// Sample every this many qualifying loads. A prime, so we don't alias with loops.
+const PERF_MEM_PERIOD: usize = 10007;
+
+/// Upper bounds, in cycles, of the latency buckets. The last bucket is the rest.
+const LATENCY_BUCKETS: &[u64] = &[64, 128, 256, 512, 1024];
+
+/// Where in the memory hierarchy a sampled access was served from, from the
+/// sample's data source.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Tier {
+    L1,
+    Lfb,
+    L2,
+    L3,
+    LocalRam,
+    RemoteRam,
+    RemoteCache,
+    Cxl,
+    Other,
+}
+
+const TIERS: &[(Tier, &str)] = &[
+    (Tier::L1, "L1"),
+    (Tier::Lfb, "LFB"),
+    (Tier::L2, "L2"),
+    (Tier::L3, "L3"),
+    (Tier::LocalRam, "Local RAM"),
+    (Tier::RemoteRam, "Remote RAM"),
+    (Tier::RemoteCache, "Remote Cache"),
+    (Tier::Cxl, "CXL"),
+    (Tier::Other, "Other"),
+];
+
+impl Tier {
+    /// Decode the mem_lvl and mem_lvl_num fields of a perf_mem_data_src.
+    fn from_data_src(data_src: u64) -> Self {
+        const LVLNUM_CXL: u64 = 0x09;
+
+        let lvl = (data_src >> 5) & 0x3fff;
+        let lvl_num = (data_src >> 33) & 0xf;
+        if lvl_num == LVLNUM_CXL {
+            Tier::Cxl
+        } else if lvl & 0x08 != 0 {
+            Tier::L1
+        } else if lvl & 0x10 != 0 {
+            Tier::Lfb
+        } else if lvl & 0x20 != 0 {
+            Tier::L2
+        } else if lvl & 0x40 != 0 {
+            Tier::L3
+        } else if lvl & 0x80 != 0 {
+            Tier::LocalRam
+        } else if lvl & 0x300 != 0 {
+            Tier::RemoteRam
+        } else if lvl & 0xc00 != 0 {
+            Tier::RemoteCache
+        } else {
+            Tier::Other
+        }
+    }
+}
+
+/// A command that waits for the process `pid` (a shell expression, see `pid_of`)
+/// and records its memory accesses to `data_file` until it exits or is killed.
+/// It has to run as root.
+pub fn perf_mem_record_cmd(pid: &str, data_file: &str) -> String {
+    format!(
+        "while [ -z \"{}\" ]; do sleep 0.1; done; \
+         exec perf mem --phys-data record -c {} -o {} --pid {}",
+        pid, PERF_MEM_PERIOD, data_file, pid
+    )
+}
+
+/// A command that copies the process's /proc/<pid>/maps to `maps_file` if it is
+/// running, so that the file has the mappings from just before it exited.
+/// `maps_file` is created empty until then, since the task only counts as
+/// started once it exists and the monitors start before the workloads.
+pub fn perf_mem_maps_cmd(pid: &str, maps_file: &str) -> String {
+    format!(
+        "touch {}; \
+         pid={}; \
+         if [ -n \"$pid\" ]; then sudo cat /proc/$pid/maps > {}.tmp && mv {}.tmp {}; fi",
+        maps_file, pid, maps_file, maps_file, maps_file
+    )
+}
+
+/// A command that writes the node of every memory block to `file`, as a line of
+/// `block_size <hex bytes>` followed by lines of `<node> <block index>`.
+pub fn perf_mem_nodes_cmd(file: &str) -> String {
+    format!(
+        "(echo block_size $(cat /sys/devices/system/memory/block_size_bytes); \
+         for n in /sys/devices/system/node/node[0-9]*; do \
+         for b in $n/memory[0-9]*; do echo ${{n##*node}} ${{b##*memory}}; done; \
+         done) > {}",
+        file
+    )
+}
+
+/// Dump the samples in `data_file` to `dump_file`, one per line.
+pub fn perf_mem_dump(
+    ushell: &SshShell,
+    data_file: &str,
+    dump_file: &str,
+) -> Result<(), failure::Error> {
+    ushell.run(cmd!(
+        "sudo perf mem -D -x , --phys-data -i {} report > {}",
+        data_file,
+        dump_file
+    ))?;
+    Ok(())
+}
+
+#[derive(Clone, Debug)]
+struct MemSample {
+    addr: u64,
+    phys_addr: u64,
+    /// In cycles.
+    weight: u64,
+    tier: Tier,
+    /// dso:symbol of the code doing the access.
+    symbol: String,
+}
+
+/// Parse the output of `perf mem -D -x ,`. Each line is
+/// `pid,tid,ip,addr,phys_addr,weight,data_src,dso:symbol`.
+fn parse_perf_mem_dump(contents: &str) -> Vec<MemSample> {
+    let hex = |s: &str| u64::from_str_radix(s.trim().trim_start_matches("0x"), 16).ok();
+
+    contents
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.splitn(8, ',').collect();
+            if fields.len() != 8 {
+                return None;
+            }
+
+            Some(MemSample {
+                addr: hex(fields[3])?,
+                phys_addr: hex(fields[4])?,
+                weight: fields[5].trim().parse().ok()?,
+                tier: Tier::from_data_src(hex(fields[6])?),
+                symbol: fields[7].trim().to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Map physical addresses to nodes using the output of `perf_mem_nodes_cmd`.
+struct NodeMap {
+    block_size: u64,
+    blocks: BTreeMap<u64, usize>,
+}
+
+impl NodeMap {
+    fn parse(contents: &str) -> Option<Self> {
+        let mut lines = contents.lines();
+        let block_size =
+            u64::from_str_radix(lines.next()?.strip_prefix("block_size ")?.trim(), 16).ok()?;
+        let blocks = lines
+            .filter_map(|line| {
+                let (node, block) = line.split_once(' ')?;
+                Some((block.trim().parse().ok()?, node.parse().ok()?))
+            })
+            .collect();
+
+        Some(NodeMap { block_size, blocks })
+    }
+
+    /// Not every sample has a physical address. perf reports 0 then.
+    fn node(&self, phys_addr: u64) -> Option<usize> {
+        if phys_addr == 0 {
+            return None;
+        }
+        self.blocks.get(&(phys_addr / self.block_size)).copied()
+    }
+}
+
+/// Name the mapping in /proc/<pid>/maps that `addr` falls in.
+fn region_of(maps: &[(u64, u64, String)], addr: u64) -> String {
+    match maps
+        .iter()
+        .find(|(start, end, _)| (*start..*end).contains(&addr))
+    {
+        Some((start, _, path)) if path.is_empty() => format!("[anon {:#x}]", start),
+        Some((_, _, path)) => path.clone(),
+        None => "[unknown]".into(),
+    }
+}
+
+fn parse_maps(contents: &str) -> Vec<(u64, u64, String)> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let (start, end) = fields.next()?.split_once('-')?;
+            let path = fields.nth(4).unwrap_or("").to_string();
+            Some((
+                u64::from_str_radix(start, 16).ok()?,
+                u64::from_str_radix(end, 16).ok()?,
+                path,
+            ))
+        })
+        .collect()
+}
+
+#[derive(Clone, Debug, Default)]
+struct Histogram {
+    samples: u64,
+    latency_sum: u64,
+    latency_buckets: [u64; LATENCY_BUCKETS.len() + 1],
+    nodes: BTreeMap<Option<usize>, u64>,
+    tiers: BTreeMap<Tier, u64>,
+}
+
+impl Histogram {
+    fn add(&mut self, sample: &MemSample, node: Option<usize>) {
+        self.samples += 1;
+        self.latency_sum += sample.weight;
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|&b| sample.weight < b)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.latency_buckets[bucket] += 1;
+        *self.nodes.entry(node).or_insert(0) += 1;
+        *self.tiers.entry(sample.tier).or_insert(0) += 1;
+    }
+}
+
+fn histograms_csv(
+    key: &str,
+    hists: &BTreeMap<String, Histogram>,
+    nodes: &BTreeSet<usize>,
+) -> String {
+    let mut csv = format!("{},Samples,Mean Latency (cycles)", key);
+    for node in nodes {
+        csv.push_str(&format!(",Node {}", node));
+    }
+    csv.push_str(",Unknown Node");
+    for (_, name) in TIERS {
+        csv.push_str(&format!(",{}", name));
+    }
+    let mut lo = 0;
+    for b in LATENCY_BUCKETS {
+        csv.push_str(&format!(",{}-{} cycles", lo, b));
+        lo = *b;
+    }
+    csv.push_str(&format!(",{}+ cycles\n", lo));
+
+    // Most sampled first
+    let mut hists: Vec<(&String, &Histogram)> = hists.iter().collect();
+    hists.sort_by_key(|(_, h)| std::cmp::Reverse(h.samples));
+    for (name, h) in hists {
+        // The keys can have commas in them, e.g. C++ symbols
+        csv.push_str(&format!(
+            "\"{}\",{},{:.1}",
+            name.replace('"', "\"\""),
+            h.samples,
+            h.latency_sum as f64 / h.samples as f64
+        ));
+        for node in nodes.iter().map(|&n| Some(n)).chain(std::iter::once(None)) {
+            csv.push_str(&format!(",{}", h.nodes.get(&node).unwrap_or(&0)));
+        }
+        for (tier, _) in TIERS {
+            csv.push_str(&format!(",{}", h.tiers.get(tier).unwrap_or(&0)));
+        }
+        for count in &h.latency_buckets {
+            csv.push_str(&format!(",{}", count));
+        }
+        csv.push('\n');
+    }
+
+    csv
+}
+
+/// Attribute the samples in `dump_file` to a node, using the block layout in
+/// `nodes_file`, and to a mapping, using `maps_file`. Write the per-region
+/// histograms to `regions_file` and the per-symbol ones to `symbols_file`.
+pub fn summarize_perf_mem(
+    ushell: &SshShell,
+    dump_file: &str,
+    nodes_file: &str,
+    maps_file: &str,
+    regions_file: &str,
+    symbols_file: &str,
+) -> Result<(), failure::Error> {
+    let samples = parse_perf_mem_dump(&ushell.run(cmd!("cat {}", dump_file))?.stdout);
+    if samples.is_empty() {
+        println!("WARNING: no perf mem samples in {}", dump_file);
+        return Ok(());
+    }
+    let node_map = NodeMap::parse(&ushell.run(cmd!("cat {}", nodes_file))?.stdout)
+        .ok_or_else(|| failure::format_err!("Could not parse {}", nodes_file))?;
+    let maps = parse_maps(&ushell.run(cmd!("cat {}", maps_file).allow_error())?.stdout);
+
+    let mut regions: BTreeMap<String, Histogram> = BTreeMap::new();
+    let mut symbols: BTreeMap<String, Histogram> = BTreeMap::new();
+    let mut total = Histogram::default();
+    for sample in &samples {
+        let node = node_map.node(sample.phys_addr);
+        regions
+            .entry(region_of(&maps, sample.addr))
+            .or_default()
+            .add(sample, node);
+        symbols
+            .entry(sample.symbol.clone())
+            .or_default()
+            .add(sample, node);
+        total.add(sample, node);
+    }
+
+    let nodes: BTreeSet<usize> = total.nodes.keys().filter_map(|&n| n).collect();
+    crate::write_remote_file(
+        ushell,
+        regions_file,
+        &histograms_csv("Region", &regions, &nodes),
+    )?;
+    crate::write_remote_file(
+        ushell,
+        symbols_file,
+        &histograms_csv("Symbol", &symbols, &nodes),
+    )?;
+
+    for (node, count) in &total.nodes {
+        let node = node.map_or("?".to_string(), |n| n.to_string());
+        println!(
+            "PERF MEM: {:.3} of {} sampled accesses on node {}",
+            *count as f64 / total.samples as f64,
+            total.samples,
+            node
+        );
+    }
+
+    Ok(())
+}
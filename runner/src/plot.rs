@@ -0,0 +1,751 @@
+//! Plot the time series the monitors of a run recorded as one SVG, with the charts
+//! stacked on a shared time axis: seconds since the run's timeline started. Samples
+//! without timestamps of their own are placed using the run's events, e.g. when
+//! their monitor started and stopped.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use clap::arg;
+
+use crate::manifest::Manifest;
+use crate::monitor::BWMON_INTERVAL_MS;
+use crate::results::{db_arg, find_manifest, find_run, open_db};
+
+const WIDTH: f64 = 1000.0;
+const CHART_HEIGHT: f64 = 200.0;
+const LEFT: f64 = 80.0;
+/// Room for the legend.
+const RIGHT: f64 = 170.0;
+const TOP: f64 = 40.0;
+const GAP: f64 = 70.0;
+
+const COLORS: &[&str] = &[
+    "#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd", "#8c564b", "#e377c2", "#7f7f7f",
+];
+
+/// The events marked on every chart.
+const MARKED_EVENTS: &[&str] = &[
+    "workload_start",
+    "workload_end",
+    "workload_kill",
+    "cipp_start",
+    "scheduled_action",
+];
+
+pub fn cli_options() -> clap::Command {
+    clap::Command::new("plot")
+        .about("Plot the monitors' time series of a run as SVG or PNG")
+        .arg_required_else_help(true)
+        .disable_version_flag(true)
+        .arg(arg!(<run> "The local directory of the run, or its ID or name in the index"))
+        .arg(db_arg())
+        .arg(arg!(--output <FILE>
+            "The SVG, or PNG if it ends in .png, to write. Default: timeseries.svg in the run's directory"))
+        .arg(
+            arg!(--moving_avg <SECS> "Smooth the bandwidth, latency and migration rates with a moving average")
+                .value_parser(clap::value_parser!(f64)),
+        )
+        .arg(
+            arg!(--window_max <SECS> "Plot the maximum of the bandwidth, latency and migration rates over a window")
+                .value_parser(clap::value_parser!(f64))
+                .conflicts_with("moving_avg"),
+        )
+}
+
+#[derive(Clone, Debug)]
+pub struct Event {
+    /// Seconds since the timeline started.
+    pub time: f64,
+    pub name: String,
+    pub details: serde_json::Value,
+}
+
+#[derive(Clone, Debug)]
+pub struct Series {
+    pub name: String,
+    pub points: Vec<(f64, f64)>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Chart {
+    pub title: &'static str,
+    pub y_label: &'static str,
+    pub series: Vec<Series>,
+    /// Hold each value until the next, rather than interpolating.
+    step: bool,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Smoothing {
+    MovingAvg(f64),
+    WindowMax(f64),
+}
+
+fn read_events(path: &Path) -> Result<Vec<Event>, failure::Error> {
+    std::fs::read_to_string(path)?
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|line| {
+            let details: serde_json::Value = serde_json::from_str(line)?;
+            Ok(Event {
+                time: details["mono"].as_f64().unwrap_or(0.0),
+                name: details["event"].as_str().unwrap_or_default().to_string(),
+                details,
+            })
+        })
+        .collect()
+}
+
+fn find_event<'a>(events: &'a [Event], name: &str, key: &str, value: &str) -> Option<&'a Event> {
+    events.iter().find(|e| {
+        e.name == name
+            && match &e.details[key] {
+                serde_json::Value::String(s) => s == value,
+                v => v.to_string() == value,
+            }
+    })
+}
+
+/// When the monitor `name` started and stopped, and the period of its task if it
+/// was periodic.
+fn monitor_span(events: &[Event], name: &str) -> Option<(f64, f64, Option<f64>)> {
+    let start = find_event(events, "monitor_start", "monitor", name)?;
+    let stop = find_event(events, "monitor_stop", "monitor", name)?;
+    Some((start.time, stop.time, start.details["period"].as_f64()))
+}
+
+/// The remote's wall clock time when the timeline started, in seconds, so that
+/// samples stamped with it can be put on the timeline.
+fn wall_zero(events: &[Event]) -> Option<f64> {
+    events
+        .iter()
+        .find_map(|e| Some(e.details["wall"].as_f64()? - e.time))
+}
+
+/// `n` sample times evenly spread over `start..end`, each at the end of its
+/// interval.
+fn spread(n: usize, start: f64, end: f64) -> Vec<f64> {
+    let dt = (end - start) / n.max(1) as f64;
+    (1..=n).map(|i| start + i as f64 * dt).collect()
+}
+
+/// The number following `word` in `line`, ignoring case.
+fn value_after(line: &str, word: &str) -> Option<f64> {
+    let mut words = line.split_whitespace();
+    words.find(|w| w.trim_end_matches(':').eq_ignore_ascii_case(word))?;
+    words.next()?.parse().ok()
+}
+
+/// The file name of `path` with the run's name stripped, e.g. `clover`.
+pub fn file_label(path: &Path, run_name: &str) -> String {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    file_name
+        .strip_prefix(run_name)
+        .unwrap_or(&file_name)
+        .trim_start_matches(['.', '-', '_'])
+        .to_string()
+}
+
+/// bwmon prints the remote's wall clock time and then the bandwidth of each node
+/// every interval, from when the first workload starts until it ends.
+fn bandwidth_chart(path: &Path, events: &[Event]) -> Result<Chart, failure::Error> {
+    let mut nodes: Vec<Vec<(Option<f64>, f64)>> = vec![];
+    let mut time = None;
+    for line in std::fs::read_to_string(path)?.lines() {
+        if line.starts_with("Time:") {
+            time = value_after(line, "time");
+        }
+        // Node <N>: Read <MB/s> Write <MB/s> Total <MB/s> MB/s
+        let node = line
+            .strip_prefix("Node ")
+            .and_then(|l| l.split(':').next())
+            .and_then(|n| n.parse::<usize>().ok());
+        if let (Some(node), Some(total)) = (node, value_after(line, "Total")) {
+            if nodes.len() <= node {
+                nodes.resize(node + 1, vec![]);
+            }
+            nodes[node].push((time, total / 1024.0));
+        }
+    }
+
+    // Older runs of bwmon didn't stamp their samples. It starts sampling as soon
+    // as its monitor starts, or once the workload it waits for does.
+    let zero = wall_zero(events);
+    let stamped = nodes.iter().flatten().all(|(time, _)| time.is_some());
+    let start = find_event(events, "monitor_start", "monitor", "bwmon")
+        .ok_or_else(|| failure::format_err!("No events to place the bwmon samples"))?
+        .time;
+    let start = match find_event(events, "workload_start", "wkld", "0") {
+        Some(wkld) if wkld.time > start => wkld.time,
+        _ => start,
+    };
+    let interval = BWMON_INTERVAL_MS as f64 / 1000.0;
+    let place = |i: usize, time: Option<f64>| match (time, zero) {
+        (Some(time), Some(zero)) if stamped => time - zero,
+        _ => start + (i + 1) as f64 * interval,
+    };
+
+    Ok(Chart {
+        title: "Bandwidth per node",
+        y_label: "GB/s",
+        series: nodes
+            .into_iter()
+            .enumerate()
+            .filter(|(_, bws)| !bws.is_empty())
+            .map(|(node, bws)| Series {
+                name: format!("Node {}", node),
+                points: bws
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, (time, bw))| (place(i, time), bw))
+                    .collect(),
+            })
+            .collect(),
+        step: false,
+    })
+}
+
+/// Colloid's view of the local and remote latency, sampled every period by the
+/// colloid_latency monitor with the remote's wall clock time, or printed
+/// continuously by memlat without one.
+fn latency_chart(path: &Path, events: &[Event]) -> Result<Chart, failure::Error> {
+    let mut samples = vec![];
+    for line in std::fs::read_to_string(path)?.lines() {
+        let local = value_after(line, "local");
+        let remote = value_after(line, "remote");
+        if let (Some(local), Some(remote)) = (local, remote) {
+            samples.push((value_after(line, "time"), local, remote));
+        }
+    }
+
+    // Older runs of colloid_latency didn't stamp their samples either
+    let times: Vec<f64> = if samples.iter().all(|(time, _, _)| time.is_some()) {
+        let zero = wall_zero(events)
+            .ok_or_else(|| failure::format_err!("No events to place the latency samples"))?;
+        samples.iter().filter_map(|(time, _, _)| Some(time? - zero)).collect()
+    } else {
+        let (start, end, _) = monitor_span(events, "memlat")
+            .or_else(|| monitor_span(events, "colloid_latency"))
+            .ok_or_else(|| failure::format_err!("No events to place the latency samples"))?;
+        spread(samples.len(), start, end)
+    };
+    let (local, remote): (Vec<f64>, Vec<f64>) =
+        samples.into_iter().map(|(_, local, remote)| (local, remote)).unzip();
+
+    Ok(Chart {
+        title: "Colloid latency",
+        y_label: "Cycles",
+        series: vec![
+            Series {
+                name: "Local".into(),
+                points: times.iter().copied().zip(local).collect(),
+            },
+            Series {
+                name: "Remote".into(),
+                points: times.iter().copied().zip(remote).collect(),
+            },
+        ],
+        step: false,
+    })
+}
+
+/// CIPP prints its settings when it starts, then the ratio it targets after each
+/// adjust interval along with the remote's wall clock time. It may have been
+/// started more than once by the schedule, each time appending to the same file.
+fn cipp_chart(path: &Path, events: &[Event]) -> Result<Chart, failure::Error> {
+    let zero = wall_zero(events);
+    let starts: Vec<f64> = events
+        .iter()
+        .filter(|e| {
+            e.name == "cipp_start"
+                || (e.name == "scheduled_action" && e.details["action"] == "StartCipp")
+        })
+        .map(|e| e.time)
+        .collect();
+
+    let mut points = vec![];
+    let mut run: Option<(f64, f64, usize)> = None;
+    let mut runs = 0;
+    for line in std::fs::read_to_string(path)?.lines() {
+        if line.starts_with("Running with") {
+            run = starts.get(runs).map(|&start| (start, 0.0, 0));
+            runs += 1;
+            if let Some((start, _, _)) = run {
+                // Everything starts out local
+                points.push((start, 100.0));
+            }
+        } else if let Some(ms) = line
+            .trim()
+            .strip_prefix("Adjust interval:")
+            .and_then(|l| l.trim_end_matches("ms").trim().parse::<f64>().ok())
+        {
+            if let Some((_, adjust, _)) = run.as_mut() {
+                *adjust = ms / 1000.0;
+            }
+        } else if let (Some(ratio), Some((start, adjust, n))) =
+            (value_after(line, "ratio"), run.as_mut())
+        {
+            *n += 1;
+            // Older runs of CIPP didn't stamp their ratios
+            let time = match (value_after(line, "time"), zero) {
+                (Some(time), Some(zero)) => time - zero,
+                _ => *start + *n as f64 * *adjust,
+            };
+            points.push((time, ratio));
+        }
+    }
+
+    Ok(Chart {
+        title: "CIPP interleave ratio",
+        y_label: "% local",
+        series: vec![Series {
+            name: "Target ratio".into(),
+            points,
+        }],
+        step: true,
+    })
+}
+
+/// The migration rates from the vmstat series, which is already on the timeline.
+fn migration_chart(path: &Path) -> Result<Chart, failure::Error> {
+    let contents = std::fs::read_to_string(path)?;
+    let mut lines = contents.lines();
+    let header: Vec<&str> = lines.next().unwrap_or_default().split(',').collect();
+    let rows: Vec<Vec<f64>> = lines
+        .filter(|l| !l.is_empty())
+        .map(|l| l.split(',').map(|f| f.parse().unwrap_or(0.0)).collect())
+        .collect();
+
+    let rates: &[(&str, &[&str])] = &[
+        ("Promotions", &["pgpromote_success/s"]),
+        (
+            "Demotions",
+            &[
+                "pgdemote_kswapd/s",
+                "pgdemote_direct/s",
+                "pgdemote_khugepaged/s",
+            ],
+        ),
+        ("Migrations", &["pgmigrate_success/s"]),
+    ];
+    let series = rates
+        .iter()
+        .filter_map(|(name, columns)| {
+            let columns: Vec<usize> = columns
+                .iter()
+                .filter_map(|c| header.iter().position(|h| h == c))
+                .collect();
+            if columns.is_empty() {
+                return None;
+            }
+            Some(Series {
+                name: name.to_string(),
+                points: rows
+                    .iter()
+                    .map(|row| {
+                        let rate = columns.iter().filter_map(|&c| row.get(c)).sum();
+                        (row[0], rate)
+                    })
+                    .collect(),
+            })
+        })
+        .collect();
+
+    Ok(Chart {
+        title: "Migration rate",
+        y_label: "Pages/s",
+        series,
+        step: false,
+    })
+}
+
+/// How far each workload that reports its progress got: CloverLeaf prints the
+/// time at every step and GAPBS the time each trial took.
+fn progress_chart(
+    paths: &[PathBuf],
+    run_name: &str,
+    events: &[Event],
+) -> Result<Chart, failure::Error> {
+    let start = events
+        .iter()
+        .find(|e| e.name == "workload_start")
+        .map_or(0.0, |e| e.time);
+
+    let mut series = vec![];
+    for path in paths {
+        let contents = std::fs::read_to_string(path)?;
+        let wall_clock: Vec<f64> = contents
+            .lines()
+            .filter(|l| l.contains("Wall clock"))
+            .filter_map(|l| value_after(l, "clock"))
+            .collect();
+        let done = if !wall_clock.is_empty() {
+            wall_clock
+        } else {
+            contents
+                .lines()
+                .filter_map(|l| value_after(l, "Time").filter(|_| l.starts_with("Trial Time")))
+                .scan(0.0, |total, t| {
+                    *total += t;
+                    Some(*total)
+                })
+                .collect()
+        };
+        if done.is_empty() {
+            continue;
+        }
+
+        let n = done.len() as f64;
+        let mut points = vec![(start, 0.0)];
+        points.extend(
+            done.iter()
+                .enumerate()
+                .map(|(i, t)| (start + t, (i + 1) as f64 / n * 100.0)),
+        );
+        series.push(Series {
+            name: file_label(path, run_name),
+            points,
+        });
+    }
+
+    Ok(Chart {
+        title: "Workload progress",
+        y_label: "% done",
+        series,
+        step: true,
+    })
+}
+
+/// Apply `smoothing` over a trailing window of time.
+fn smooth(points: &[(f64, f64)], smoothing: Smoothing) -> Vec<(f64, f64)> {
+    let secs = match smoothing {
+        Smoothing::MovingAvg(secs) | Smoothing::WindowMax(secs) => secs,
+    };
+    let mut first = 0;
+    points
+        .iter()
+        .enumerate()
+        .map(|(i, &(t, _))| {
+            while first < i && points[first].0 <= t - secs {
+                first += 1;
+            }
+            let window = points[first..=i].iter().map(|(_, v)| *v);
+            let value = match smoothing {
+                Smoothing::MovingAvg(_) => window.sum::<f64>() / (i + 1 - first) as f64,
+                Smoothing::WindowMax(_) => window.fold(f64::MIN, f64::max),
+            };
+            (t, value)
+        })
+        .collect()
+}
+
+/// A round step that divides `range` into about `target` parts.
+fn nice_step(range: f64, target: f64) -> f64 {
+    let raw = (range / target).max(f64::MIN_POSITIVE);
+    let mag = 10f64.powf(raw.log10().floor());
+    let norm = raw / mag;
+    mag * if norm <= 1.0 {
+        1.0
+    } else if norm <= 2.0 {
+        2.0
+    } else if norm <= 5.0 {
+        5.0
+    } else {
+        10.0
+    }
+}
+
+fn format_tick(value: f64, step: f64) -> String {
+    let decimals = (-step.log10().floor()).max(0.0) as usize;
+    format!("{:.*}", decimals, value)
+}
+
+pub fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+pub fn render_svg(title: &str, charts: &[Chart], events: &[Event]) -> String {
+    let marked: Vec<&Event> = events
+        .iter()
+        .filter(|e| MARKED_EVENTS.contains(&e.name.as_str()))
+        .collect();
+    let x_max = charts
+        .iter()
+        .flat_map(|c| c.series.iter().flat_map(|s| s.points.iter().map(|p| p.0)))
+        .chain(events.iter().map(|e| e.time))
+        .fold(1.0, f64::max);
+    let x_step = nice_step(x_max, 10.0);
+    let x_max = (x_max / x_step).ceil() * x_step;
+    let plot_width = WIDTH - LEFT - RIGHT;
+    let x = |t: f64| LEFT + t / x_max * plot_width;
+
+    let height = TOP + charts.len() as f64 * (CHART_HEIGHT + GAP);
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\" \
+         font-family=\"sans-serif\" font-size=\"11\">\n\
+         <rect width=\"{w}\" height=\"{h}\" fill=\"white\"/>\n\
+         <text x=\"{cx}\" y=\"20\" text-anchor=\"middle\" font-size=\"15\">{t}</text>\n",
+        w = WIDTH,
+        h = height,
+        cx = WIDTH / 2.0,
+        t = escape_xml(title)
+    );
+
+    for (i, chart) in charts.iter().enumerate() {
+        let top = TOP + i as f64 * (CHART_HEIGHT + GAP) + 20.0;
+        let bottom = top + CHART_HEIGHT;
+        let y_max = chart
+            .series
+            .iter()
+            .flat_map(|s| s.points.iter().map(|p| p.1))
+            .fold(0.0, f64::max);
+        let y_step = nice_step(if y_max > 0.0 { y_max } else { 1.0 }, 5.0);
+        let y_max = (y_max / y_step).ceil().max(1.0) * y_step;
+        let y = |v: f64| bottom - v / y_max * CHART_HEIGHT;
+
+        svg.push_str(&format!(
+            "<text x=\"{}\" y=\"{}\" font-size=\"13\" font-weight=\"bold\">{}</text>\n\
+             <text transform=\"translate({},{}) rotate(-90)\" text-anchor=\"middle\">{}</text>\n\
+             <rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"none\" stroke=\"black\"/>\n",
+            LEFT,
+            top - 6.0,
+            escape_xml(chart.title),
+            LEFT - 55.0,
+            top + CHART_HEIGHT / 2.0,
+            escape_xml(chart.y_label),
+            LEFT,
+            top,
+            plot_width,
+            CHART_HEIGHT
+        ));
+
+        // Every chart gets the same ticks, so they line up
+        let mut t = 0.0;
+        while t <= x_max + x_step / 2.0 {
+            svg.push_str(&format!(
+                "<line x1=\"{x}\" y1=\"{b}\" x2=\"{x}\" y2=\"{t}\" stroke=\"#eee\"/>\n\
+                 <text x=\"{x}\" y=\"{ty}\" text-anchor=\"middle\">{l}</text>\n",
+                x = x(t),
+                b = bottom,
+                t = top,
+                ty = bottom + 14.0,
+                l = format_tick(t, x_step)
+            ));
+            t += x_step;
+        }
+        let mut v = 0.0;
+        while v <= y_max + y_step / 2.0 {
+            svg.push_str(&format!(
+                "<line x1=\"{l}\" y1=\"{y}\" x2=\"{r}\" y2=\"{y}\" stroke=\"#eee\"/>\n\
+                 <text x=\"{tx}\" y=\"{ty}\" text-anchor=\"end\">{label}</text>\n",
+                l = LEFT,
+                r = LEFT + plot_width,
+                y = y(v),
+                tx = LEFT - 5.0,
+                ty = y(v) + 4.0,
+                label = format_tick(v, y_step)
+            ));
+            v += y_step;
+        }
+        svg.push_str(&format!(
+            "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">Time (s)</text>\n",
+            LEFT + plot_width / 2.0,
+            bottom + 30.0
+        ));
+
+        for e in &marked {
+            svg.push_str(&format!(
+                "<line x1=\"{x}\" y1=\"{t}\" x2=\"{x}\" y2=\"{b}\" stroke=\"#999\" stroke-dasharray=\"4,3\">\
+                 <title>{n} {d}</title></line>\n",
+                x = x(e.time),
+                t = top,
+                b = bottom,
+                n = escape_xml(&e.name),
+                d = escape_xml(&e.details.to_string())
+            ));
+        }
+
+        for (j, series) in chart.series.iter().enumerate() {
+            let color = COLORS[j % COLORS.len()];
+            let mut points = vec![];
+            for (k, &(t, v)) in series.points.iter().enumerate() {
+                if chart.step && k > 0 {
+                    points.push(format!("{:.1},{:.1}", x(t), y(series.points[k - 1].1)));
+                }
+                points.push(format!("{:.1},{:.1}", x(t), y(v)));
+            }
+            svg.push_str(&format!(
+                "<polyline fill=\"none\" stroke=\"{}\" stroke-width=\"1.5\" points=\"{}\"/>\n\
+                 <line x1=\"{lx}\" y1=\"{ly}\" x2=\"{lx2}\" y2=\"{ly}\" stroke=\"{}\" stroke-width=\"3\"/>\n\
+                 <text x=\"{tx}\" y=\"{ty}\">{}</text>\n",
+                color,
+                points.join(" "),
+                color,
+                escape_xml(&series.name),
+                lx = LEFT + plot_width + 10.0,
+                lx2 = LEFT + plot_width + 30.0,
+                ly = top + 10.0 + j as f64 * 16.0,
+                tx = LEFT + plot_width + 35.0,
+                ty = top + 14.0 + j as f64 * 16.0,
+            ));
+        }
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// The local directory of the run `key`: a path, or an ID or name in the index.
+pub fn resolve_run(key: &str, db: &Path) -> Result<PathBuf, failure::Error> {
+    let path = PathBuf::from(key);
+    if path.is_dir() {
+        return Ok(path);
+    }
+    match find_run(&open_db(db)?, key)? {
+        Some(run) => Ok(PathBuf::from(run.source)),
+        None => Err(failure::format_err!(
+            "No run directory or indexed run {}",
+            key
+        )),
+    }
+}
+
+/// The charts of the run in `run_dir` that it has the data for, and its events.
+pub fn run_charts(
+    run_dir: &Path,
+    manifest: &Manifest,
+    smoothing: Option<Smoothing>,
+) -> Result<(Vec<Chart>, Vec<Event>), failure::Error> {
+    let run_name = run_dir
+        .file_name()
+        .map_or(String::new(), |n| n.to_string_lossy().into_owned());
+    let events = match manifest.local_files(run_dir, "timeline").first() {
+        Some(path) => read_events(path)?,
+        None => {
+            return Err(failure::format_err!(
+                "{} has no timeline",
+                run_dir.display()
+            ))
+        }
+    };
+    let monitor_file = |monitor: &str, suffix: &str| {
+        manifest
+            .local_files(run_dir, &format!("monitor:{}", monitor))
+            .into_iter()
+            .find(|p| p.to_string_lossy().ends_with(suffix))
+    };
+
+    let mut charts = vec![];
+    if let Some(path) = monitor_file("bwmon", "bwmon") {
+        charts.push(bandwidth_chart(&path, &events)?);
+    }
+    if let Some(path) = manifest.local_files(run_dir, "cipp").first() {
+        charts.push(cipp_chart(path, &events)?);
+    }
+    if let Some(path) = monitor_file("colloid_latency", "colloid.lat")
+        .or_else(|| monitor_file("memlat", "colloid.lat"))
+    {
+        charts.push(latency_chart(&path, &events)?);
+    }
+    if let Some(path) = monitor_file("vmstat", "vmstat_series") {
+        charts.push(migration_chart(&path)?);
+    }
+    charts.push(progress_chart(
+        &manifest.local_files(run_dir, "workload"),
+        &run_name,
+        &events,
+    )?);
+
+    charts.retain(|c| c.series.iter().any(|s| !s.points.is_empty()));
+    if let Some(smoothing) = smoothing {
+        for chart in charts.iter_mut().filter(|c| !c.step) {
+            for series in chart.series.iter_mut() {
+                series.points = smooth(&series.points, smoothing);
+            }
+        }
+    }
+
+    Ok((charts, events))
+}
+
+pub fn run(sub_m: &clap::ArgMatches) -> Result<(), failure::Error> {
+    let db = PathBuf::from(sub_m.get_one::<String>("db").unwrap());
+    let run_dir = resolve_run(sub_m.get_one::<String>("run").unwrap(), &db)?;
+    let manifest_path = find_manifest(&run_dir)?
+        .ok_or_else(|| failure::format_err!("{} has no manifest to plot", run_dir.display()))?;
+    let manifest: Manifest = serde_json::from_str(&std::fs::read_to_string(&manifest_path)?)?;
+    let run_name = run_dir
+        .file_name()
+        .map_or(String::new(), |n| n.to_string_lossy().into_owned());
+    let smoothing = match (
+        sub_m.get_one::<f64>("moving_avg"),
+        sub_m.get_one::<f64>("window_max"),
+    ) {
+        (Some(&secs), _) => Some(Smoothing::MovingAvg(secs)),
+        (_, Some(&secs)) => Some(Smoothing::WindowMax(secs)),
+        _ => None,
+    };
+
+    let (charts, events) = run_charts(&run_dir, &manifest, smoothing)?;
+    if charts.is_empty() {
+        return Err(failure::format_err!(
+            "Nothing to plot in {}",
+            run_dir.display()
+        ));
+    }
+
+    let output = sub_m
+        .get_one::<String>("output")
+        .map_or_else(|| run_dir.join("timeseries.svg"), PathBuf::from);
+    let svg = render_svg(&run_name, &charts, &events);
+    if output
+        .extension()
+        .map_or(false, |ext| ext.eq_ignore_ascii_case("png"))
+    {
+        write_png(&svg, &output)?;
+    } else {
+        std::fs::write(&output, svg)?;
+    }
+    println!("RESULTS: {}", output.display());
+
+    Ok(())
+}
+
+/// Rasterize `svg` to the PNG `output` with rsvg-convert, from librsvg.
+fn write_png(svg: &str, output: &Path) -> Result<(), failure::Error> {
+    let mut child = Command::new("rsvg-convert")
+        .args(["--format", "png", "--background-color", "white", "--output"])
+        .arg(output)
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| {
+            failure::format_err!(
+                "Could not run rsvg-convert to write {}, install librsvg or write an SVG: {}",
+                output.display(),
+                e
+            )
+        })?;
+    // Dropping stdin closes it, so rsvg-convert sees the end of the SVG
+    child
+        .stdin
+        .take()
+        .ok_or_else(|| failure::format_err!("Could not write to rsvg-convert"))?
+        .write_all(svg.as_bytes())?;
+
+    let out = child.wait_with_output()?;
+    if !out.status.success() {
+        return Err(failure::format_err!(
+            "rsvg-convert could not write {}: {}",
+            output.display(),
+            String::from_utf8_lossy(&out.stderr)
+        ));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,226 @@
+//! Configure, build and run Quartz to emulate slower memory.
+
+use libscail::dir;
+
+use serde::{Deserialize, Serialize};
+
+use spurs::{cmd, Execute, SshShell, SshSpawnHandle};
+use spurs_util::escape_for_bash;
+
+use crate::bw_model::BwModel;
+
+const QUARTZ_INI: &str = "/tmp/nvmemul.ini";
+const QUARTZ_PID_FILE: &str = "/tmp/quartz_throttle.pid";
+/// Where we record the hash of the sources Quartz was last built from.
+const QUARTZ_SRC_HASH_FILE: &str = "build/.src_hash";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QuartzLatency {
+    /// Target read latency in ns.
+    pub read_ns: u64,
+    /// Target write latency in ns.
+    pub write_ns: u64,
+    /// How often Quartz injects delays, in us.
+    pub epoch_us: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QuartzConfig {
+    /// Target read bandwidth in MB/s.
+    pub read_bw: u64,
+    /// Target write bandwidth in MB/s.
+    pub write_bw: u64,
+    /// Emulate higher latency too. This only affects processes Quartz is
+    /// preloaded into, so the workloads are run under Quartz when it is set.
+    pub latency: Option<QuartzLatency>,
+    /// Where Quartz caches the throttle register to bandwidth map. Defaults to
+    /// the user's home directory so it persists between reboots.
+    pub model_file: Option<String>,
+    /// Where Quartz caches the memory controller PCI addresses. Defaults to the
+    /// user's home directory so it persists between reboots.
+    pub mc_pci_file: Option<String>,
+    /// A local bandwidth model, e.g. from calibrate_bw, to copy to `model_file`
+    /// instead of having Quartz measure its own.
+    #[serde(default)]
+    pub local_model: Option<String>,
+    pub debug_level: u64,
+}
+
+impl QuartzConfig {
+    fn to_ini(&self, model_file: &str, mc_pci_file: &str) -> String {
+        let latency = match &self.latency {
+            Some(lat) => format!(
+                "latency:\n{{\n    enable = true;\n    inject_delay = true;\n    \
+                 read = {};\n    write = {};\n    max_epoch_duration_us = {};\n    \
+                 min_epoch_duration_us = {};\n    calibration = false;\n}};\n",
+                lat.read_ns, lat.write_ns, lat.epoch_us, lat.epoch_us
+            ),
+            None => "latency:\n{\n    enable = false;\n};\n".into(),
+        };
+
+        format!(
+            "# Generated by runner\n\n\
+             {}\n\
+             bandwidth:\n{{\n    enable = true;\n    model = \"{}\";\n    \
+             read = {};\n    write = {};\n}};\n\n\
+             topology:\n{{\n    mc_pci = \"{}\";\n    physical_nodes = \"0,1\";\n    \
+             hyperthreading = true;\n}};\n\n\
+             statistics:\n{{\n    enable = false;\n}};\n\n\
+             debug:\n{{\n    level = {};\n    verbose = 0;\n    \
+             module:\n    {{\n        all = False;\n    }};\n}};\n",
+            latency,
+            model_file,
+            self.read_bw,
+            self.write_bw,
+            mc_pci_file,
+            self.debug_level
+        )
+    }
+}
+
+/// The library that loads Quartz into a process, and the environment variables
+/// it needs.
+pub fn quartz_preload(quartz_dir: &str) -> (String, String) {
+    (
+        dir!(quartz_dir, "build/src/lib/libnvmemul.so"),
+        format!("NVMEMUL_INI={}", QUARTZ_INI),
+    )
+}
+
+/// The environment variables that load Quartz into a process.
+fn quartz_envs(quartz_dir: &str) -> String {
+    let (lib, envs) = quartz_preload(quartz_dir);
+    format!("LD_PRELOAD={} {}", lib, envs)
+}
+
+/// Build Quartz if its sources changed since the last build. We can't do this in
+/// setup_wkspc because it requires the kernel being installed.
+pub fn build_quartz(ushell: &SshShell, quartz_dir: &str) -> Result<(), failure::Error> {
+    let hash = ushell
+        .run(
+            cmd!("find src CMakeLists.txt -type f | sort | xargs sha1sum | sha1sum")
+                .cwd(quartz_dir),
+        )?
+        .stdout;
+    let hash = hash.trim();
+    let old_hash = ushell
+        .run(cmd!("cat {} 2>/dev/null", QUARTZ_SRC_HASH_FILE).cwd(quartz_dir).allow_error())?
+        .stdout;
+    let built = ushell
+        .run(cmd!("test -f build/src/lib/libnvmemul.so").cwd(quartz_dir))
+        .is_ok();
+
+    if built && old_hash.trim() == hash {
+        println!("Quartz is up to date");
+        return Ok(());
+    }
+
+    ushell.run(cmd!("make all").cwd(dir!(quartz_dir, "build")))?;
+    ushell.run(cmd!("echo '{}' > {}", hash, QUARTZ_SRC_HASH_FILE).cwd(quartz_dir))?;
+
+    Ok(())
+}
+
+/// A process holding the Quartz bandwidth throttle. The throttle lasts until this
+/// is stopped or dropped, so an early return from the experiment also ends it.
+pub struct QuartzThrottle<'s> {
+    ushell: &'s SshShell,
+    handle: Option<SshSpawnHandle>,
+}
+
+impl QuartzThrottle<'_> {
+    /// Kill the throttling process.
+    pub fn stop(mut self) -> Result<(), failure::Error> {
+        self.kill()
+    }
+
+    fn kill(&mut self) -> Result<(), failure::Error> {
+        if let Some(handle) = self.handle.take() {
+            self.ushell
+                .run(cmd!("kill $(cat {}) && rm {}", QUARTZ_PID_FILE, QUARTZ_PID_FILE))?;
+            // Being killed makes the command fail, which is expected
+            let _ = handle.join();
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for QuartzThrottle<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = self.kill() {
+            println!("Failed to stop Quartz: {}", e);
+        }
+    }
+}
+
+/// Configure Quartz with `cfg` and start throttling the memory bandwidth.
+pub fn start_quartz<'s>(
+    ushell: &'s SshShell,
+    quartz_dir: &str,
+    user_home: &str,
+    cfg: &QuartzConfig,
+) -> Result<QuartzThrottle<'s>, failure::Error> {
+    let model_file = cfg
+        .model_file
+        .clone()
+        .unwrap_or_else(|| dir!(user_home, "bandwidth_model"));
+    let mc_pci_file = cfg
+        .mc_pci_file
+        .clone()
+        .unwrap_or_else(|| dir!(user_home, "mc_pci_bus"));
+    let envs = quartz_envs(quartz_dir);
+
+    build_quartz(ushell, quartz_dir)?;
+
+    if let Some(local_model) = &cfg.local_model {
+        let model = BwModel::load(local_model)?;
+        model.check_knob("quartz")?;
+        crate::write_remote_file(ushell, &model_file, &model.format())?;
+    }
+
+    ushell.run(cmd!(
+        "echo {} > {}",
+        escape_for_bash(&cfg.to_ini(&model_file, &mc_pci_file)),
+        QUARTZ_INI
+    ))?;
+    // Log the config we are using
+    ushell.run(cmd!("cat {}", QUARTZ_INI))?;
+
+    // Load the kernel module
+    ushell.run(cmd!("sudo {}/scripts/setupdev.sh load", quartz_dir))?;
+    // Gotta do some permission stuff
+    ushell.run(cmd!("echo 2 | sudo tee /sys/devices/cpu/rdpmc"))?;
+
+    // The first runs of Quartz populate the model and PCI cache files, so only
+    // do them if the files don't exist yet
+    for _ in 0..2 {
+        let cached = ushell
+            .run(cmd!("test -s {} && test -s {}", &model_file, &mc_pci_file))
+            .is_ok();
+        if cached {
+            break;
+        }
+        ushell.run(cmd!("{} true", envs))?;
+    }
+
+    // Keep a process with Quartz loaded running until the experiment ends. Only
+    // sleep gets Quartz loaded; bash just records the pid, which exec keeps.
+    let handle = ushell.spawn(cmd!(
+        "bash -c 'echo $$ > {}; exec env {} sleep infinity'",
+        QUARTZ_PID_FILE,
+        envs
+    ))?;
+    let throttle = QuartzThrottle {
+        ushell,
+        handle: Some(handle),
+    };
+
+    ushell.run(cmd!(
+        "for i in $(seq 30); do test -s {} && kill -0 $(cat {}) && exit 0; sleep 1; done; exit 1",
+        QUARTZ_PID_FILE,
+        QUARTZ_PID_FILE
+    ))?;
+
+    Ok(throttle)
+}
@@ -0,0 +1,158 @@
+//! Measure energy with the RAPL counters exposed by powercap. Each socket has a
+//! package domain, and usually a DRAM domain under it.
+
+use std::collections::BTreeMap;
+
+use spurs::{cmd, Execute, SshShell};
+
+const POWERCAP_DIR: &str = "/sys/class/powercap";
+
+#[derive(Clone, Debug)]
+struct RaplDomain {
+    /// The powercap zone, e.g. intel-rapl:0:1.
+    zone: String,
+    /// e.g. package-0 or package-0.dram
+    label: String,
+    /// The counter wraps to 0 after this many uJ.
+    max_range_uj: u64,
+}
+
+/// A command that writes a line of `<zone> <label> <max_energy_range_uj>` to
+/// `domains_file` for each package and DRAM domain.
+pub fn rapl_domains_cmd(domains_file: &str) -> String {
+    format!(
+        "for z in {}/intel-rapl:*; do \
+         name=$(cat $z/name); \
+         case ${{z##*/}} in intel-rapl:*:*) name=$(cat ${{z%:*}}/name).$name;; esac; \
+         case $name in package-*|*.dram) echo ${{z##*/}} $name $(sudo cat $z/max_energy_range_uj);; esac; \
+         done > {}",
+        POWERCAP_DIR, domains_file
+    )
+}
+
+/// A command that appends a line of `time <secs> <zone>=<energy_uj> ...` to
+/// `samples_file` for each domain in `domains_file`.
+pub fn rapl_sample_cmd(domains_file: &str, samples_file: &str) -> String {
+    format!(
+        "(echo -n \"time $(date +%s.%N)\"; \
+         for z in $(cut -d' ' -f1 {}); do echo -n \" $z=$(sudo cat {}/$z/energy_uj)\"; done; \
+         echo) >> {}",
+        domains_file, POWERCAP_DIR, samples_file
+    )
+}
+
+fn parse_rapl_domains(contents: &str) -> Vec<RaplDomain> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            Some(RaplDomain {
+                zone: fields.next()?.to_string(),
+                label: fields.next()?.to_string(),
+                max_range_uj: fields.next()?.parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+fn parse_rapl_samples(contents: &str) -> Vec<(f64, BTreeMap<String, u64>)> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            if fields.next()? != "time" {
+                return None;
+            }
+            let time = fields.next()?.parse::<f64>().ok()?;
+            let counters = fields
+                .filter_map(|f| {
+                    let (zone, uj) = f.split_once('=')?;
+                    Some((zone.to_string(), uj.parse::<u64>().ok()?))
+                })
+                .collect();
+
+            Some((time, counters))
+        })
+        .collect()
+}
+
+/// The energy used between two readings of a counter, in uJ, allowing for the
+/// counter wrapping around once in between.
+fn energy_delta(prev: u64, cur: u64, max_range_uj: u64) -> u64 {
+    if cur >= prev {
+        cur - prev
+    } else {
+        max_range_uj - prev + cur
+    }
+}
+
+/// Turn the samples into the average power of each domain over time, in seconds
+/// since the wall clock time `time_zero`, written as a CSV to `series_file`, and
+/// the total energy and average power of each domain over the whole run, written
+/// to `energy_file`.
+pub fn summarize_rapl(
+    ushell: &SshShell,
+    domains_file: &str,
+    samples_file: &str,
+    series_file: &str,
+    energy_file: &str,
+    time_zero: f64,
+) -> Result<(), failure::Error> {
+    let domains = parse_rapl_domains(&ushell.run(cmd!("cat {}", domains_file))?.stdout);
+    let samples = parse_rapl_samples(
+        &ushell
+            .run(cmd!("cat {}", samples_file).allow_error())?
+            .stdout,
+    );
+    if samples.len() < 2 {
+        println!("WARNING: not enough RAPL samples in {}", samples_file);
+        return Ok(());
+    }
+
+    let start = samples[0].0;
+    let mut totals_uj = vec![0u64; domains.len()];
+
+    let mut series = String::from("Time (s)");
+    for domain in &domains {
+        series.push_str(&format!(",{} (W)", domain.label));
+    }
+    series.push('\n');
+    for pair in samples.windows(2) {
+        let ((prev_time, prev), (cur_time, cur)) = (&pair[0], &pair[1]);
+        let secs = cur_time - prev_time;
+
+        series.push_str(&format!("{:.2}", cur_time - time_zero));
+        for (domain, total) in domains.iter().zip(totals_uj.iter_mut()) {
+            let delta = match (prev.get(&domain.zone), cur.get(&domain.zone)) {
+                (Some(&p), Some(&c)) => energy_delta(p, c, domain.max_range_uj),
+                _ => 0,
+            };
+            *total += delta;
+            series.push_str(&format!(",{:.2}", delta as f64 / 1e6 / secs));
+        }
+        series.push('\n');
+    }
+    crate::write_remote_file(ushell, series_file, &series)?;
+
+    let secs = samples.last().unwrap().0 - start;
+    let mut energy = String::from("Domain,Energy (J),Time (s),Avg Power (W)\n");
+    for (domain, &total) in domains.iter().zip(totals_uj.iter()) {
+        let joules = total as f64 / 1e6;
+        energy.push_str(&format!(
+            "{},{:.2},{:.2},{:.2}\n",
+            domain.label,
+            joules,
+            secs,
+            joules / secs
+        ));
+        println!(
+            "ENERGY: {} {:.1} J ({:.1} W)",
+            domain.label,
+            joules,
+            joules / secs
+        );
+    }
+    crate::write_remote_file(ushell, energy_file, &energy)?;
+
+    Ok(())
+}
@@ -0,0 +1,359 @@
+//! A self-contained HTML report of a run copied back to the local machine: its
+//! config and manifest, the workloads' metrics, a summary of what each monitor
+//! recorded, the charts `plot` draws, and links to the raw artifacts. It needs
+//! nothing but a browser to read.
+
+use std::path::{Path, PathBuf};
+
+use clap::arg;
+
+use crate::manifest::Manifest;
+use crate::plot::{escape_xml as escape, file_label, render_svg, resolve_run, run_charts};
+use crate::results::{db_arg, find_manifest, format_unix_secs, parse_metrics};
+
+/// The report in each run's local directory.
+pub const REPORT_FILE: &str = "report.html";
+
+/// Tables from artifacts with more rows than this are cut short.
+const MAX_TABLE_ROWS: usize = 50;
+/// Artifacts bigger than this are only linked to.
+const MAX_SUMMARIZED_BYTES: u64 = 16 << 20;
+
+const STYLE: &str = "body { font-family: sans-serif; margin: 2em auto; max-width: 1100px; }
+table { border-collapse: collapse; margin: 0.5em 0 1.5em; font-size: 13px; }
+th, td { border: 1px solid #ccc; padding: 2px 8px; text-align: left; }
+td.num { text-align: right; font-family: monospace; }
+pre { background: #f6f6f6; padding: 1em; overflow-x: auto; font-size: 12px; }
+h2 { border-bottom: 1px solid #ccc; margin-top: 2em; }";
+
+pub fn cli_options() -> clap::Command {
+    clap::Command::new("report")
+        .about("Write a self-contained HTML report of a run")
+        .arg_required_else_help(true)
+        .disable_version_flag(true)
+        .arg(arg!(<run> "The local directory of the run, or its ID or name in the index"))
+        .arg(db_arg())
+}
+
+fn format_num(v: f64) -> String {
+    if v.fract() == 0.0 && v.abs() < 1e15 {
+        format!("{:.0}", v)
+    } else if v.abs() >= 100.0 {
+        format!("{:.1}", v)
+    } else {
+        format!("{:.3}", v)
+    }
+}
+
+/// An HTML table. Cells that are numbers are right-aligned.
+fn table<S: AsRef<str>>(header: &[S], rows: &[Vec<String>]) -> String {
+    let mut html = String::from("<table>\n<tr>");
+    for h in header {
+        html.push_str(&format!("<th>{}</th>", escape(h.as_ref())));
+    }
+    html.push_str("</tr>\n");
+    for row in rows {
+        html.push_str("<tr>");
+        for cell in row {
+            let class = if cell.parse::<f64>().is_ok() {
+                " class=\"num\""
+            } else {
+                ""
+            };
+            html.push_str(&format!("<td{}>{}</td>", class, cell));
+        }
+        html.push_str("</tr>\n");
+    }
+    html.push_str("</table>\n");
+    html
+}
+
+/// (samples, min, mean, max) of `values`.
+fn stats(values: &[f64]) -> Option<(usize, f64, f64, f64)> {
+    if values.is_empty() {
+        return None;
+    }
+    let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    Some((values.len(), min, mean, max))
+}
+
+/// Summarize a CSV artifact: the statistics of each column of a time series, or
+/// the first rows of anything else. None if it isn't CSV.
+fn summarize_csv(contents: &str) -> Option<String> {
+    let mut lines = contents.lines().filter(|l| !l.is_empty());
+    let header: Vec<&str> = lines.next()?.split(',').collect();
+    let rows: Vec<Vec<&str>> = lines.map(|l| l.split(',').collect()).collect();
+    if header.len() < 2 || rows.iter().any(|r| r.len() != header.len()) {
+        return None;
+    }
+
+    if header[0] == "Time (s)" {
+        let summary: Vec<Vec<String>> = header[1..]
+            .iter()
+            .enumerate()
+            .filter_map(|(i, column)| {
+                let values: Vec<f64> = rows.iter().filter_map(|r| r[i + 1].parse().ok()).collect();
+                let (n, min, mean, max) = stats(&values)?;
+                Some(vec![
+                    escape(column),
+                    n.to_string(),
+                    format_num(min),
+                    format_num(mean),
+                    format_num(max),
+                ])
+            })
+            .collect();
+        return Some(table(
+            &["Column", "Samples", "Min", "Mean", "Max"],
+            &summary,
+        ));
+    }
+
+    let shown: Vec<Vec<String>> = rows
+        .iter()
+        .take(MAX_TABLE_ROWS)
+        .map(|r| r.iter().map(|f| escape(f)).collect())
+        .collect();
+    let mut html = table(&header, &shown);
+    if rows.len() > MAX_TABLE_ROWS {
+        html.push_str(&format!(
+            "<p>First {} of {} rows.</p>\n",
+            MAX_TABLE_ROWS,
+            rows.len()
+        ));
+    }
+    Some(html)
+}
+
+fn link(path: &Path) -> String {
+    let name = escape(&path.file_name().unwrap_or_default().to_string_lossy());
+    format!("<a href=\"{}\">{}</a>", name, name)
+}
+
+/// Write `report.html` to the local directory of the run.
+pub fn write_report(run_dir: &Path) -> Result<PathBuf, failure::Error> {
+    let manifest_path = find_manifest(run_dir)?
+        .ok_or_else(|| failure::format_err!("{} has no manifest", run_dir.display()))?;
+    let manifest: Manifest = serde_json::from_str(&std::fs::read_to_string(&manifest_path)?)?;
+    let run_name = run_dir
+        .file_name()
+        .map_or(String::new(), |n| n.to_string_lossy().into_owned());
+    let read = |path: &Path| -> Option<String> {
+        if std::fs::metadata(path).ok()?.len() > MAX_SUMMARIZED_BYTES {
+            return None;
+        }
+        std::fs::read_to_string(path).ok()
+    };
+
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{name}</title>\n\
+         <style>\n{style}\n</style>\n</head>\n<body>\n<h1>{name}</h1>\n",
+        name = escape(&run_name),
+        style = STYLE
+    );
+
+    // Overview
+    let (charts, events) = match run_charts(run_dir, &manifest, None) {
+        Ok((charts, events)) => (charts, events),
+        Err(err) => {
+            html.push_str(&format!("<p>No charts: {}</p>\n", escape(&err.to_string())));
+            (vec![], vec![])
+        }
+    };
+    let started = events
+        .first()
+        .and_then(|e| e.details["wall"].as_f64())
+        .map_or("-".into(), format_unix_secs);
+    let duration = events.last().map_or("-".into(), |e| format_num(e.time));
+    html.push_str(&table(
+        &["Started (UTC)", "Duration (s)", "Host", "Kernel", "Runner"],
+        &[vec![
+            escape(&started),
+            duration,
+            escape(manifest.system.hostname.as_deref().unwrap_or("-")),
+            escape(&manifest.system.kernel_release),
+            escape(&format!(
+                "{} {}{}",
+                manifest.runner.version,
+                manifest.runner.git_hash.as_deref().unwrap_or(""),
+                if manifest.runner.dirty == Some(true) {
+                    " (dirty)"
+                } else {
+                    ""
+                }
+            )),
+        ]],
+    ));
+
+    // Workload metrics
+    html.push_str("<h2>Workload metrics</h2>\n");
+    let mut metrics = vec![];
+    for path in manifest
+        .local_files(run_dir, "workload")
+        .into_iter()
+        .chain(manifest.local_files(run_dir, "time"))
+    {
+        if let Some(contents) = read(&path) {
+            for (name, value) in parse_metrics(&contents) {
+                metrics.push(vec![
+                    escape(&file_label(&path, &run_name)),
+                    escape(&name),
+                    format_num(value),
+                    link(&path),
+                ]);
+            }
+        }
+    }
+    if metrics.is_empty() {
+        html.push_str("<p>No metrics found in the workloads' output.</p>\n");
+    } else {
+        html.push_str(&table(&["Workload", "Metric", "Value", "From"], &metrics));
+    }
+
+    // Charts, and the statistics of what they plot
+    if !charts.is_empty() {
+        html.push_str("<h2>Charts</h2>\n");
+        html.push_str(&render_svg(&run_name, &charts, &events));
+        let rows: Vec<Vec<String>> = charts
+            .iter()
+            .flat_map(|c| c.series.iter().map(move |s| (c, s)))
+            .filter_map(|(chart, series)| {
+                let values: Vec<f64> = series.points.iter().map(|p| p.1).collect();
+                let (n, min, mean, max) = stats(&values)?;
+                Some(vec![
+                    escape(chart.title),
+                    escape(&series.name),
+                    escape(chart.y_label),
+                    n.to_string(),
+                    format_num(min),
+                    format_num(mean),
+                    format_num(max),
+                ])
+            })
+            .collect();
+        html.push_str(&table(
+            &["Chart", "Series", "Unit", "Samples", "Min", "Mean", "Max"],
+            &rows,
+        ));
+    }
+
+    // Each monitor's artifacts
+    html.push_str("<h2>Monitors</h2>\n");
+    let mut monitors: Vec<&str> = manifest
+        .artifacts
+        .iter()
+        .filter_map(|a| a.role.strip_prefix("monitor:"))
+        .collect();
+    monitors.dedup();
+    if monitors.is_empty() {
+        html.push_str("<p>No monitors ran.</p>\n");
+    }
+    for monitor in monitors {
+        html.push_str(&format!("<h3>{}</h3>\n", escape(monitor)));
+        for path in manifest.local_files(run_dir, &format!("monitor:{}", monitor)) {
+            html.push_str(&format!("<p>{}</p>\n", link(&path)));
+            if let Some(summary) = read(&path).as_deref().and_then(summarize_csv) {
+                html.push_str(&summary);
+            }
+        }
+    }
+
+    // Timeline
+    html.push_str("<h2>Timeline</h2>\n");
+    let rows: Vec<Vec<String>> = events
+        .iter()
+        .map(|e| {
+            let mut details = e.details.clone();
+            if let Some(details) = details.as_object_mut() {
+                for key in ["mono", "wall", "event"] {
+                    details.remove(key);
+                }
+            }
+            vec![
+                format!("{:.3}", e.time),
+                escape(&e.name),
+                escape(&details.to_string()),
+            ]
+        })
+        .collect();
+    html.push_str(&table(&["Time (s)", "Event", "Details"], &rows));
+
+    // Config
+    html.push_str("<h2>Config</h2>\n");
+    for path in manifest.local_files(run_dir, "config") {
+        let config = read(&path)
+            .and_then(|c| serde_json::from_str::<serde_json::Value>(&c).ok())
+            .and_then(|c| serde_json::to_string_pretty(&c).ok())
+            .unwrap_or_default();
+        html.push_str(&format!("<pre>{}</pre>\n", escape(&config)));
+    }
+
+    // Manifest
+    html.push_str("<h2>Repositories</h2>\n");
+    let rows: Vec<Vec<String>> = manifest
+        .repos
+        .iter()
+        .map(|r| {
+            vec![
+                escape(&r.name),
+                escape(&r.path),
+                escape(r.git_hash.as_deref().unwrap_or("-")),
+                r.dirty.map_or("-".into(), |d| d.to_string()),
+            ]
+        })
+        .collect();
+    html.push_str(&table(&["Name", "Path", "Commit", "Dirty"], &rows));
+
+    html.push_str("<h2>System</h2>\n");
+    let system = serde_json::to_value(&manifest.system)?;
+    let rows: Vec<Vec<String>> = system
+        .as_object()
+        .into_iter()
+        .flatten()
+        .map(|(k, v)| {
+            let v = match v {
+                serde_json::Value::String(s) => s.clone(),
+                v => v.to_string(),
+            };
+            vec![escape(k), escape(&v)]
+        })
+        .collect();
+    html.push_str(&table(&["", ""], &rows));
+
+    html.push_str("<h2>Artifacts</h2>\n");
+    let rows: Vec<Vec<String>> = manifest
+        .artifacts
+        .iter()
+        .map(|a| {
+            let local = a
+                .local_path
+                .as_ref()
+                .map(|p| run_dir.join(Path::new(p).file_name().unwrap_or_default()));
+            vec![
+                escape(&a.role),
+                local
+                    .filter(|p| p.exists())
+                    .map_or_else(|| escape(&a.path), |p| link(&p)),
+                escape(&a.path),
+                escape(a.sha256.as_deref().unwrap_or("-")),
+            ]
+        })
+        .collect();
+    html.push_str(&table(&["Role", "File", "Remote path", "SHA-256"], &rows));
+
+    html.push_str("</body>\n</html>\n");
+
+    let output = run_dir.join(REPORT_FILE);
+    std::fs::write(&output, html)?;
+    Ok(output)
+}
+
+pub fn run(sub_m: &clap::ArgMatches) -> Result<(), failure::Error> {
+    let db = PathBuf::from(sub_m.get_one::<String>("db").unwrap());
+    let run_dir = resolve_run(sub_m.get_one::<String>("run").unwrap(), &db)?;
+    let report = write_report(&run_dir)?;
+    println!("RESULTS: {}", report.display());
+    Ok(())
+}
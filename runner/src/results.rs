@@ -0,0 +1,716 @@
+//! An SQLite index of the runs in the local results tree, so they can be found
+//! and exported without grepping through directories. It indexes both the runs
+//! copied back by the runner, which have a manifest, and the `output_*` folders
+//! the old bash scripts produced.
+
+use std::path::{Path, PathBuf};
+
+use clap::{arg, ArgAction};
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::manifest::Manifest;
+
+/// The index in each local results tree.
+pub const DB_FILE: &str = "results.db";
+
+/// The scripts' logs that come from monitors rather than workloads.
+const LEGACY_MONITORS: &[&str] = &["bwmon", "cipp", "latency", "pgmigrate", "vmstat"];
+
+/// Add the options that pick runs out of the index to `cmd`.
+pub fn filter_args(cmd: clap::Command) -> clap::Command {
+    cmd.arg(
+        arg!(--strategy <STRATEGY> "Only runs with this strategy, e.g. cipp. Can be repeated")
+            .action(ArgAction::Append),
+    )
+    .arg(arg!(--workload <WKLD> "Only runs with a workload whose name contains WKLD"))
+    .arg(arg!(--exp <EXP> "Only runs of this experiment"))
+    .arg(arg!(--host <HOST> "Only runs on this host"))
+    .arg(arg!(--since <DATE> "Only runs started on or after DATE (YYYY-MM-DD)"))
+}
+
+/// The `--db` option.
+pub fn db_arg() -> clap::Arg {
+    arg!(--db <FILE> "The index. Default: results/results.db").default_value("results/results.db")
+}
+
+pub fn cli_options() -> clap::Command {
+    clap::Command::new("results")
+        .about("Index the local results and query the index")
+        .arg_required_else_help(true)
+        .disable_version_flag(true)
+        .arg(db_arg())
+        .subcommand(
+            clap::Command::new("import")
+                .about("Index the runs in the given directories")
+                .arg(
+                    arg!([dirs] ... "Results trees or run directories. Default: results")
+                        .default_value("results"),
+                )
+                .arg(
+                    arg!(--reimport "Replace runs that are already indexed")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(filter_args(
+            clap::Command::new("list").about("List the indexed runs"),
+        ))
+        .subcommand(filter_args(
+            clap::Command::new("export")
+                .about("Write the indexed runs and their metrics to a CSV file")
+                .arg(arg!(<output> "The CSV file to write")),
+        ))
+        .subcommand_required(true)
+}
+
+/// A run to add to the index.
+#[derive(Clone, Debug)]
+struct RunRecord {
+    /// The directory or file the run was imported from. Unique.
+    source: String,
+    name: String,
+    /// `YYYY-MM-DD HH:MM[:SS]`, so that it sorts and compares as text.
+    started: Option<String>,
+    host: Option<String>,
+    exp: String,
+    strategy: String,
+    throttle: Option<String>,
+    workloads: Vec<String>,
+    config: serde_json::Value,
+    /// (label, name, value), where the label says which file or workload it's from.
+    metrics: Vec<(String, String, f64)>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Filters {
+    pub strategies: Vec<String>,
+    workload: Option<String>,
+    exp: Option<String>,
+    host: Option<String>,
+    since: Option<String>,
+}
+
+impl Filters {
+    pub fn from_matches(sub_m: &clap::ArgMatches) -> Result<Self, failure::Error> {
+        let since = sub_m.get_one::<String>("since").cloned();
+        if let Some(since) = &since {
+            let valid = since.len() == 10
+                && since.chars().enumerate().all(|(i, c)| {
+                    if matches!(i, 4 | 7) {
+                        c == '-'
+                    } else {
+                        c.is_ascii_digit()
+                    }
+                });
+            if !valid {
+                return Err(failure::format_err!(
+                    "--since must be YYYY-MM-DD: {}",
+                    since
+                ));
+            }
+        }
+
+        Ok(Filters {
+            strategies: sub_m
+                .get_many::<String>("strategy")
+                .map_or(vec![], |s| s.map(|s| s.to_lowercase()).collect()),
+            workload: sub_m
+                .get_one::<String>("workload")
+                .map(|s| s.to_lowercase()),
+            exp: sub_m.get_one::<String>("exp").cloned(),
+            host: sub_m.get_one::<String>("host").cloned(),
+            since,
+        })
+    }
+
+    /// A WHERE clause and its parameters.
+    fn to_sql(&self) -> (String, Vec<String>) {
+        let mut clauses = vec!["1".to_string()];
+        let mut params = vec![];
+        if !self.strategies.is_empty() {
+            clauses.push(format!(
+                "strategy IN ({})",
+                vec!["?"; self.strategies.len()].join(", ")
+            ));
+            params.extend(self.strategies.iter().cloned());
+        }
+        if let Some(workload) = &self.workload {
+            clauses.push("workloads LIKE ?".into());
+            params.push(format!("%{}%", workload));
+        }
+        if let Some(exp) = &self.exp {
+            clauses.push("exp = ?".into());
+            params.push(exp.clone());
+        }
+        if let Some(host) = &self.host {
+            clauses.push("host = ?".into());
+            params.push(host.clone());
+        }
+        if let Some(since) = &self.since {
+            clauses.push("started >= ?".into());
+            params.push(since.clone());
+        }
+        (clauses.join(" AND "), params)
+    }
+}
+
+/// A run in the index.
+#[derive(Clone, Debug)]
+pub struct IndexedRun {
+    pub id: i64,
+    pub source: String,
+    pub name: String,
+    pub started: Option<String>,
+    pub host: Option<String>,
+    pub exp: String,
+    pub strategy: String,
+    pub throttle: Option<String>,
+    pub workloads: String,
+    pub config: String,
+}
+
+pub fn open_db(path: &Path) -> Result<Connection, failure::Error> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let conn = Connection::open(path)?;
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS runs (
+            id INTEGER PRIMARY KEY,
+            source TEXT NOT NULL UNIQUE,
+            name TEXT NOT NULL,
+            started TEXT,
+            host TEXT,
+            exp TEXT NOT NULL,
+            strategy TEXT NOT NULL,
+            throttle TEXT,
+            workloads TEXT NOT NULL,
+            config TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS metrics (
+            run_id INTEGER NOT NULL REFERENCES runs(id) ON DELETE CASCADE,
+            label TEXT NOT NULL,
+            name TEXT NOT NULL,
+            value REAL NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS metrics_run ON metrics(run_id);
+        PRAGMA foreign_keys = ON;",
+    )?;
+    Ok(conn)
+}
+
+/// Add `run` to the index, replacing it if `replace` and it's already there.
+/// Returns false if it was already there and not replaced.
+fn insert_run(conn: &Connection, run: &RunRecord, replace: bool) -> Result<bool, failure::Error> {
+    let existing: Option<i64> = conn
+        .query_row(
+            "SELECT id FROM runs WHERE source = ?",
+            params![run.source],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if let Some(id) = existing {
+        if !replace {
+            return Ok(false);
+        }
+        conn.execute("DELETE FROM runs WHERE id = ?", params![id])?;
+    }
+
+    conn.execute(
+        "INSERT INTO runs (source, name, started, host, exp, strategy, throttle, workloads, config)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        params![
+            run.source,
+            run.name,
+            run.started,
+            run.host,
+            run.exp,
+            run.strategy,
+            run.throttle,
+            run.workloads.join(","),
+            run.config.to_string(),
+        ],
+    )?;
+    let id = conn.last_insert_rowid();
+    for (label, name, value) in &run.metrics {
+        conn.execute(
+            "INSERT INTO metrics (run_id, label, name, value) VALUES (?, ?, ?, ?)",
+            params![id, label, name, value],
+        )?;
+    }
+
+    Ok(true)
+}
+
+const RUN_COLUMNS: &str =
+    "id, source, name, started, host, exp, strategy, throttle, workloads, config";
+
+fn run_from_row(row: &rusqlite::Row) -> rusqlite::Result<IndexedRun> {
+    Ok(IndexedRun {
+        id: row.get(0)?,
+        source: row.get(1)?,
+        name: row.get(2)?,
+        started: row.get(3)?,
+        host: row.get(4)?,
+        exp: row.get(5)?,
+        strategy: row.get(6)?,
+        throttle: row.get(7)?,
+        workloads: row.get(8)?,
+        config: row.get(9)?,
+    })
+}
+
+/// The runs matching `filters`, oldest first.
+pub fn query_runs(conn: &Connection, filters: &Filters) -> Result<Vec<IndexedRun>, failure::Error> {
+    let (clause, params) = filters.to_sql();
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM runs WHERE {} ORDER BY started, name",
+        RUN_COLUMNS, clause
+    ))?;
+    let runs = stmt
+        .query_map(rusqlite::params_from_iter(params), run_from_row)?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(runs)
+}
+
+/// The run with the ID or name `key`.
+pub fn find_run(conn: &Connection, key: &str) -> Result<Option<IndexedRun>, failure::Error> {
+    Ok(conn
+        .query_row(
+            &format!(
+                "SELECT {} FROM runs WHERE CAST(id AS TEXT) = ?1 OR name = ?1",
+                RUN_COLUMNS
+            ),
+            params![key],
+            run_from_row,
+        )
+        .optional()?)
+}
+
+/// The (label, name, value) metrics of the run `id`.
+pub fn query_metrics(
+    conn: &Connection,
+    id: i64,
+) -> Result<Vec<(String, String, f64)>, failure::Error> {
+    let mut stmt =
+        conn.prepare("SELECT label, name, value FROM metrics WHERE run_id = ? ORDER BY rowid")?;
+    let metrics = stmt
+        .query_map(params![id], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(metrics)
+}
+
+/// The metrics the workloads report in their output, or that GNU time reports.
+pub fn parse_metrics(contents: &str) -> Vec<(String, f64)> {
+    let last_number = |line: &str| {
+        line.split(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .filter_map(|w| w.parse::<f64>().ok())
+            .last()
+    };
+    let mut metrics = vec![];
+
+    // The last one is the total for the runs that print one per step or trial
+    let patterns: &[(&str, &str)] = &[
+        ("total seconds elapsed", "runtime_s"),
+        ("Average Time:", "avg_time_s"),
+        ("Wall clock", "wall_clock_s"),
+    ];
+    for (pattern, name) in patterns {
+        if let Some(value) = contents
+            .lines()
+            .filter(|l| l.contains(pattern))
+            .filter_map(last_number)
+            .last()
+        {
+            metrics.push((name.to_string(), value));
+        }
+    }
+
+    // STREAM: the best rate in MB/s
+    if let Some(value) = contents
+        .lines()
+        .find(|l| l.starts_with("Triad:"))
+        .and_then(|l| l.split_whitespace().nth(1))
+        .and_then(|v| v.parse::<f64>().ok())
+    {
+        metrics.push(("triad_mb_s".into(), value));
+    }
+
+    // GNU time reports the elapsed time as [h:]m:s.cs
+    if let Some(value) = contents
+        .split_whitespace()
+        .find_map(|w| w.strip_suffix("elapsed"))
+        .and_then(|e| {
+            e.split(':')
+                .try_fold(0.0, |secs, f| Some(secs * 60.0 + f.parse::<f64>().ok()?))
+        })
+    {
+        metrics.push(("elapsed_s".into(), value));
+    }
+
+    metrics
+}
+
+/// The name of the variant of a serialized enum, lowercased.
+fn variant_name(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::String(s) => Some(s.to_lowercase()),
+        serde_json::Value::Object(o) => o.keys().next().map(|k| k.to_lowercase()),
+        _ => None,
+    }
+}
+
+/// Days since 1970-01-01 to (year, month, day).
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (yoe + era * 400 + (month <= 2) as i64, month, day)
+}
+
+/// Seconds since the epoch as `YYYY-MM-DD HH:MM:SS` UTC.
+pub fn format_unix_secs(secs: f64) -> String {
+    let secs = secs as i64;
+    let (y, m, d) = civil_from_days(secs.div_euclid(86400));
+    let tod = secs.rem_euclid(86400);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        y,
+        m,
+        d,
+        tod / 3600,
+        tod % 3600 / 60,
+        tod % 60
+    )
+}
+
+/// Read the run the runner copied to `run_dir`.
+fn read_run_dir(run_dir: &Path, manifest_path: &Path) -> Result<RunRecord, failure::Error> {
+    let manifest: Manifest = serde_json::from_str(&std::fs::read_to_string(manifest_path)?)?;
+    let name = run_dir
+        .file_name()
+        .map_or(String::new(), |n| n.to_string_lossy().into_owned());
+
+    // Only read the copies of the files in this directory
+    let local = |role: &str| manifest.local_files(run_dir, role);
+
+    let config: serde_json::Value = match local("config").first() {
+        Some(path) => serde_json::from_str(&std::fs::read_to_string(path)?)?,
+        None => {
+            return Err(failure::format_err!(
+                "{} has no config",
+                manifest_path.display()
+            ))
+        }
+    };
+
+    // The timeline starts with the remote's wall clock time
+    let started = local("timeline").first().and_then(|path| {
+        let contents = std::fs::read_to_string(path).ok()?;
+        let first: serde_json::Value = serde_json::from_str(contents.lines().next()?).ok()?;
+        first["wall"].as_f64().map(format_unix_secs)
+    });
+
+    let mut metrics = vec![];
+    for path in local("workload").into_iter().chain(local("time")) {
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let label = file_name
+            .strip_prefix(name.as_str())
+            .unwrap_or(&file_name)
+            .trim_start_matches(['.', '-', '_'])
+            .to_string();
+        for (metric, value) in parse_metrics(&std::fs::read_to_string(&path)?) {
+            metrics.push((label.clone(), metric, value));
+        }
+    }
+
+    Ok(RunRecord {
+        source: run_dir.to_string_lossy().into_owned(),
+        name,
+        started,
+        host: manifest.system.hostname.clone(),
+        exp: config["exp"].as_str().unwrap_or_default().to_string(),
+        strategy: variant_name(&config["strategy"]).unwrap_or_default(),
+        throttle: variant_name(&config["throttle"]),
+        workloads: config["workloads"]
+            .as_array()
+            .map_or(vec![], |w| w.iter().filter_map(variant_name).collect()),
+        config,
+        metrics,
+    })
+}
+
+/// Read the runs in an `output_<exp>_<MMDDYYYY>_<HHMM>` folder of the bash scripts.
+/// Each workload log is a run: `<wkld>/<kind>_output_trial_<N>_cpu_<C>_<rest>.log`,
+/// where the rest is the workload, or for the colloid and multi-application
+/// scripts, the strategy.
+fn read_legacy_dir(dir: &Path) -> Result<Vec<RunRecord>, failure::Error> {
+    let dir_name = dir
+        .file_name()
+        .map_or(String::new(), |n| n.to_string_lossy().into_owned());
+    let fields: Vec<&str> = dir_name
+        .strip_prefix("output_")
+        .unwrap_or(&dir_name)
+        .split('_')
+        .collect();
+
+    // The date and time are the last two fields
+    let (exp, started) = match fields.as_slice() {
+        [exp @ .., date, time] if date.len() == 8 && time.len() == 4 => (
+            exp.join("_"),
+            Some(format!(
+                "{}-{}-{} {}:{}",
+                &date[4..],
+                &date[..2],
+                &date[2..4],
+                &time[..2],
+                &time[2..]
+            )),
+        ),
+        _ => (fields.join("_"), None),
+    };
+    // e.g. colloid_bwaves is the colloid script run on bwaves
+    let (script, script_wkld) = match exp.split_once('_') {
+        Some(("colloid", wkld)) => ("colloid", Some(wkld.to_string())),
+        _ => (exp.as_str(), None),
+    };
+
+    let mut logs = vec![];
+    for sub in std::fs::read_dir(dir)? {
+        let sub = sub?.path();
+        if sub.is_dir() {
+            for log in std::fs::read_dir(&sub)? {
+                logs.push(log?.path());
+            }
+        }
+    }
+    logs.sort();
+
+    let mut runs = vec![];
+    for log in logs {
+        let stem = match log.file_stem() {
+            Some(stem) if log.extension().map_or(false, |e| e == "log") => {
+                stem.to_string_lossy().into_owned()
+            }
+            _ => continue,
+        };
+        let (kind, rest) = match stem.split_once("_output_") {
+            Some((kind, rest)) if !LEGACY_MONITORS.contains(&kind) => (kind, rest),
+            _ => continue,
+        };
+
+        let mut config = serde_json::json!({ "exp": exp, "legacy": true });
+        let mut rest_fields = vec![];
+        let mut tokens = rest.split('_').peekable();
+        while let Some(token) = tokens.next() {
+            let number = tokens.peek().and_then(|n| n.parse::<u64>().ok());
+            match (token, number) {
+                ("trial" | "ratio" | "cpu", Some(n)) => {
+                    config[token] = n.into();
+                    tokens.next();
+                }
+                _ => rest_fields.push(token),
+            }
+        }
+        let rest = rest_fields.join("_");
+
+        let (workload, strategy) = match (script, &script_wkld) {
+            ("colloid", Some(wkld)) => (wkld.clone(), rest.clone()),
+            ("multi_application", _) => (kind.to_string(), rest.clone()),
+            _ if script.starts_with("cipp") => (rest.clone(), "cipp".to_string()),
+            _ => (rest.clone(), script.to_string()),
+        };
+        if exp.starts_with("cipp_total_bw") {
+            config["total_bw"] = true.into();
+        }
+
+        let metrics = parse_metrics(&std::fs::read_to_string(&log)?)
+            .into_iter()
+            .map(|(name, value)| (workload.clone(), name, value))
+            .collect();
+
+        runs.push(RunRecord {
+            source: log.to_string_lossy().into_owned(),
+            name: format!("{}/{}", dir_name, stem),
+            started: started.clone(),
+            host: None,
+            exp: exp.clone(),
+            strategy,
+            throttle: None,
+            workloads: vec![workload],
+            config,
+            metrics,
+        });
+    }
+
+    Ok(runs)
+}
+
+/// The manifest in the local directory of a run, if `dir` is one.
+pub fn find_manifest(dir: &Path) -> Result<Option<PathBuf>, failure::Error> {
+    Ok(std::fs::read_dir(dir)?
+        .filter_map(|e| e.ok())
+        .find(|e| e.file_name().to_string_lossy().ends_with("manifest.json"))
+        .map(|e| e.path()))
+}
+
+/// The runs in `path`: a run directory, a legacy folder, or a tree of either.
+fn read_runs(path: &Path) -> Result<Vec<RunRecord>, failure::Error> {
+    if let Some(manifest) = find_manifest(path)? {
+        return Ok(vec![read_run_dir(path, &manifest)?]);
+    }
+    if path
+        .file_name()
+        .map_or(false, |n| n.to_string_lossy().starts_with("output_"))
+    {
+        return read_legacy_dir(path);
+    }
+
+    let mut runs = vec![];
+    let mut subdirs: Vec<PathBuf> = std::fs::read_dir(path)?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.is_dir())
+        .collect();
+    subdirs.sort();
+    for sub in subdirs {
+        runs.extend(read_runs(&sub)?);
+    }
+    Ok(runs)
+}
+
+/// Add the run the runner copied to `run_dir` to the index at `db`, replacing it
+/// if it was already there.
+pub fn index_run(db: &Path, run_dir: &Path) -> Result<(), failure::Error> {
+    let conn = open_db(db)?;
+    for run in read_runs(run_dir)? {
+        insert_run(&conn, &run, true)?;
+    }
+    Ok(())
+}
+
+pub fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+pub fn run(sub_m: &clap::ArgMatches) -> Result<(), failure::Error> {
+    let db = PathBuf::from(sub_m.get_one::<String>("db").unwrap());
+    let conn = open_db(&db)?;
+
+    match sub_m.subcommand() {
+        Some(("import", import_m)) => {
+            let reimport = import_m.get_flag("reimport");
+            let (mut added, mut skipped) = (0, 0);
+            for dir in import_m.get_many::<String>("dirs").unwrap() {
+                for run in read_runs(Path::new(dir))? {
+                    if insert_run(&conn, &run, reimport)? {
+                        added += 1;
+                    } else {
+                        skipped += 1;
+                    }
+                }
+            }
+            println!(
+                "Indexed {} runs in {}, {} already indexed",
+                added,
+                db.display(),
+                skipped
+            );
+        }
+
+        Some(("list", list_m)) => {
+            let runs = query_runs(&conn, &Filters::from_matches(list_m)?)?;
+            println!(
+                "{:>5}  {:<19}  {:<12}  {:<10}  {:<24}  NAME",
+                "ID", "STARTED", "HOST", "STRATEGY", "WORKLOADS"
+            );
+            for run in &runs {
+                println!(
+                    "{:>5}  {:<19}  {:<12}  {:<10}  {:<24}  {}",
+                    run.id,
+                    run.started.as_deref().unwrap_or("-"),
+                    run.host.as_deref().unwrap_or("-"),
+                    run.strategy,
+                    run.workloads,
+                    run.name
+                );
+            }
+            println!("{} runs", runs.len());
+        }
+
+        Some(("export", export_m)) => {
+            let runs = query_runs(&conn, &Filters::from_matches(export_m)?)?;
+            let output = export_m.get_one::<String>("output").unwrap();
+
+            // One column per metric of any run
+            let metrics = runs
+                .iter()
+                .map(|run| query_metrics(&conn, run.id))
+                .collect::<Result<Vec<_>, _>>()?;
+            let mut columns: Vec<String> = metrics
+                .iter()
+                .flatten()
+                .map(|(label, name, _)| format!("{}.{}", label, name))
+                .collect();
+            columns.sort();
+            columns.dedup();
+
+            let mut csv =
+                String::from("ID,Name,Started,Host,Exp,Strategy,Throttle,Workloads,Source,Config");
+            for column in &columns {
+                csv.push(',');
+                csv.push_str(&csv_field(column));
+            }
+            csv.push('\n');
+            for (run, metrics) in runs.iter().zip(&metrics) {
+                let fields = [
+                    run.id.to_string(),
+                    run.name.clone(),
+                    run.started.clone().unwrap_or_default(),
+                    run.host.clone().unwrap_or_default(),
+                    run.exp.clone(),
+                    run.strategy.clone(),
+                    run.throttle.clone().unwrap_or_default(),
+                    run.workloads.clone(),
+                    run.source.clone(),
+                    run.config.clone(),
+                ];
+                csv.push_str(
+                    &fields
+                        .iter()
+                        .map(|f| csv_field(f))
+                        .collect::<Vec<_>>()
+                        .join(","),
+                );
+                for column in &columns {
+                    csv.push(',');
+                    if let Some((_, _, value)) = metrics
+                        .iter()
+                        .find(|(label, name, _)| format!("{}.{}", label, name) == *column)
+                    {
+                        csv.push_str(&value.to_string());
+                    }
+                }
+                csv.push('\n');
+            }
+
+            std::fs::write(output, csv)?;
+            println!("Exported {} runs to {}", runs.len(), output);
+        }
+
+        _ => unreachable!(),
+    }
+
+    Ok(())
+}
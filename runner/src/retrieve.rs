@@ -0,0 +1,199 @@
+//! Copy the artifacts of a run from the remote to a local results tree, so they
+//! outlive the machine they were produced on.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde::{Deserialize, Serialize};
+
+use spurs::{cmd, Execute, SshShell};
+
+use crate::manifest::Manifest;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RetrieveConfig {
+    /// The local directory each run gets its own directory in.
+    pub local_dir: String,
+    /// Compress artifacts bigger than this many MB before copying them.
+    pub compress_over_mb: Option<u64>,
+}
+
+fn local_sha256(path: &Path) -> Result<String, failure::Error> {
+    let out = Command::new("sha256sum").arg(path).output()?;
+    if !out.status.success() {
+        return Err(failure::format_err!(
+            "sha256sum {} failed: {}",
+            path.display(),
+            String::from_utf8_lossy(&out.stderr)
+        ));
+    }
+
+    Ok(String::from_utf8_lossy(&out.stdout)
+        .split_whitespace()
+        .next()
+        .unwrap_or("")
+        .to_string())
+}
+
+/// Run `cmd` locally, failing with its stderr if it fails.
+fn run_local(cmd: &mut Command) -> Result<(), failure::Error> {
+    let out = cmd.output()?;
+    if !out.status.success() {
+        return Err(failure::format_err!(
+            "{:?} failed: {}",
+            cmd,
+            String::from_utf8_lossy(&out.stderr)
+        ));
+    }
+
+    Ok(())
+}
+
+/// Copy every artifact in `manifest` into `<cfg.local_dir>/<run_name>/`, compressing
+/// big ones with gzip first, and check that each copy has the same SHA-256 as the
+/// original. Everything is bundled into one tarball on the remote, so only one
+/// scp is needed; it logs in as `username` at `hostname`, which may be
+/// `host:port`, like the runner's shells. The local path and checksum of each
+/// artifact are added to the manifest, which is then written both locally and
+/// back to `manifest_file` on the remote. Returns the local directory.
+pub fn retrieve_results(
+    ushell: &SshShell,
+    username: &str,
+    hostname: &str,
+    cfg: &RetrieveConfig,
+    run_name: &str,
+    manifest: &mut Manifest,
+    manifest_file: &str,
+) -> Result<PathBuf, failure::Error> {
+    let local_dir = Path::new(&cfg.local_dir).join(run_name);
+    std::fs::create_dir_all(&local_dir)?;
+
+    let paths: Vec<&str> = manifest.artifacts.iter().map(|a| a.path.as_str()).collect();
+    // Recordings like perf.data are written by root and only readable by root
+    ushell.run(cmd!("sudo chmod a+r {}", paths.join(" ")))?;
+    let sizes = ushell
+        .run(cmd!("stat -c %s {}", paths.join(" ")))?
+        .stdout
+        .lines()
+        .map(|l| l.trim().parse::<u64>())
+        .collect::<Result<Vec<_>, _>>()?;
+    let compress: Vec<bool> = sizes
+        .iter()
+        .map(|&size| cfg.compress_over_mb.map_or(false, |mb| size > (mb << 20)))
+        .collect();
+
+    // Keep the originals on the remote, only the copies are compressed
+    let to_gzip: Vec<&str> = paths
+        .iter()
+        .zip(&compress)
+        .filter(|(_, compress)| **compress)
+        .map(|(&path, _)| path)
+        .collect();
+    if !to_gzip.is_empty() {
+        ushell.run(cmd!("gzip -kf {}", to_gzip.join(" ")))?;
+    }
+    let remote_paths: Vec<String> = paths
+        .iter()
+        .zip(&compress)
+        .map(|(path, &compress)| {
+            if compress {
+                format!("{}.gz", path)
+            } else {
+                path.to_string()
+            }
+        })
+        .collect();
+
+    // sha256sum prints the sums in the order of its arguments
+    let sha256s: Vec<String> = ushell
+        .run(cmd!("sha256sum {}", remote_paths.join(" ")))?
+        .stdout
+        .lines()
+        .filter_map(|l| l.split_whitespace().next().map(str::to_string))
+        .collect();
+
+    let mut tar_args = vec![];
+    let mut file_names = vec![];
+    for remote_path in &remote_paths {
+        let path = Path::new(remote_path);
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| failure::format_err!("Artifact {} has no file name", remote_path))?
+            .to_string_lossy()
+            .into_owned();
+        let dir = path.parent().map_or(".".into(), |d| d.to_string_lossy());
+        tar_args.push(format!("-C {} {}", dir, file_name));
+        file_names.push(file_name);
+    }
+    let remote_archive = format!("/tmp/{}.tar", run_name);
+    ushell.run(cmd!("tar cf {} {}", remote_archive, tar_args.join(" ")))?;
+    // Only the archive is needed now
+    let gz_paths: Vec<&str> = remote_paths
+        .iter()
+        .zip(&compress)
+        .filter(|(_, compress)| **compress)
+        .map(|(path, _)| path.as_str())
+        .collect();
+    if !gz_paths.is_empty() {
+        ushell.run(cmd!("rm -f {}", gz_paths.join(" ")))?;
+    }
+
+    let local_archive = local_dir.join(format!("{}.tar", run_name));
+    let (host, port) = match hostname.rsplit_once(':') {
+        Some((host, port)) => (host, Some(port)),
+        None => (hostname, None),
+    };
+    let mut scp = Command::new("scp");
+    scp.arg("-q").arg("-o").arg("BatchMode=yes");
+    // scp doesn't take a port in the host
+    if let Some(port) = port {
+        scp.arg("-P").arg(port);
+    }
+    run_local(
+        scp.arg(format!("{}@{}:{}", username, host, remote_archive))
+            .arg(&local_archive),
+    )?;
+    ushell.run(cmd!("rm -f {}", remote_archive))?;
+    run_local(
+        Command::new("tar")
+            .arg("xf")
+            .arg(&local_archive)
+            .arg("-C")
+            .arg(&local_dir),
+    )?;
+    std::fs::remove_file(&local_archive)?;
+
+    for (i, artifact) in manifest.artifacts.iter_mut().enumerate() {
+        let local_path = local_dir.join(&file_names[i]);
+        let sha256 = sha256s.get(i).cloned().unwrap_or_default();
+        if local_sha256(&local_path)? != sha256 {
+            return Err(failure::format_err!(
+                "Checksum of {} does not match {}",
+                local_path.display(),
+                remote_paths[i]
+            ));
+        }
+
+        artifact.local_path = Some(local_path.to_string_lossy().into_owned());
+        artifact.sha256 = Some(sha256);
+        artifact.compressed = compress[i];
+    }
+
+    manifest.local_dir = Some(local_dir.to_string_lossy().into_owned());
+    let json = serde_json::to_string_pretty(&manifest)?;
+    let local_manifest = local_dir.join(
+        Path::new(manifest_file)
+            .file_name()
+            .unwrap_or_else(|| "manifest.json".as_ref()),
+    );
+    std::fs::write(&local_manifest, &json)?;
+    crate::write_remote_file(ushell, manifest_file, &json)?;
+
+    println!(
+        "Copied {} artifacts to {}",
+        manifest.artifacts.len(),
+        local_dir.display()
+    );
+
+    Ok(local_dir)
+}
@@ -0,0 +1,151 @@
+//! Apply timed changes to the placement settings while the workloads run.
+
+use serde::{Deserialize, Serialize};
+
+use spurs::{cmd, Execute, SshShell};
+
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::timeline::Timeline;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ScheduleAction {
+    /// Set the global weighted interleave weights of nodes 0 and 1.
+    InterleaveWeights { local: usize, remote: usize },
+    /// Set the node weights of the `bwmmfs<mount>` instance.
+    BwmfsWeights {
+        mount: usize,
+        local: usize,
+        remote: usize,
+    },
+    /// Write `mode` to /proc/sys/kernel/numa_balancing.
+    NumaBalancing { mode: usize },
+    /// Start the CIPP controller.
+    StartCipp,
+    /// Stop the CIPP controller.
+    StopCipp,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScheduledAction {
+    /// Seconds after the workloads are launched to apply the action.
+    pub at_secs: u64,
+    pub action: ScheduleAction,
+}
+
+/// Parse a `--schedule` value of the form `<seconds>=<action>`.
+pub fn parse_scheduled_action(arg: &str) -> Result<ScheduledAction, failure::Error> {
+    let (at, action) = arg
+        .split_once('=')
+        .ok_or_else(|| failure::format_err!("--schedule should be <seconds>=<action>"))?;
+    let at_secs = at.parse::<u64>()?;
+
+    let mut split = action.split(':');
+    let name = split.next().unwrap();
+    let args = split
+        .map(str::parse::<usize>)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let action = match (name, args.as_slice()) {
+        ("interleave", &[local, remote]) => ScheduleAction::InterleaveWeights { local, remote },
+        ("bwmfs", &[mount, local, remote]) => ScheduleAction::BwmfsWeights {
+            mount,
+            local,
+            remote,
+        },
+        ("numa_balancing", &[mode]) => ScheduleAction::NumaBalancing { mode },
+        ("cipp_start", []) => ScheduleAction::StartCipp,
+        ("cipp_stop", []) => ScheduleAction::StopCipp,
+        _ => {
+            return Err(failure::format_err!(
+                "Unknown scheduled action \"{}\"",
+                action
+            ))
+        }
+    };
+
+    Ok(ScheduledAction { at_secs, action })
+}
+
+/// A command to run on the remote at a fixed offset from the start of the workloads.
+pub struct ScheduledCmd {
+    pub at: Duration,
+    pub desc: String,
+    pub cmd: String,
+    /// Spawn the command instead of waiting for it to finish.
+    pub background: bool,
+}
+
+/// Runs scheduled commands from a separate thread with its own SSH connection so
+/// they are applied on time regardless of what the main thread is blocked on.
+pub struct Scheduler {
+    stop: mpsc::Sender<()>,
+    handle: JoinHandle<Result<(), failure::Error>>,
+}
+
+impl Scheduler {
+    /// Start applying `cmds`, timed relative to now. The remote time and the offset
+    /// from the start of each applied command are appended to `log_file`, and each
+    /// one is recorded in `timeline`.
+    pub fn start(
+        username: &str,
+        host: &str,
+        mut cmds: Vec<ScheduledCmd>,
+        log_file: String,
+        timeline: Timeline,
+    ) -> Result<Self, failure::Error> {
+        let start = Instant::now();
+        let username = username.to_string();
+        let host = host.to_string();
+        let (stop, stop_rx) = mpsc::channel();
+
+        cmds.sort_by_key(|c| c.at);
+
+        let handle = std::thread::spawn(move || {
+            let shell = SshShell::with_any_key(&username, &host)?;
+
+            for c in cmds {
+                // Doubles as an interruptible sleep
+                match stop_rx.recv_timeout(c.at.saturating_sub(start.elapsed())) {
+                    Err(RecvTimeoutError::Timeout) => (),
+                    Ok(()) | Err(RecvTimeoutError::Disconnected) => break,
+                }
+
+                if c.background {
+                    shell.spawn(cmd!("{}", c.cmd))?;
+                } else {
+                    shell.run(cmd!("{}", c.cmd))?;
+                }
+
+                let offset = start.elapsed().as_secs_f64();
+                println!("SCHEDULE: {:.3}s {}", offset, c.desc);
+                shell.run(cmd!(
+                    "echo \"$(date +%s.%N) {:.3} {}\" >> {}",
+                    offset,
+                    c.desc,
+                    log_file
+                ))?;
+                timeline.record(
+                    &shell,
+                    "scheduled_action",
+                    serde_json::json!({ "action": c.desc, "at": c.at.as_secs_f64() }),
+                )?;
+            }
+
+            Ok(())
+        });
+
+        Ok(Scheduler { stop, handle })
+    }
+
+    /// Skip any actions that have not been applied yet and wait for the thread to exit.
+    pub fn stop(self) -> Result<(), failure::Error> {
+        // The thread may have already finished and dropped the receiver
+        let _ = self.stop.send(());
+        self.handle
+            .join()
+            .map_err(|_| failure::format_err!("Scheduler thread panicked"))?
+    }
+}
@@ -0,0 +1,431 @@
+//! Helpers for throttling memory bandwidth on the remote.
+
+use serde::{Deserialize, Serialize};
+
+use spurs::{cmd, Execute, SshShell};
+
+const MSR_UNCORE_RATIO_LIMIT: &str = "0x620";
+const UNCORE_SYSFS_DIR: &str = "/sys/devices/system/cpu/intel_uncore_frequency";
+
+/// How to change the uncore frequency limits.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum UncoreInterface {
+    /// Write MSR_UNCORE_RATIO_LIMIT directly.
+    Msr,
+    /// Use the intel_uncore_frequency driver's sysfs files.
+    Sysfs,
+    /// Use sysfs if the driver is available, otherwise the MSR.
+    Auto,
+}
+
+/// The uncore frequency limits to put on one socket, in units of 100MHz.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct UncoreLimit {
+    pub socket: usize,
+    pub min_ratio: u64,
+    pub max_ratio: u64,
+}
+
+/// The uncore frequency limits of a socket before we changed them.
+#[derive(Clone, Debug)]
+enum UncoreOriginal {
+    Msr {
+        socket: usize,
+        cpu: usize,
+        value: u64,
+    },
+    Sysfs {
+        socket: usize,
+        dir: String,
+        min_khz: u64,
+        max_khz: u64,
+    },
+}
+
+impl UncoreOriginal {
+    fn socket(&self) -> usize {
+        match self {
+            UncoreOriginal::Msr { socket, .. } | UncoreOriginal::Sysfs { socket, .. } => *socket,
+        }
+    }
+}
+
+fn first_cpu_of_socket(ushell: &SshShell, socket: usize) -> Result<usize, failure::Error> {
+    let out = ushell
+        .run(cmd!(
+            "lscpu -p=CPU,SOCKET | grep -v '#' | awk -F, '$2 == {} {{ print $1; exit }}'",
+            socket
+        ))?
+        .stdout;
+
+    out.trim()
+        .parse::<usize>()
+        .map_err(|_| failure::format_err!("Socket {} has no CPUs", socket))
+}
+
+fn read_msr(ushell: &SshShell, cpu: usize, msr: &str) -> Result<u64, failure::Error> {
+    let out = ushell.run(cmd!("sudo rdmsr -p {} {}", cpu, msr))?.stdout;
+    Ok(u64::from_str_radix(out.trim(), 16)?)
+}
+
+fn read_sysfs_u64(ushell: &SshShell, path: &str) -> Result<u64, failure::Error> {
+    Ok(ushell.run(cmd!("cat {}", path))?.stdout.trim().parse::<u64>()?)
+}
+
+fn uncore_sysfs_dir(socket: usize) -> String {
+    format!("{}/package_{:02}_die_00", UNCORE_SYSFS_DIR, socket)
+}
+
+/// Uncore frequency limits we put on some sockets. The original limits are put
+/// back when this is restored or dropped, so an early return from the experiment
+/// doesn't leave the uncore throttled.
+pub struct UncoreThrottle<'s> {
+    ushell: &'s SshShell,
+    interface: UncoreInterface,
+    log_file: String,
+    /// The limits of each socket from before we first changed them.
+    originals: Vec<UncoreOriginal>,
+}
+
+impl UncoreThrottle<'_> {
+    /// Change the limits of each socket in `limits`, e.g. to the next setting of a
+    /// sweep. Each write is read back to make sure it took, and what was done is
+    /// appended to the log file.
+    pub fn set(&mut self, limits: &[UncoreLimit]) -> Result<(), failure::Error> {
+        for limit in limits {
+            set_socket_uncore_ratio(
+                self.ushell,
+                limit,
+                self.interface,
+                &self.log_file,
+                &mut self.originals,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Put back the original limits.
+    pub fn restore(mut self) -> Result<(), failure::Error> {
+        self.put_back()
+    }
+
+    fn put_back(&mut self) -> Result<(), failure::Error> {
+        // Only forget the sockets that were put back, so the rest are tried again
+        while let Some(original) = self.originals.last() {
+            restore_uncore(self.ushell, original)?;
+            self.originals.pop();
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for UncoreThrottle<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = self.put_back() {
+            println!("Failed to restore the uncore limits: {}", e);
+        }
+    }
+}
+
+/// Limit the uncore frequency of each socket in `limits` to between its min and
+/// max ratio. Each write is read back to make sure it took. What was done is
+/// appended to `log_file`.
+pub fn set_uncore_ratio<'s>(
+    ushell: &'s SshShell,
+    limits: &[UncoreLimit],
+    interface: UncoreInterface,
+    log_file: &str,
+) -> Result<UncoreThrottle<'s>, failure::Error> {
+    let interface = match interface {
+        UncoreInterface::Auto => {
+            ushell.run(cmd!("sudo modprobe intel_uncore_frequency").allow_error())?;
+            let has_sysfs = limits.iter().all(|l| {
+                ushell
+                    .run(cmd!("test -d {}", uncore_sysfs_dir(l.socket)))
+                    .is_ok()
+            });
+            if has_sysfs {
+                UncoreInterface::Sysfs
+            } else {
+                UncoreInterface::Msr
+            }
+        }
+        UncoreInterface::Sysfs => {
+            ushell.run(cmd!("sudo modprobe intel_uncore_frequency"))?;
+            UncoreInterface::Sysfs
+        }
+        UncoreInterface::Msr => UncoreInterface::Msr,
+    };
+    if let UncoreInterface::Msr = interface {
+        ushell.run(cmd!("sudo modprobe msr"))?;
+    }
+
+    // If a later socket fails, dropping this puts back the ones already changed
+    let mut throttle = UncoreThrottle {
+        ushell,
+        interface,
+        log_file: log_file.to_string(),
+        originals: Vec::new(),
+    };
+    throttle.set(limits)?;
+
+    Ok(throttle)
+}
+
+/// Limit the uncore frequency of one socket, pushing its original limits to
+/// `originals` before changing them the first time.
+fn set_socket_uncore_ratio(
+    ushell: &SshShell,
+    limit: &UncoreLimit,
+    interface: UncoreInterface,
+    log_file: &str,
+    originals: &mut Vec<UncoreOriginal>,
+) -> Result<(), failure::Error> {
+    let &UncoreLimit {
+        socket,
+        min_ratio,
+        max_ratio,
+    } = limit;
+    let first_change = !originals.iter().any(|o| o.socket() == socket);
+
+    match interface {
+        UncoreInterface::Msr => {
+            let cpu = first_cpu_of_socket(ushell, socket)?;
+            let original = read_msr(ushell, cpu, MSR_UNCORE_RATIO_LIMIT)?;
+            if first_change {
+                originals.push(UncoreOriginal::Msr {
+                    socket,
+                    cpu,
+                    value: original,
+                });
+            }
+
+            // Bits 14:8 are the min ratio and bits 6:0 are the max ratio
+            let value = (original & !0x7f7f) | ((min_ratio & 0x7f) << 8) | (max_ratio & 0x7f);
+            ushell.run(cmd!(
+                "sudo wrmsr -p {} {} {:#x}",
+                cpu,
+                MSR_UNCORE_RATIO_LIMIT,
+                value
+            ))?;
+
+            let readback = read_msr(ushell, cpu, MSR_UNCORE_RATIO_LIMIT)?;
+            ushell.run(cmd!(
+                "echo 'socket {} cpu {} msr original {:#x} written {:#x} readback {:#x}' | tee -a {}",
+                socket,
+                cpu,
+                original,
+                value,
+                readback,
+                log_file
+            ))?;
+            if readback & 0x7f7f != value & 0x7f7f {
+                return Err(failure::format_err!(
+                    "Uncore ratio limit on CPU {} is {:#x} after writing {:#x}",
+                    cpu,
+                    readback,
+                    value
+                ));
+            }
+        }
+        UncoreInterface::Sysfs => {
+            let dir = uncore_sysfs_dir(socket);
+            let min_khz = read_sysfs_u64(ushell, &format!("{}/min_freq_khz", dir))?;
+            let max_khz = read_sysfs_u64(ushell, &format!("{}/max_freq_khz", dir))?;
+            if first_change {
+                originals.push(UncoreOriginal::Sysfs {
+                    socket,
+                    dir: dir.clone(),
+                    min_khz,
+                    max_khz,
+                });
+            }
+
+            let (new_min, new_max) = (min_ratio * 100_000, max_ratio * 100_000);
+            write_sysfs_limits(ushell, &dir, (min_khz, max_khz), (new_min, new_max))?;
+
+            let readback = (
+                read_sysfs_u64(ushell, &format!("{}/min_freq_khz", dir))?,
+                read_sysfs_u64(ushell, &format!("{}/max_freq_khz", dir))?,
+            );
+            ushell.run(cmd!(
+                "echo 'socket {} sysfs original {}-{}kHz written {}-{}kHz readback {}-{}kHz' | tee -a {}",
+                socket,
+                min_khz,
+                max_khz,
+                new_min,
+                new_max,
+                readback.0,
+                readback.1,
+                log_file
+            ))?;
+            if readback != (new_min, new_max) {
+                return Err(failure::format_err!(
+                    "Uncore frequency limits of socket {} are {:?} after writing {:?}",
+                    socket,
+                    readback,
+                    (new_min, new_max)
+                ));
+            }
+        }
+        UncoreInterface::Auto => unreachable!(),
+    }
+
+    Ok(())
+}
+
+/// The driver rejects a min above the current max (and vice versa), so order the
+/// writes based on which direction the limits are moving.
+fn write_sysfs_limits(
+    ushell: &SshShell,
+    dir: &str,
+    old: (u64, u64),
+    new: (u64, u64),
+) -> Result<(), failure::Error> {
+    let write_min = cmd!("echo {} | sudo tee {}/min_freq_khz", new.0, dir);
+    let write_max = cmd!("echo {} | sudo tee {}/max_freq_khz", new.1, dir);
+
+    if new.0 > old.1 {
+        ushell.run(write_max)?;
+        ushell.run(write_min)?;
+    } else {
+        ushell.run(write_min)?;
+        ushell.run(write_max)?;
+    }
+
+    Ok(())
+}
+
+/// Put back the original uncore frequency limits of a socket.
+fn restore_uncore(ushell: &SshShell, original: &UncoreOriginal) -> Result<(), failure::Error> {
+    match original {
+        UncoreOriginal::Msr { cpu, value, .. } => {
+            ushell.run(cmd!(
+                "sudo wrmsr -p {} {} {:#x}",
+                cpu,
+                MSR_UNCORE_RATIO_LIMIT,
+                value
+            ))?;
+        }
+        UncoreOriginal::Sysfs {
+            dir,
+            min_khz,
+            max_khz,
+            ..
+        } => {
+            let current = (
+                read_sysfs_u64(ushell, &format!("{}/min_freq_khz", dir))?,
+                read_sysfs_u64(ushell, &format!("{}/max_freq_khz", dir))?,
+            );
+            write_sysfs_limits(ushell, dir, current, (*min_khz, *max_khz))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Classes of service made with Intel RDT MBA. They are removed, along with the
+/// resctrl mount if we made it, when this is reset or dropped, so an early return
+/// from the experiment doesn't leave the CPUs throttled.
+pub struct MbaThrottle<'s> {
+    ushell: &'s SshShell,
+    log_file: String,
+    /// Whether resctrl had to be mounted, so it should be unmounted again.
+    mounted: bool,
+    active: bool,
+}
+
+impl MbaThrottle<'_> {
+    /// Put each group of CPUs in its own class of service, with its memory
+    /// bandwidth limited to the given MBA percentage. Calling this again updates
+    /// the existing groups.
+    pub fn set(&mut self, groups: &[(u64, String)]) -> Result<(), failure::Error> {
+        for (i, (percent, cores)) in groups.iter().enumerate() {
+            let group_dir = format!("/sys/fs/resctrl/wkld{}", i);
+
+            self.ushell.run(cmd!("sudo mkdir -p {}", group_dir))?;
+            // Tasks in the default group follow the group of the CPU they run on
+            self.ushell
+                .run(cmd!("echo {} | sudo tee {}/cpus_list", cores, group_dir))?;
+            // Throttle every MBA domain by replacing the values of the default MB line
+            self.ushell.run(cmd!(
+                "grep '^ *MB:' /sys/fs/resctrl/schemata | sed -E 's/=[0-9]+/={}/g' | \
+                 sudo tee {}/schemata",
+                percent,
+                group_dir
+            ))?;
+            self.ushell.run(cmd!(
+                "(echo wkld{}; cat {}/cpus_list {}/schemata) | tee -a {}",
+                i,
+                group_dir,
+                group_dir,
+                self.log_file
+            ))?;
+        }
+
+        Ok(())
+    }
+
+    /// Remove the classes of service, and unmount resctrl if we mounted it.
+    pub fn reset(mut self) -> Result<(), failure::Error> {
+        self.remove()
+    }
+
+    fn remove(&mut self) -> Result<(), failure::Error> {
+        if !self.active {
+            return Ok(());
+        }
+
+        // Removing the groups gives their CPUs back to the default group, which
+        // is unthrottled. Some may not have been made if setting them up failed.
+        self.ushell
+            .run(cmd!("ls -d /sys/fs/resctrl/wkld* 2>/dev/null | xargs -r sudo rmdir"))?;
+        if self.mounted {
+            self.ushell.run(cmd!("sudo umount /sys/fs/resctrl"))?;
+        }
+        self.active = false;
+
+        Ok(())
+    }
+}
+
+impl Drop for MbaThrottle<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = self.remove() {
+            println!("Failed to reset MBA: {}", e);
+        }
+    }
+}
+
+/// Put each group of CPUs in its own resctrl class of service, with its memory
+/// bandwidth limited to the given MBA percentage. The schemata before and after
+/// are appended to `log_file`.
+pub fn set_mba<'s>(
+    ushell: &'s SshShell,
+    groups: &[(u64, String)],
+    log_file: &str,
+) -> Result<MbaThrottle<'s>, failure::Error> {
+    // The mba flag is only present if the CPU supports MBA and the kernel
+    // has resctrl support for it
+    if ushell.run(cmd!("grep -q -w mba /proc/cpuinfo")).is_err() {
+        return Err(failure::format_err!("This CPU does not support Intel RDT MBA"));
+    }
+
+    let mounted = ushell.run(cmd!("mountpoint -q /sys/fs/resctrl")).is_err();
+    if mounted {
+        ushell.run(cmd!("sudo mount -t resctrl resctrl /sys/fs/resctrl"))?;
+    }
+    let mut throttle = MbaThrottle {
+        ushell,
+        log_file: log_file.to_string(),
+        mounted,
+        active: true,
+    };
+
+    ushell.run(cmd!("cat /sys/fs/resctrl/schemata | tee -a {}", log_file))?;
+    throttle.set(groups)?;
+
+    Ok(throttle)
+}
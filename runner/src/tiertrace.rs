@@ -0,0 +1,107 @@
+//! Trace page migrations and NUMA hint faults with tools/tiertrace.py, and work
+//! out which workload's pages were moved, in which direction, and when.
+
+use std::collections::BTreeMap;
+
+use spurs::{cmd, Execute, SshShell};
+
+/// How often tiertrace.py writes out its counts, in seconds.
+const TIERTRACE_INTERVAL: usize = 1;
+
+/// A command that traces until killed. It has to run as root.
+pub fn tiertrace_cmd(tools_dir: &str, counts_file: &str, latency_file: &str) -> String {
+    format!(
+        "exec python3 {}/tiertrace.py {} {} {}",
+        tools_dir, TIERTRACE_INTERVAL, counts_file, latency_file
+    )
+}
+
+#[derive(Clone, Debug, Default)]
+struct TraceTotals {
+    events: u64,
+    succeeded: u64,
+    failed: u64,
+    first: Option<f64>,
+    last: f64,
+}
+
+/// Total the counts in `counts_file` per process and direction, and write them to
+/// `summary_file` along with when the process was first and last seen doing it,
+/// in seconds since the wall clock time `time_zero`. Processes are matched to the
+/// workloads by name.
+pub fn summarize_tiertrace(
+    ushell: &SshShell,
+    counts_file: &str,
+    summary_file: &str,
+    proc_names: &[&str],
+    time_zero: f64,
+) -> Result<(), failure::Error> {
+    let contents = ushell
+        .run(cmd!("cat {}", counts_file).allow_error())?
+        .stdout;
+
+    let mut totals: BTreeMap<(String, u64, String), TraceTotals> = BTreeMap::new();
+    // time,pid,comm,direction,events,succeeded,failed
+    for line in contents.lines().skip(1) {
+        let fields: Vec<&str> = line.split(',').collect();
+        if fields.len() != 7 {
+            continue;
+        }
+        let (time, pid) = match (fields[0].parse::<f64>(), fields[1].parse::<u64>()) {
+            (Ok(time), Ok(pid)) => (time, pid),
+            _ => continue,
+        };
+        let counts: Vec<u64> = fields[4..].iter().filter_map(|f| f.parse().ok()).collect();
+        if counts.len() != 3 {
+            continue;
+        }
+
+        // The counts cover the interval before the time they were written
+        let begin = time - TIERTRACE_INTERVAL as f64;
+        let t = totals
+            .entry((fields[2].to_string(), pid, fields[3].to_string()))
+            .or_default();
+        t.events += counts[0];
+        t.succeeded += counts[1];
+        t.failed += counts[2];
+        t.first = Some(t.first.map_or(begin, |f| f.min(begin)));
+        t.last = t.last.max(time);
+    }
+
+    if totals.is_empty() {
+        println!("WARNING: nothing traced in {}", counts_file);
+        return Ok(());
+    }
+
+    let mut csv = String::from(
+        "Comm,PID,Workload,Direction,Events,Pages Succeeded,Pages Failed,First (s),Last (s)\n",
+    );
+    for ((comm, pid, dir), t) in &totals {
+        let wkld = proc_names
+            .iter()
+            .position(|name| name == comm)
+            .map_or(String::new(), |i| i.to_string());
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{},{:.0},{:.0}\n",
+            comm,
+            pid,
+            wkld,
+            dir,
+            t.events,
+            t.succeeded,
+            t.failed,
+            t.first.unwrap() - time_zero,
+            t.last - time_zero
+        ));
+
+        if !wkld.is_empty() || dir == "demotion" {
+            println!(
+                "TIERTRACE: {} ({}) {} {} events, {} pages moved, {} failed",
+                comm, pid, dir, t.events, t.succeeded, t.failed
+            );
+        }
+    }
+    crate::write_remote_file(ushell, summary_file, &csv)?;
+
+    Ok(())
+}
@@ -0,0 +1,92 @@
+//! A log of everything significant that happens during a run, so that it can be
+//! lined up with what the monitors recorded. Each event is a line of JSON in
+//! `events.jsonl` with the seconds since the timeline started, from a monotonic
+//! clock, and the wall clock time on the remote.
+
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use spurs::{cmd, Execute, SshShell};
+use spurs_util::escape_for_bash;
+
+/// Timestamps events on the remote's wall clock without asking the remote every
+/// time: the offset between our clock and the remote's is measured once.
+///
+/// It can be cloned to record events from other threads, e.g. the scheduler's,
+/// with their own shell.
+#[derive(Clone, Debug)]
+pub struct Timeline {
+    file: String,
+    start: Instant,
+    /// Remote wall clock time when the timeline started, in seconds.
+    zero: f64,
+    /// Remote wall clock minus ours, in seconds.
+    offset: f64,
+}
+
+fn unix_secs() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |d| d.as_secs_f64())
+}
+
+impl Timeline {
+    /// Start a timeline, logging to `file` on the remote.
+    pub fn start(ushell: &SshShell, file: &str) -> Result<Self, failure::Error> {
+        let before = unix_secs();
+        let remote = ushell.run(cmd!("date +%s.%N"))?.stdout;
+        let after = unix_secs();
+        let remote = remote
+            .trim()
+            .parse::<f64>()
+            .map_err(|_| failure::format_err!("Could not parse the remote time: {}", remote))?;
+
+        // Assume the remote read its clock halfway through the round trip
+        let offset = remote - (before + after) / 2.0;
+        let timeline = Timeline {
+            file: file.to_string(),
+            start: Instant::now(),
+            zero: unix_secs() + offset,
+            offset,
+        };
+
+        ushell.run(cmd!("truncate -s 0 {}", file))?;
+        timeline.record(
+            ushell,
+            "timeline_start",
+            serde_json::json!({ "clock_offset": offset, "rtt": after - before }),
+        )?;
+
+        Ok(timeline)
+    }
+
+    /// The remote wall clock time when the timeline started, in seconds. Series
+    /// that are timed relative to this line up with the events.
+    pub fn zero(&self) -> f64 {
+        self.zero
+    }
+
+    /// Log `event` along with `details`, which should be a JSON object or null.
+    pub fn record(
+        &self,
+        shell: &SshShell,
+        event: &str,
+        details: serde_json::Value,
+    ) -> Result<(), failure::Error> {
+        let mut line = serde_json::json!({
+            "mono": self.start.elapsed().as_secs_f64(),
+            "wall": unix_secs() + self.offset,
+            "event": event,
+        });
+        if let (Some(line), serde_json::Value::Object(details)) = (line.as_object_mut(), details) {
+            line.extend(details);
+        }
+
+        shell.run(cmd!(
+            "echo {} >> {}",
+            escape_for_bash(&line.to_string()),
+            self.file
+        ))?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,152 @@
+//! Sample the tiering-related counters of /proc/vmstat and each node's vmstat over
+//! time, and turn them into deltas and rates.
+
+use std::collections::BTreeMap;
+
+use spurs::{cmd, Execute, SshShell};
+
+/// The counters we track: promotion, demotion, migration, NUMA hint faults and
+/// THP splits.
+const VMSTAT_COUNTERS: &[&str] = &[
+    "pgpromote_success",
+    "pgpromote_candidate",
+    "pgdemote_kswapd",
+    "pgdemote_direct",
+    "pgdemote_khugepaged",
+    "pgmigrate_success",
+    "pgmigrate_fail",
+    "numa_pages_migrated",
+    "numa_hint_faults",
+    "numa_hint_faults_local",
+    "thp_split_page",
+    "thp_split_pmd",
+];
+
+fn counter_pattern() -> String {
+    format!("^({}) ", VMSTAT_COUNTERS.join("|"))
+}
+
+/// A command that appends a line of `time <secs> <counter>=<value> ...` to `file`.
+/// Per-node counters are named `n<node>.<counter>`.
+pub fn vmstat_sample_cmd(file: &str) -> String {
+    let pattern = counter_pattern();
+    format!(
+        "(echo -n \"time $(date +%s.%N)\"; \
+         grep -E '{}' /proc/vmstat | awk '{{ printf \" %s=%s\", $1, $2 }}'; \
+         for n in /sys/devices/system/node/node[0-9]*; do \
+         grep -E '{}' $n/vmstat | awk -v n=${{n##*node}} '{{ printf \" n%s.%s=%s\", n, $1, $2 }}'; \
+         done; echo) >> {}",
+        pattern, pattern, file
+    )
+}
+
+#[derive(Clone, Debug)]
+struct VmstatSample {
+    time: f64,
+    counters: BTreeMap<String, u64>,
+}
+
+fn parse_vmstat_samples(contents: &str) -> Vec<VmstatSample> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            if fields.next()? != "time" {
+                return None;
+            }
+            let time = fields.next()?.parse::<f64>().ok()?;
+            let counters = fields
+                .filter_map(|f| {
+                    let (name, value) = f.split_once('=')?;
+                    Some((name.to_string(), value.parse::<u64>().ok()?))
+                })
+                .collect();
+
+            Some(VmstatSample { time, counters })
+        })
+        .collect()
+}
+
+/// Turn the samples in `samples_file` into a CSV at `series_file` with, for each
+/// counter, its change since the previous sample and its rate per second. Times
+/// are in seconds since the wall clock time `time_zero`.
+pub fn summarize_vmstat_series(
+    ushell: &SshShell,
+    samples_file: &str,
+    series_file: &str,
+    time_zero: f64,
+) -> Result<(), failure::Error> {
+    let samples = parse_vmstat_samples(&ushell.run(cmd!("cat {}", samples_file))?.stdout);
+    if samples.len() < 2 {
+        println!("WARNING: not enough vmstat samples in {}", samples_file);
+        return Ok(());
+    }
+
+    // Not every kernel has every counter, so only use the ones we saw
+    let names: Vec<&String> = samples[0].counters.keys().collect();
+
+    let mut csv = String::from("Time (s)");
+    for name in &names {
+        csv.push_str(&format!(",{},{}/s", name, name));
+    }
+    csv.push('\n');
+    for pair in samples.windows(2) {
+        let (prev, cur) = (&pair[0], &pair[1]);
+        let secs = cur.time - prev.time;
+
+        csv.push_str(&format!("{:.2}", cur.time - time_zero));
+        for &name in &names {
+            let delta = cur
+                .counters
+                .get(name)
+                .unwrap_or(&0)
+                .saturating_sub(*prev.counters.get(name).unwrap_or(&0));
+            csv.push_str(&format!(",{},{:.1}", delta, delta as f64 / secs));
+        }
+        csv.push('\n');
+    }
+
+    crate::write_remote_file(ushell, series_file, &csv)?;
+
+    Ok(())
+}
+
+fn parse_vmstat(contents: &str) -> BTreeMap<String, u64> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (name, value) = line.split_once(' ')?;
+            Some((name.to_string(), value.trim().parse::<u64>().ok()?))
+        })
+        .collect()
+}
+
+/// Write every /proc/vmstat counter that changed between the `before` and `after`
+/// dumps to `diff_file`, and print the change in the counters we track.
+pub fn vmstat_diff(
+    ushell: &SshShell,
+    before_file: &str,
+    after_file: &str,
+    diff_file: &str,
+) -> Result<(), failure::Error> {
+    let before = parse_vmstat(&ushell.run(cmd!("cat {}", before_file))?.stdout);
+    let after = parse_vmstat(&ushell.run(cmd!("cat {}", after_file))?.stdout);
+
+    let mut diff = String::from("Counter,Before,After,Delta\n");
+    for (name, &end) in &after {
+        let start = *before.get(name).unwrap_or(&0);
+        if start == end {
+            continue;
+        }
+
+        let delta = end as i128 - start as i128;
+        diff.push_str(&format!("{},{},{},{}\n", name, start, end, delta));
+        if VMSTAT_COUNTERS.contains(&name.as_str()) {
+            println!("VMSTAT: {} {:+}", name, delta);
+        }
+    }
+
+    crate::write_remote_file(ushell, diff_file, &diff)?;
+
+    Ok(())
+}
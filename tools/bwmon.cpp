@@ -128,6 +128,8 @@ int main(int argc, char* argv[])
             apply_ioctl(PERF_EVENT_IOC_DISABLE, wr_fds[i]);
         }
 
+        out << "Time: " << wall_time() << std::endl;
+
         for (i = 0; i < num_nodes; i++) {
             rd_count = wr_count = 0;
 
@@ -0,0 +1,54 @@
+/*
+ * Take away memory from a NUMA node by allocating it and locking it in place
+ * (so it can't be demoted or swapped out) until killed.
+ *
+ * Usage: caphog <node> <size (MB)>
+ */
+#define _GNU_SOURCE
+#include <stdio.h>
+#include <stdlib.h>
+#include <unistd.h>
+#include <sys/mman.h>
+#include <numaif.h>
+
+int main(int argc, char *argv[])
+{
+	unsigned long node_mask;
+	size_t size;
+	char *buf;
+	int node;
+
+	if (argc != 3) {
+		fprintf(stderr, "Usage: caphog <node> <size (MB)>\n");
+		return -1;
+	}
+
+	node = atoi(argv[1]);
+	size = strtoul(argv[2], NULL, 10) << 20;
+
+	buf = mmap(NULL, size, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0);
+	if (buf == MAP_FAILED) {
+		perror("mmap");
+		return -1;
+	}
+
+	node_mask = 1UL << node;
+	if (mbind(buf, size, MPOL_BIND, &node_mask, sizeof(node_mask) * 8, MPOL_MF_STRICT)) {
+		perror("mbind");
+		return -1;
+	}
+
+	// Faults in every page on the node and keeps it there
+	if (mlock(buf, size)) {
+		perror("mlock");
+		return -1;
+	}
+
+	printf("locked %zu MB on node %d\n", size >> 20, node);
+	fflush(stdout);
+
+	for (;;)
+		pause();
+
+	return 0;
+}
@@ -0,0 +1,365 @@
+/*
+ * LD_PRELOAD library that places the first CAPSPLIT_LOCAL_GB of a process's
+ * anonymous memory on the local node and spills everything allocated after
+ * that to the remote node.
+ *
+ * Usage: env LD_PRELOAD=libcapsplit.so CAPSPLIT_LOCAL_GB=<gb> \
+ *            [CAPSPLIT_LOCAL_NODE=0] [CAPSPLIT_REMOTE_NODE=1] \
+ *            [CAPSPLIT_VERBOSE=1] <program> [args..]
+ *
+ * glibc's malloc gets its memory from brk and from internal calls to mmap that
+ * an interposed mmap never sees, so the allocation functions are interposed
+ * too. The pages of each allocation or mapping that haven't been placed yet
+ * count against the budget in the order they are allocated, and the ones past
+ * it are moved to the remote node. The budget is not returned when memory is
+ * freed, so this approximates a "first N GB local" policy for workloads that
+ * allocate their data up front.
+ *
+ * With CAPSPLIT_VERBOSE set, how much was placed on each node is printed when
+ * the process exits.
+ */
+#define _GNU_SOURCE
+#include <errno.h>
+#include <limits.h>
+#include <malloc.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <unistd.h>
+#include <pthread.h>
+#include <sys/mman.h>
+#include <sys/syscall.h>
+#include <numaif.h>
+
+#define PAGE_SIZE 4096UL
+#define GB (1UL << 30)
+#define MB (1UL << 20)
+/* Placed ranges are merged when they touch, so few are needed */
+#define MAX_RANGES 65536
+/* Set in the size field of a glibc malloc chunk that has its own mapping */
+#define CHUNK_IS_MMAPPED 0x2
+
+/* The real allocators, which glibc exports under these names */
+extern void *__libc_malloc(size_t size);
+extern void *__libc_calloc(size_t nmemb, size_t size);
+extern void *__libc_realloc(void *ptr, size_t size);
+extern void *__libc_memalign(size_t alignment, size_t size);
+extern void *__libc_valloc(size_t size);
+extern void *__libc_pvalloc(size_t size);
+
+struct range {
+	uintptr_t start;
+	uintptr_t end;
+};
+
+static size_t local_budget = 0;
+static size_t local_used = 0;
+static size_t remote_used = 0;
+static int local_node = 0;
+static int remote_node = 1;
+static int verbose = 0;
+static int enabled = 0;
+static pthread_mutex_t lock = PTHREAD_MUTEX_INITIALIZER;
+
+/* The pages already placed, sorted and disjoint */
+static struct range ranges[MAX_RANGES];
+static size_t nr_ranges = 0;
+
+/*
+ * Set while this thread is placing memory, so that anything libc allocates on
+ * our behalf, e.g. to print an error, isn't placed again.
+ */
+static __thread int busy = 0;
+
+static int env_int(const char *name, int def)
+{
+	char *val = getenv(name);
+
+	if (!val)
+		return def;
+
+	return atoi(val);
+}
+
+__attribute__((constructor))
+static void capsplit_init(void)
+{
+	unsigned long local_mask;
+	char *budget = getenv("CAPSPLIT_LOCAL_GB");
+
+	if (!budget) {
+		fprintf(stderr, "capsplit: CAPSPLIT_LOCAL_GB not set, doing nothing\n");
+		return;
+	}
+
+	local_budget = strtoul(budget, NULL, 10) * GB;
+	local_node = env_int("CAPSPLIT_LOCAL_NODE", 0);
+	remote_node = env_int("CAPSPLIT_REMOTE_NODE", 1);
+	verbose = env_int("CAPSPLIT_VERBOSE", 0);
+
+	// Everything not explicitly spilled should prefer the local node
+	local_mask = 1UL << local_node;
+	if (set_mempolicy(MPOL_PREFERRED, &local_mask, sizeof(local_mask) * 8)) {
+		perror("capsplit: set_mempolicy");
+		return;
+	}
+
+	// Memory trimmed from the top of the heap comes back without its policy
+	// when the heap grows again, but we would think it is still placed
+	mallopt(M_TRIM_THRESHOLD, INT_MAX);
+
+	enabled = 1;
+}
+
+__attribute__((destructor))
+static void capsplit_exit(void)
+{
+	if (enabled && verbose)
+		fprintf(stderr, "capsplit: placed %lu MB on node %d and %lu MB on node %d\n",
+			local_used / MB, local_node, remote_used / MB, remote_node);
+}
+
+/* The index of the first range that ends after addr */
+static size_t find_range(uintptr_t addr)
+{
+	size_t lo = 0, hi = nr_ranges;
+
+	while (lo < hi) {
+		size_t mid = (lo + hi) / 2;
+
+		if (ranges[mid].end <= addr)
+			lo = mid + 1;
+		else
+			hi = mid;
+	}
+
+	return lo;
+}
+
+/*
+ * Insert [start, end) just before ranges[i], which it must not overlap. Returns
+ * 0 if there is no room for it.
+ */
+static int insert_range(size_t i, uintptr_t start, uintptr_t end)
+{
+	int prev = i > 0 && ranges[i - 1].end == start;
+	int next = i < nr_ranges && ranges[i].start == end;
+
+	if (prev && next) {
+		ranges[i - 1].end = ranges[i].end;
+		memmove(&ranges[i], &ranges[i + 1], (nr_ranges - i - 1) * sizeof(ranges[0]));
+		nr_ranges--;
+	} else if (prev) {
+		ranges[i - 1].end = end;
+	} else if (next) {
+		ranges[i].start = start;
+	} else {
+		if (nr_ranges == MAX_RANGES)
+			return 0;
+		memmove(&ranges[i + 1], &ranges[i], (nr_ranges - i) * sizeof(ranges[0]));
+		ranges[i].start = start;
+		ranges[i].end = end;
+		nr_ranges++;
+	}
+
+	return 1;
+}
+
+/*
+ * Forget that [start, end) was placed, because it was just mapped again.
+ * Returns 0 if there is no room to split the range around it.
+ */
+static int forget_range(uintptr_t start, uintptr_t end)
+{
+	size_t i = find_range(start);
+
+	while (i < nr_ranges && ranges[i].start < end) {
+		struct range *r = &ranges[i];
+
+		if (r->start < start && r->end > end) {
+			uintptr_t old_end = r->end;
+
+			r->end = start;
+			return insert_range(i + 1, end, old_end);
+		} else if (r->start < start) {
+			r->end = start;
+			i++;
+		} else if (r->end > end) {
+			r->start = end;
+			i++;
+		} else {
+			memmove(r, r + 1, (nr_ranges - i - 1) * sizeof(ranges[0]));
+			nr_ranges--;
+		}
+	}
+
+	return 1;
+}
+
+/*
+ * Charge the new pages [start, end) to the local budget and move the ones past
+ * it to the remote node. Some may have been touched already, e.g. by malloc
+ * writing its chunk headers, so they are moved rather than only bound.
+ */
+static void place_new(uintptr_t start, uintptr_t end)
+{
+	unsigned long remote_mask = 1UL << remote_node;
+	size_t length = end - start;
+	size_t local_len = local_budget - local_used;
+
+	if (local_len > length)
+		local_len = length;
+	local_used += local_len;
+	remote_used += length - local_len;
+
+	if (local_len < length &&
+	    mbind((void *)(start + local_len), length - local_len, MPOL_PREFERRED,
+		  &remote_mask, sizeof(remote_mask) * 8, MPOL_MF_MOVE))
+		perror("capsplit: mbind");
+}
+
+/*
+ * Place the pages of [addr, addr + length) that haven't been placed yet. If
+ * `fresh`, the whole range was just mapped, so none of it has been.
+ */
+static void place(void *addr, size_t length, int fresh)
+{
+	uintptr_t start = (uintptr_t)addr & ~(PAGE_SIZE - 1);
+	uintptr_t end = ((uintptr_t)addr + length + PAGE_SIZE - 1) & ~(PAGE_SIZE - 1);
+
+	if (!enabled || busy || length == 0)
+		return;
+
+	busy = 1;
+	pthread_mutex_lock(&lock);
+
+	if (fresh && !forget_range(start, end))
+		goto full;
+
+	while (start < end) {
+		size_t i = find_range(start);
+		uintptr_t gap_end = end;
+
+		if (i < nr_ranges && ranges[i].start <= start) {
+			start = ranges[i].end;
+			continue;
+		}
+		if (i < nr_ranges && ranges[i].start < end)
+			gap_end = ranges[i].start;
+
+		if (!insert_range(i, start, gap_end))
+			goto full;
+		place_new(start, gap_end);
+		start = gap_end;
+	}
+
+	pthread_mutex_unlock(&lock);
+	busy = 0;
+	return;
+
+full:
+	// Without knowing what was placed we would count memory twice
+	enabled = 0;
+	pthread_mutex_unlock(&lock);
+	fprintf(stderr, "capsplit: too many ranges, no longer placing memory\n");
+	busy = 0;
+}
+
+/* Place the memory malloc returned for a request of `size` bytes */
+static void place_chunk(void *ptr, size_t size)
+{
+	// Big chunks get a mapping of their own, which may reuse the addresses of
+	// one that was freed
+	int fresh = ((size_t *)ptr)[-1] & CHUNK_IS_MMAPPED;
+
+	place(ptr, size, fresh);
+}
+
+void *mmap(void *addr, size_t length, int prot, int flags, int fd, off_t offset)
+{
+	void *ret;
+
+	// Call into the kernel directly so we don't have to resolve the libc
+	// symbol, which can itself allocate memory
+	ret = (void *)syscall(SYS_mmap, addr, length, prot, flags, fd, offset);
+	if (ret != MAP_FAILED && (flags & MAP_ANONYMOUS))
+		place(ret, length, 1);
+
+	return ret;
+}
+
+void *malloc(size_t size)
+{
+	void *ret = __libc_malloc(size);
+
+	if (ret)
+		place_chunk(ret, size);
+	return ret;
+}
+
+void *calloc(size_t nmemb, size_t size)
+{
+	void *ret = __libc_calloc(nmemb, size);
+
+	// It would have failed if this overflowed
+	if (ret)
+		place_chunk(ret, nmemb * size);
+	return ret;
+}
+
+void *realloc(void *ptr, size_t size)
+{
+	void *ret = __libc_realloc(ptr, size);
+
+	if (ret)
+		place_chunk(ret, size);
+	return ret;
+}
+
+void *memalign(size_t alignment, size_t size)
+{
+	void *ret = __libc_memalign(alignment, size);
+
+	if (ret)
+		place_chunk(ret, size);
+	return ret;
+}
+
+void *aligned_alloc(size_t alignment, size_t size)
+{
+	return memalign(alignment, size);
+}
+
+int posix_memalign(void **memptr, size_t alignment, size_t size)
+{
+	void *ret;
+
+	if (alignment % sizeof(void *) || (alignment & (alignment - 1)))
+		return EINVAL;
+
+	ret = memalign(alignment, size);
+	if (!ret)
+		return ENOMEM;
+
+	*memptr = ret;
+	return 0;
+}
+
+void *valloc(size_t size)
+{
+	void *ret = __libc_valloc(size);
+
+	if (ret)
+		place_chunk(ret, size);
+	return ret;
+}
+
+void *pvalloc(size_t size)
+{
+	void *ret = __libc_pvalloc(size);
+
+	if (ret)
+		place_chunk(ret, size);
+	return ret;
+}
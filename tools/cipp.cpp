@@ -191,7 +191,8 @@ int adjust_interleave_ratio(std::list<int64_t> &bw_history, int ratio, int64_t b
     std::cout << "Target ratio: " << ratio << " "
               << "BW Change: " << bw_change << " "
               << "Int Change: " << interleave_change << " "
-              << "BW: " << cur_bw << std::endl;
+              << "BW: " << cur_bw << " "
+              << "Time: " << wall_time() << std::endl;
 
     return ratio;
 }
@@ -0,0 +1,95 @@
+/*
+ * Measure the memory latency of a NUMA node by chasing pointers through a
+ * buffer bound to it for a fixed amount of time.
+ *
+ * Usage: latprobe <node> <buffer size (MB)> <seconds>
+ */
+#define _GNU_SOURCE
+#include <stdio.h>
+#include <stdlib.h>
+#include <stdint.h>
+#include <time.h>
+#include <sys/mman.h>
+#include <numaif.h>
+
+#define LINE_SIZE 64
+#define CHASE_BATCH (1 << 20)
+
+static uint64_t now_ns(void)
+{
+	struct timespec ts;
+
+	clock_gettime(CLOCK_MONOTONIC, &ts);
+	return ts.tv_sec * 1000000000UL + ts.tv_nsec;
+}
+
+int main(int argc, char *argv[])
+{
+	unsigned long node_mask;
+	uint64_t *order;
+	uint64_t start, elapsed, loads = 0;
+	size_t size, num_lines, i, j, tmp;
+	char *buf;
+	void **p;
+	int node, seconds;
+
+	if (argc != 4) {
+		fprintf(stderr, "Usage: latprobe <node> <buffer size (MB)> <seconds>\n");
+		return -1;
+	}
+
+	node = atoi(argv[1]);
+	size = strtoul(argv[2], NULL, 10) << 20;
+	seconds = atoi(argv[3]);
+	num_lines = size / LINE_SIZE;
+
+	buf = mmap(NULL, size, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0);
+	if (buf == MAP_FAILED) {
+		perror("mmap");
+		return -1;
+	}
+
+	// Use huge pages so we measure memory latency rather than TLB misses
+	madvise(buf, size, MADV_HUGEPAGE);
+
+	node_mask = 1UL << node;
+	if (mbind(buf, size, MPOL_BIND, &node_mask, sizeof(node_mask) * 8, MPOL_MF_STRICT)) {
+		perror("mbind");
+		return -1;
+	}
+
+	// Visit the lines in a random single cycle (Sattolo's algorithm) to defeat
+	// the prefetchers
+	order = malloc(num_lines * sizeof(*order));
+	if (!order) {
+		fprintf(stderr, "Could not allocate buffer\n");
+		return -1;
+	}
+	srandom(1);
+	for (i = 0; i < num_lines; i++)
+		order[i] = i;
+	for (i = num_lines - 1; i > 0; i--) {
+		j = random() % i;
+		tmp = order[i];
+		order[i] = order[j];
+		order[j] = tmp;
+	}
+	for (i = 0; i < num_lines; i++)
+		*(void **)(buf + order[i] * LINE_SIZE) = buf + order[(i + 1) % num_lines] * LINE_SIZE;
+	free(order);
+
+	p = (void **)buf;
+	start = now_ns();
+	do {
+		for (i = 0; i < CHASE_BATCH; i++)
+			p = *p;
+		loads += CHASE_BATCH;
+		elapsed = now_ns() - start;
+	} while (elapsed < (uint64_t)seconds * 1000000000UL);
+
+	// Printing p keeps the chase from being optimized away
+	printf("end: %p\n", (void *)p);
+	printf("latency_ns: %.2f\n", (double)elapsed / loads);
+
+	return 0;
+}
@@ -1,5 +1,7 @@
 #include <cassert>
+#include <cstdio>
 #include <cstring>
+#include <ctime>
 #include <fstream>
 #include <iostream>
 #include <sstream>
@@ -201,3 +203,15 @@ int perf_sample_open(pid_t pid, int cpu, int group_fd, uint64_t type, uint64_t c
 
     return fd;
 }
+
+// The wall clock time in seconds, so the runner can put samples on its timeline
+std::string wall_time()
+{
+    struct timespec ts;
+    char buf[32];
+
+    clock_gettime(CLOCK_REALTIME, &ts);
+    snprintf(buf, sizeof(buf), "%ld.%03ld", (long)ts.tv_sec, ts.tv_nsec / 1000000);
+
+    return buf;
+}
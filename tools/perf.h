@@ -2,6 +2,7 @@
 #define _PERF_H
 
 #include <fstream>
+#include <string>
 #include <vector>
 #include <cstdint>
 
@@ -22,5 +23,6 @@ void open_perf_events(int cpu, std::vector<uint32_t> types,
 int perf_sample_open(pid_t pid, int cpu, int group_fd, uint64_t type, uint64_t config,
     uint64_t config1, uint64_t sample_type, uint64_t sample_period);
 void apply_ioctl(int cmd, std::vector<int> fds);
+std::string wall_time();
 
 #endif
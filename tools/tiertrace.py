@@ -0,0 +1,242 @@
+#!/usr/bin/env python3
+#
+# Trace page migrations and NUMA hint faults with eBPF. Every interval, append the
+# number of migrations and migrated pages per process and direction to a CSV.
+# When killed, write latency histograms of migrations and hint faults.
+#
+# Demotions happen in kswapd or in whoever is reclaiming, so they are attributed
+# to that process rather than the owner of the pages. Promotions happen in the
+# NUMA hint fault of the process that touched the page.
+#
+# Usage: sudo tiertrace.py <interval secs> <counts csv> <latency csv>
+
+import signal
+import sys
+import time
+
+from bcc import BPF
+
+# Keep in sync with the DIR_ defines below
+DIRECTIONS = [
+    "demotion",
+    "promotion",
+    "policy",
+    "compaction",
+    "other",
+    "hint_fault",
+    "ratelimited",
+]
+
+BPF_TEXT = r"""
+#include <linux/sched.h>
+
+#define DIR_DEMOTION 0
+#define DIR_PROMOTION 1
+#define DIR_POLICY 2
+#define DIR_COMPACTION 3
+#define DIR_OTHER 4
+#define DIR_HINT_FAULT 5
+#define DIR_RATELIMITED 6
+
+// enum migrate_reason
+#define MR_COMPACTION 0
+#define MR_SYSCALL 3
+#define MR_MEMPOLICY_MBIND 4
+#define MR_NUMA_MISPLACED 5
+#define MR_DEMOTION 8
+
+struct key_t {
+    u32 pid;
+    u32 dir;
+    char comm[TASK_COMM_LEN];
+};
+
+struct val_t {
+    u64 events;
+    u64 succeeded;
+    u64 failed;
+};
+
+struct hist_key_t {
+    u32 pid;
+    u32 dir;
+    char comm[TASK_COMM_LEN];
+    u64 slot;
+};
+
+BPF_HASH(counts, struct key_t, struct val_t);
+BPF_HISTOGRAM(latency, struct hist_key_t);
+BPF_HASH(migrate_start, u32, u64);
+BPF_HASH(fault_start, u32, u64);
+
+static u32 reason_dir(int reason) {
+    switch (reason) {
+    case MR_DEMOTION:
+        return DIR_DEMOTION;
+    case MR_NUMA_MISPLACED:
+        return DIR_PROMOTION;
+    case MR_SYSCALL:
+    case MR_MEMPOLICY_MBIND:
+        return DIR_POLICY;
+    case MR_COMPACTION:
+        return DIR_COMPACTION;
+    default:
+        return DIR_OTHER;
+    }
+}
+
+static struct val_t *count(u32 dir) {
+    struct key_t key = {};
+    struct val_t zero = {};
+
+    key.pid = bpf_get_current_pid_tgid() >> 32;
+    key.dir = dir;
+    bpf_get_current_comm(&key.comm, sizeof(key.comm));
+
+    return counts.lookup_or_try_init(&key, &zero);
+}
+
+static void record_latency(u32 dir, u64 start_ns) {
+    struct hist_key_t key = {};
+
+    key.pid = bpf_get_current_pid_tgid() >> 32;
+    key.dir = dir;
+    bpf_get_current_comm(&key.comm, sizeof(key.comm));
+    key.slot = bpf_log2l((bpf_ktime_get_ns() - start_ns) / 1000);
+    latency.increment(key);
+}
+
+int hint_fault_entry(struct pt_regs *ctx) {
+    u32 tid = bpf_get_current_pid_tgid();
+    u64 ts = bpf_ktime_get_ns();
+
+    fault_start.update(&tid, &ts);
+    return 0;
+}
+
+int hint_fault_return(struct pt_regs *ctx) {
+    u32 tid = bpf_get_current_pid_tgid();
+    u64 *ts = fault_start.lookup(&tid);
+    struct val_t *val;
+
+    if (!ts)
+        return 0;
+
+    val = count(DIR_HINT_FAULT);
+    if (val)
+        val->events++;
+    record_latency(DIR_HINT_FAULT, *ts);
+    fault_start.delete(&tid);
+    return 0;
+}
+"""
+
+MIGRATE_START_PROBE = r"""
+TRACEPOINT_PROBE(migrate, mm_migrate_pages_start) {
+    u32 tid = bpf_get_current_pid_tgid();
+    u64 ts = bpf_ktime_get_ns();
+
+    migrate_start.update(&tid, &ts);
+    return 0;
+}
+"""
+
+MIGRATE_PROBE = r"""
+TRACEPOINT_PROBE(migrate, mm_migrate_pages) {
+    u32 tid = bpf_get_current_pid_tgid();
+    u32 dir = reason_dir(args->reason);
+    struct val_t *val = count(dir);
+    u64 *ts;
+
+    if (val) {
+        val->events++;
+        val->succeeded += args->succeeded;
+        val->failed += args->failed;
+    }
+
+    ts = migrate_start.lookup(&tid);
+    if (ts) {
+        record_latency(dir, *ts);
+        migrate_start.delete(&tid);
+    }
+    return 0;
+}
+"""
+
+RATELIMIT_PROBE = r"""
+TRACEPOINT_PROBE(migrate, mm_numa_migrate_ratelimit) {
+    struct val_t *val = count(DIR_RATELIMITED);
+
+    if (val) {
+        val->events++;
+        val->failed += args->nr_pages;
+    }
+    return 0;
+}
+"""
+
+
+def dump_counts(b, f, now):
+    counts = b["counts"]
+    for k, v in sorted(counts.items(), key=lambda kv: (kv[0].pid, kv[0].dir)):
+        f.write("%.3f,%d,%s,%s,%d,%d,%d\n" % (
+            now, k.pid, k.comm.decode("utf-8", "replace"), DIRECTIONS[k.dir],
+            v.events, v.succeeded, v.failed))
+    counts.clear()
+    f.flush()
+
+
+def dump_latency(b, latency_file):
+    with open(latency_file, "w") as f:
+        f.write("pid,comm,direction,lo_us,hi_us,count\n")
+        for k, v in sorted(b["latency"].items(), key=lambda kv: (kv[0].pid, kv[0].dir, kv[0].slot)):
+            lo = 0 if k.slot == 0 else 1 << k.slot
+            f.write("%d,%s,%s,%d,%d,%d\n" % (
+                k.pid, k.comm.decode("utf-8", "replace"), DIRECTIONS[k.dir],
+                lo, 1 << (k.slot + 1), v.value))
+
+
+def main():
+    if len(sys.argv) != 4:
+        print("Usage: %s <interval secs> <counts csv> <latency csv>" % sys.argv[0])
+        sys.exit(1)
+    interval = float(sys.argv[1])
+    counts_file, latency_file = sys.argv[2], sys.argv[3]
+
+    # Not every kernel has every tracepoint
+    text = BPF_TEXT
+    if BPF.tracepoint_exists("migrate", "mm_migrate_pages_start"):
+        text += MIGRATE_START_PROBE
+    if BPF.tracepoint_exists("migrate", "mm_migrate_pages"):
+        text += MIGRATE_PROBE
+    else:
+        print("WARNING: no migrate:mm_migrate_pages tracepoint")
+    if BPF.tracepoint_exists("migrate", "mm_numa_migrate_ratelimit"):
+        text += RATELIMIT_PROBE
+
+    b = BPF(text=text)
+    if BPF.get_kprobe_functions(b"^do_numa_page$"):
+        b.attach_kprobe(event="do_numa_page", fn_name="hint_fault_entry")
+        b.attach_kretprobe(event="do_numa_page", fn_name="hint_fault_return")
+    else:
+        print("WARNING: do_numa_page cannot be probed, so hint faults will not be traced")
+
+    # Be killed the same way as with ^C so everything gets written out
+    signal.signal(signal.SIGTERM, lambda *_: sys.exit(0))
+
+    with open(counts_file, "w") as f:
+        f.write("time,pid,comm,direction,events,succeeded,failed\n")
+        print("Tracing...")
+        try:
+            while True:
+                time.sleep(interval)
+                dump_counts(b, f, time.time())
+        except (KeyboardInterrupt, SystemExit):
+            pass
+        finally:
+            dump_counts(b, f, time.time())
+            dump_latency(b, latency_file)
+
+
+if __name__ == "__main__":
+    main()